@@ -2,8 +2,31 @@
 //!
 //! This module provides structs corresponding to the expected replies from the
 //! Vagrant Cloud API.
+//!
+//! All structs are deserialized leniently: missing or `null` fields are
+//! replaced by their default value and fields that are not known to vagabond
+//! are collected in the `extra` map. See the [`schema`](../schema/index.html)
+//! module for a strict alternative.
+
+use serde::{Deserialize, Deserializer};
+
+use super::constraint::{VersionConstraint, VersionNumber};
+use super::{BoxTag, ProviderName, Result};
+
+/// Map of fields of a reply that are unknown to vagabond
+pub type ExtraFields = serde_json::Map<String, serde_json::Value>;
+
+/// Deserialize `null` into the default value of `T`
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
 
 #[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
 /// Reply from the Vagrant Cloud API containing the information about a
 /// provider.
 ///
@@ -11,49 +34,138 @@
 /// documentation](https://www.vagrantup.com/docs/vagrant-cloud/api.html#providers)
 pub struct Provider {
     /// Name of the provider
-    pub name: String,
+    #[serde(deserialize_with = "null_as_default")]
+    pub name: ProviderName,
     /// Is the box for this provider hosted on Vagrant Cloud?
+    #[serde(deserialize_with = "null_as_default")]
     pub hosted: bool,
-    ///
+    /// Token used for uploading a box hosted on Vagrant Cloud
     pub hosted_token: Option<String>,
     /// Original URL from which the box was downloaded
     pub original_url: Option<String>,
     /// Date string indicating when this box was created
+    #[serde(deserialize_with = "null_as_default")]
     pub created_at: String,
     /// Date string indicating when this box was last updated
+    #[serde(deserialize_with = "null_as_default")]
     pub updated_at: String,
     /// Download URL of this box
+    #[serde(deserialize_with = "null_as_default")]
     pub download_url: String,
+    /// Architecture of the guest in this box (e.g. amd64, arm64), not
+    /// reported by older versions of the API
+    pub architecture: Option<String>,
+    /// Checksum of the box file
+    pub checksum: Option<String>,
+    /// Type of the checksum (e.g. `sha256`)
+    pub checksum_type: Option<String>,
+    /// Fields of the reply unknown to vagabond
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
 #[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
 pub struct Version {
+    #[serde(deserialize_with = "null_as_default")]
     pub version: String,
+    #[serde(deserialize_with = "null_as_default")]
     pub status: String,
     pub description_html: Option<String>,
     pub description_markdown: Option<String>,
     pub created_at: Option<String>,
     pub updated_at: Option<String>,
+    #[serde(deserialize_with = "null_as_default")]
     pub number: String,
+    #[serde(deserialize_with = "null_as_default")]
     pub release_url: String,
+    #[serde(deserialize_with = "null_as_default")]
     pub revoke_url: String,
+    #[serde(deserialize_with = "null_as_default")]
     pub providers: Vec<Provider>,
+    /// Fields of the reply unknown to vagabond
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+/// Reply from the Vagrant Cloud API when preparing the upload of a box file
+pub struct UploadTarget {
+    /// URL to which the box file has to be uploaded via `PUT`
+    #[serde(deserialize_with = "null_as_default")]
+    pub upload_path: String,
+    /// Fields of the reply unknown to vagabond
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
 #[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
 pub struct VagrantBox {
     pub tag: Option<String>,
+    #[serde(deserialize_with = "null_as_default")]
     pub username: String,
+    #[serde(deserialize_with = "null_as_default")]
     pub name: String,
     pub private: Option<bool>,
+    #[serde(deserialize_with = "null_as_default")]
     pub downloads: usize,
+    #[serde(deserialize_with = "null_as_default")]
     pub created_at: String,
+    #[serde(deserialize_with = "null_as_default")]
     pub updated_at: String,
     pub short_description: Option<String>,
     pub description_markdown: Option<String>,
     pub description_html: Option<String>,
+    #[serde(deserialize_with = "null_as_default")]
     pub versions: Vec<Version>,
     pub current_version: Option<Version>,
+    /// Fields of the reply unknown to vagabond
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+impl VagrantBox {
+    /// Returns the validated [`BoxTag`](../tag/struct.BoxTag.html) of this box
+    pub fn box_tag(&self) -> Result<BoxTag> {
+        BoxTag::new(self.username.as_str(), self.name.as_str())
+    }
+
+    /// Find the version that `vagrant up` would pull for the given `constraint`
+    ///
+    /// Returns the highest released version (i.e. with the status `active`)
+    /// satisfying `constraint` that contains a provider named `provider`. If
+    /// `architecture` is `Some(arch)`, then the provider must furthermore be
+    /// built for `arch`.
+    ///
+    /// Versions whose version number cannot be parsed are ignored.
+    pub fn resolve_version(
+        &self,
+        constraint: &VersionConstraint,
+        provider: &str,
+        architecture: Option<&str>,
+    ) -> Option<&Version> {
+        self.versions
+            .iter()
+            .filter(|ver| ver.status == "active")
+            .filter(|ver| {
+                ver.providers.iter().any(|prov| {
+                    prov.name == provider
+                        && architecture
+                            .is_none_or(|arch| prov.architecture.as_deref() == Some(arch))
+                })
+            })
+            .filter_map(|ver| {
+                ver.version
+                    .parse::<VersionNumber>()
+                    .ok()
+                    .filter(|num| constraint.matches(num))
+                    .map(|num| (num, ver))
+            })
+            .max_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs))
+            .map(|(_, ver)| ver)
+    }
 }
 
 impl<'a, 'b, 'c, 'd> PartialEq<super::VagrantBox<'a, 'b, 'c, 'd>> for &VagrantBox {
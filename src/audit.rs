@@ -0,0 +1,324 @@
+//! # Audit module
+//!
+//! An [`AuditLog`](struct.AuditLog.html) records every modification (`POST`,
+//! `PUT` and `DELETE` requests) performed through a
+//! [`Client`](../struct.Client.html) as one JSON object per line (JSON Lines).
+//! Each [`AuditRecord`](struct.AuditRecord.html) contains the time of the
+//! request, a fingerprint of the API token (never the token itself), the
+//! endpoint, the box, version and provider that were modified, the status of
+//! the reply and the beginning of the reply.
+//!
+//! Credentials are never written to the log: the query string of endpoints
+//! is dropped, only the first segment of the path of upload paths (which
+//! contain their token) is kept and the values of the `hosted_token`,
+//! `token`, `upload_path` and `callback` fields of replies are replaced by
+//! `[redacted]`. URLs and replies in error messages are redacted alike.
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::audit::{self, AuditLog};
+//! let log = AuditLog::open("/var/log/vagabond/audit.jsonl")
+//!     .unwrap()
+//!     // keep at most 10 files of 1 MiB each
+//!     .with_rotation(1024 * 1024, 9);
+//! let client = Client::new(Some("my_api_key_here".to_string())).with_audit_log(log);
+//!
+//! // ... modify boxes via the client ...
+//!
+//! let tag: BoxTag = "my_user/awesome_box".parse().unwrap();
+//! for record in audit::history("/var/log/vagabond/audit.jsonl", &tag).unwrap() {
+//!     println!(
+//!         "{} {} {} {:?} by {}",
+//!         record.timestamp, record.method, record.endpoint, record.status,
+//!         record.token_fingerprint
+//!     );
+//! }
+//! ```
+//!
+//! Once the log file would exceed the configured size, it is renamed to
+//! `audit.jsonl.1` (shifting existing rotated files to `audit.jsonl.2` and so
+//! on) and a new file is started.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use super::observer::{Entity, Event, Observer, Outcome};
+use super::{BoxTag, Client, Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// A single modification performed via the Vagrant Cloud API
+pub struct AuditRecord {
+    /// When the request finished
+    pub timestamp: DateTime<Utc>,
+    /// Fingerprint of the API token that was used, see
+    /// [`token_fingerprint`](fn.token_fingerprint.html)
+    pub token_fingerprint: String,
+    /// The HTTP method of the request
+    pub method: String,
+    /// The URL of the request without its query string (only the host and
+    /// the first segment of the path for box file transfers)
+    pub endpoint: String,
+    /// The modified box, version and provider
+    #[serde(flatten)]
+    pub entity: Entity,
+    /// The HTTP status of the reply, `None` if no reply was received
+    pub status: Option<u16>,
+    /// Whether the request succeeded
+    pub success: bool,
+    /// The beginning of the reply with credentials redacted
+    pub response: Option<String>,
+    /// The error that occurred, if any
+    pub error: Option<String>,
+    /// How long the request took in milliseconds
+    pub duration_ms: u64,
+}
+
+/// Fingerprint identifying an API token without revealing it: the first 16
+/// hex digits of its SHA-256 hash prefixed with `sha256:`, or `anonymous`
+/// if no token is used.
+pub fn token_fingerprint(token: Option<&str>) -> String {
+    match token {
+        None => "anonymous".to_string(),
+        Some(t) => {
+            let digest = Sha256::digest(t.as_bytes());
+            let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+            format!("sha256:{}", hex)
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Append-only JSON Lines file of [`AuditRecord`](struct.AuditRecord.html)s
+/// with optional size based rotation
+pub struct AuditLog {
+    path: PathBuf,
+    /// (maximum size of a file in bytes, number of rotated files to keep)
+    rotation: Option<(u64, usize)>,
+    file: Mutex<File>,
+}
+
+/// Path of the `index`-th rotated file of the log at `path`
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl AuditLog {
+    /// Open the audit log at `path`, appending to it if it already exists.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AuditLog> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)?;
+        Ok(AuditLog {
+            path,
+            rotation: None,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Rotate the log once it would grow beyond `max_size` bytes, keeping at
+    /// most `keep` rotated files (older ones are deleted).
+    pub fn with_rotation(mut self, max_size: u64, keep: usize) -> AuditLog {
+        self.rotation = Some((max_size, keep));
+        self
+    }
+
+    /// The path of the current log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `record` to the log, rotating it beforehand if necessary.
+    pub fn append(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record)
+            .map_err(|e| Error::InternalError(format!("cannot serialize audit record: {}", e)))?;
+        line.push('\n');
+
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| Error::InternalError("audit log lock is poisoned".to_string()))?;
+
+        if let Some((max_size, keep)) = self.rotation {
+            let size = file.metadata()?.len();
+            if size > 0 && size + line.len() as u64 > max_size {
+                self.rotate(keep)?;
+                *file = open_append(&self.path)?;
+            }
+        }
+
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Shift all rotated files by one and move the current file to `.1`
+    fn rotate(&self, keep: usize) -> io::Result<()> {
+        if keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        let oldest = rotated_path(&self.path, keep);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for i in (1..keep).rev() {
+            let from = rotated_path(&self.path, i);
+            if from.exists() {
+                fs::rename(from, rotated_path(&self.path, i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))
+    }
+}
+
+/// Read all records of the log at `path` including its rotated files, oldest
+/// first.
+pub fn read_records<P: AsRef<Path>>(path: P) -> Result<Vec<AuditRecord>> {
+    let path = path.as_ref();
+    let mut files = vec![];
+    while rotated_path(path, files.len() + 1).exists() {
+        files.push(rotated_path(path, files.len() + 1));
+    }
+    files.reverse();
+    if path.exists() {
+        files.push(path.to_path_buf());
+    }
+
+    let mut records = vec![];
+    for file in files {
+        for (i, line) in BufReader::new(File::open(&file)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(serde_json::from_str(&line).map_err(|e| {
+                Error::InvalidAuditLog(format!("{}:{}: {}", file.display(), i + 1, e))
+            })?);
+        }
+    }
+    records.sort_by_key(|r: &AuditRecord| r.timestamp);
+    Ok(records)
+}
+
+/// Reconstruct the history of the box `tag` from the log at `path`: all
+/// records concerning the box, its versions or its providers, oldest first.
+pub fn history<P: AsRef<Path>>(path: P, tag: &BoxTag) -> Result<Vec<AuditRecord>> {
+    Ok(read_records(path)?
+        .into_iter()
+        .filter(|r| {
+            r.entity.username.as_deref() == Some(tag.username())
+                && r.entity.name.as_deref() == Some(tag.name())
+        })
+        .collect())
+}
+
+/// Remove the query string and fragment from `url`, as they may contain
+/// credentials
+///
+/// The URLs of box file transfers (`transfer`) are reduced to their host and
+/// the first segment of their path, as upload paths may carry their token in
+/// the path (e.g. `https://archivist.example.com/v1/object/<token>`).
+fn redact_url(url: &str, transfer: bool) -> String {
+    let mut url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return url.split(['?', '#']).next().unwrap_or_default().to_string(),
+    };
+    url.set_query(None);
+    url.set_fragment(None);
+    if transfer {
+        let first = url
+            .path_segments()
+            .and_then(|mut segments| segments.next())
+            .unwrap_or_default()
+            .to_string();
+        url.set_path(&first);
+    }
+    url.to_string()
+}
+
+/// Replace the values of fields containing credentials in the (possibly
+/// truncated) JSON `response`
+fn redact_response(response: &str) -> String {
+    static SECRETS: OnceLock<Regex> = OnceLock::new();
+    SECRETS
+        .get_or_init(|| {
+            Regex::new(r#""(hosted_token|token|upload_path|callback)"\s*:\s*"[^"]*"?"#).unwrap()
+        })
+        .replace_all(response, r#""$1": "[redacted]""#)
+        .into_owned()
+}
+
+/// Redact the URLs (see `redact_url()`) and the fields containing
+/// credentials in the message of `error`
+fn redact_error(error: &Error, transfer: bool) -> String {
+    static URLS: OnceLock<Regex> = OnceLock::new();
+    let message = URLS
+        .get_or_init(|| Regex::new(r#"[a-zA-Z][a-zA-Z0-9+.-]*://[^\s()<>"']+"#).unwrap())
+        .replace_all(&error.to_string(), |url: &regex::Captures| {
+            redact_url(&url[0], transfer)
+        })
+        .into_owned();
+    redact_response(&message)
+}
+
+/// Observer writing all modifying API calls to an audit log
+struct AuditObserver {
+    log: AuditLog,
+    token_fingerprint: String,
+}
+
+impl Observer for AuditObserver {
+    fn after(&self, event: &Event, outcome: &Outcome) {
+        let (method, url, entity, transfer) = match event {
+            Event::ApiCall {
+                method,
+                url,
+                entity,
+                transfer,
+                ..
+            } if event.is_mutation() => (method, url, entity, *transfer),
+            _ => return,
+        };
+        let record = AuditRecord {
+            timestamp: Utc::now(),
+            token_fingerprint: self.token_fingerprint.clone(),
+            method: method.to_string(),
+            endpoint: redact_url(url, transfer),
+            entity: (*entity).clone(),
+            status: outcome.status.map(|s| s.as_u16()),
+            success: outcome.is_success(),
+            response: outcome.response.map(redact_response),
+            error: outcome.error.map(|e| redact_error(e, transfer)),
+            duration_ms: outcome.duration.as_millis() as u64,
+        };
+        if let Err(e) = self.log.append(&record) {
+            error!(
+                "Failed to write to the audit log {}: {}",
+                self.log.path().display(),
+                e
+            );
+        }
+    }
+}
+
+impl Client {
+    /// Record all modifications performed by this client in `log`.
+    pub fn with_audit_log(self, log: AuditLog) -> Client {
+        let token_fingerprint = token_fingerprint(self.token.as_deref());
+        self.with_observer(AuditObserver {
+            log,
+            token_fingerprint,
+        })
+    }
+}
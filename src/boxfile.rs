@@ -0,0 +1,292 @@
+//! # Box file module
+//!
+//! A `.box` file is a tar archive, optionally compressed with gzip, bzip2 or
+//! xz, that contains the disk images of a box together with a
+//! `metadata.json` (describing the provider), an optional `info.json` (shown
+//! by `vagrant box list -i`) and an optional `Vagrantfile`.
+//!
+//! [`BoxFile::open`](struct.BoxFile.html#method.open) reads all of these in a
+//! single pass over the archive, without extracting anything to disk:
+//!
+//! ```no_run
+//! # use vagabond::boxfile::BoxFile;
+//! let box_file = BoxFile::open("output/awesome_box.libvirt.box").unwrap();
+//! println!(
+//!     "{} box in the format {:?}",
+//!     box_file.metadata().provider,
+//!     box_file.metadata().format
+//! );
+//! for entry in box_file.entries() {
+//!     println!("{} ({} bytes)", entry.path, entry.size);
+//! }
+//! if let Some(vagrantfile) = box_file.vagrantfile() {
+//!     println!("{}", vagrantfile);
+//! }
+//! ```
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path};
+
+use super::api::ExtraFields;
+use super::{Error, ProviderName, Result};
+
+/// Files that are read into memory are rejected if they are larger than this
+const MAX_EMBEDDED_FILE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Compression of a `.box` file
+pub enum Compression {
+    /// An uncompressed tar archive
+    None,
+    /// A gzip compressed tar archive
+    Gzip,
+    /// A bzip2 compressed tar archive
+    Bzip2,
+    /// A xz compressed tar archive
+    Xz,
+}
+
+impl Compression {
+    /// Detect the compression from the first bytes of a file
+    fn detect(header: &[u8]) -> Compression {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if header.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Compression::None => "tar",
+                Compression::Gzip => "tar.gz",
+                Compression::Bzip2 => "tar.bz2",
+                Compression::Xz => "tar.xz",
+            }
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+/// Contents of the `metadata.json` of a box
+pub struct Metadata {
+    /// The provider of the box
+    pub provider: ProviderName,
+    /// Format of the disk image (e.g. `qcow2`), only used by some providers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Size of the disk in GB, only used by some providers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_size: Option<u64>,
+    /// Architecture of the guest (e.g. `amd64`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    /// Fields unknown to vagabond
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A file or directory in a `.box` file
+pub struct Entry {
+    /// Path of the entry inside the archive without a leading `./`
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Whether this entry is a directory
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The contents of a `.box` file
+pub struct BoxFile {
+    compression: Compression,
+    metadata: Metadata,
+    info: Option<ExtraFields>,
+    vagrantfile: Option<String>,
+    entries: Vec<Entry>,
+}
+
+/// Normalize `path` by dropping `.` components, so that `./metadata.json`
+/// and `metadata.json` are the same
+fn normalize(path: &Path) -> String {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Read the embedded file `name` of `size` bytes into a String
+fn read_embedded<R: Read>(name: &str, size: u64, reader: R) -> Result<String> {
+    if size > MAX_EMBEDDED_FILE_SIZE {
+        return Err(Error::InvalidBoxFile(format!(
+            "{} is too large ({} bytes)",
+            name, size
+        )));
+    }
+    let mut contents = String::new();
+    reader
+        .take(MAX_EMBEDDED_FILE_SIZE)
+        .read_to_string(&mut contents)
+        .map_err(|e| Error::InvalidBoxFile(format!("cannot read {}: {}", name, e)))?;
+    Ok(contents)
+}
+
+/// Detect the compression of `reader` and wrap it in the matching decoder
+fn decompress<'r, R: Read + 'r>(reader: R) -> Result<(Compression, Box<dyn Read + 'r>)> {
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::detect(reader.fill_buf()?);
+    let decoded: Box<dyn Read + 'r> = match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+        Compression::Bzip2 => Box::new(bzip2::read::BzDecoder::new(reader)),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new(reader)),
+    };
+    Ok((compression, decoded))
+}
+
+impl BoxFile {
+    /// Read the `.box` file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<BoxFile> {
+        BoxFile::from_reader(File::open(path)?)
+    }
+
+    /// Read a `.box` file from `reader`, detecting its compression.
+    ///
+    /// Returns an `Error::InvalidBoxFile` if the archive is malformed or
+    /// lacks a `metadata.json`.
+    pub fn from_reader<R: Read>(reader: R) -> Result<BoxFile> {
+        let (compression, reader) = decompress(reader)?;
+        BoxFile::from_tar(reader, compression, None)
+    }
+
+    /// Extract the `.box` file at `path` into the directory `destination`
+    /// (e.g. to install it) and return its contents, reading the archive
+    /// only once.
+    ///
+    /// Only files and directories are extracted: archives containing other
+    /// entries (like symlinks) or paths outside of `destination` are
+    /// rejected with an `Error::InvalidBoxFile`, leaving `destination`
+    /// partially populated.
+    pub fn extract<P: AsRef<Path>, D: AsRef<Path>>(path: P, destination: D) -> Result<BoxFile> {
+        let (compression, reader) = decompress(File::open(path)?)?;
+        BoxFile::from_tar(reader, compression, Some(destination.as_ref()))
+    }
+
+    fn from_tar<R: Read>(
+        reader: R,
+        compression: Compression,
+        destination: Option<&Path>,
+    ) -> Result<BoxFile> {
+        let invalid = |e: std::io::Error| Error::InvalidBoxFile(e.to_string());
+        let mut archive = tar::Archive::new(reader);
+
+        let mut metadata = None;
+        let mut info = None;
+        let mut vagrantfile = None;
+        let mut entries = vec![];
+        for entry in archive.entries().map_err(invalid)? {
+            let mut entry = entry.map_err(invalid)?;
+            let path = normalize(&entry.path().map_err(invalid)?);
+            let size = entry.header().size().map_err(invalid)?;
+            let entry_type = entry.header().entry_type();
+            let is_dir = entry_type.is_dir();
+            if entry_type.is_pax_global_extensions() {
+                continue;
+            }
+
+            let embedded = match path.as_str() {
+                "metadata.json" | "info.json" | "Vagrantfile" => {
+                    Some(read_embedded(&path, size, &mut entry)?)
+                }
+                _ => None,
+            };
+            if let Some(destination) = destination {
+                if !(entry_type.is_file() || is_dir) {
+                    return Err(Error::InvalidBoxFile(format!(
+                        "{} is neither a file nor a directory",
+                        path
+                    )));
+                }
+                let unpacked = match &embedded {
+                    Some(contents) => fs::write(destination.join(&path), contents).map(|_| true),
+                    None => entry.unpack_in(destination),
+                };
+                if !unpacked.map_err(invalid)? {
+                    return Err(Error::InvalidBoxFile(format!(
+                        "{} is outside of the box",
+                        path
+                    )));
+                }
+            }
+
+            match (path.as_str(), embedded) {
+                ("metadata.json", Some(contents)) => {
+                    metadata = Some(serde_json::from_str(&contents).map_err(|e| {
+                        Error::InvalidBoxFile(format!("invalid metadata.json: {}", e))
+                    })?);
+                }
+                ("info.json", Some(contents)) => {
+                    info =
+                        Some(serde_json::from_str(&contents).map_err(|e| {
+                            Error::InvalidBoxFile(format!("invalid info.json: {}", e))
+                        })?);
+                }
+                ("Vagrantfile", contents) => vagrantfile = contents,
+                _ => (),
+            }
+            if !path.is_empty() {
+                entries.push(Entry { path, size, is_dir });
+            }
+        }
+
+        Ok(BoxFile {
+            compression,
+            metadata: metadata.ok_or_else(|| {
+                Error::InvalidBoxFile("the archive contains no metadata.json".to_string())
+            })?,
+            info,
+            vagrantfile,
+            entries,
+        })
+    }
+
+    /// The compression of the archive
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// The contents of `metadata.json`
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// The contents of `info.json`, if the box contains one
+    pub fn info(&self) -> Option<&ExtraFields> {
+        self.info.as_ref()
+    }
+
+    /// The bundled `Vagrantfile`, if the box contains one
+    pub fn vagrantfile(&self) -> Option<&str> {
+        self.vagrantfile.as_deref()
+    }
+
+    /// All files and directories in the archive, in the order in which they
+    /// are stored
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
@@ -0,0 +1,292 @@
+//! # Catalog module
+//!
+//! Vagrant does not need Vagrant Cloud to offer versioned boxes:
+//! `vagrant box add https://host/box.json` accepts a catalog document
+//! listing all versions and providers of a box with their download URLs and
+//! checksums. A [`Catalog`](struct.Catalog.html) can be created from a box
+//! on Vagrant Cloud or from local `.box` files, which can then be served from
+//! any plain HTTP server:
+//!
+//! ```no_run
+//! # use vagabond::catalog::Catalog;
+//! let catalog = Catalog::from_box_files(
+//!     "my_user/awesome_box",
+//!     "https://boxes.example.com/awesome_box",
+//!     &[
+//!         ("1.0.0", "output/awesome_box-1.0.0.libvirt.box"),
+//!         ("1.0.0", "output/awesome_box-1.0.0.virtualbox.box"),
+//!         ("1.1.0", "output/awesome_box-1.1.0.libvirt.box"),
+//!     ],
+//! )
+//! .unwrap()
+//! .with_description("The most awesome box");
+//!
+//! std::fs::write("output/awesome_box.json", catalog.to_json().unwrap()).unwrap();
+//! ```
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::boxfile::{BoxFile, Metadata};
+use super::checksum::{Checksum, ChecksumType, HashingReader};
+use super::constraint::VersionNumber;
+use super::{api, Error, ProviderName, Result};
+
+/// Characters that are percent-encoded in file names appended to a base URL
+const FILE_NAME: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A box catalog as consumed by `vagrant box add`
+pub struct Catalog {
+    /// The name of the box, e.g. `my_user/awesome_box`
+    pub name: String,
+    /// A description of the box
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// All versions of the box
+    #[serde(default)]
+    pub versions: Vec<CatalogVersion>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A version of a box in a [`Catalog`](struct.Catalog.html)
+pub struct CatalogVersion {
+    /// The version number
+    pub version: String,
+    /// A description of this version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// All providers of this version
+    #[serde(default)]
+    pub providers: Vec<CatalogProvider>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A provider of a version in a [`Catalog`](struct.Catalog.html)
+pub struct CatalogProvider {
+    /// The name of the provider
+    pub name: ProviderName,
+    /// The URL from which the box file can be downloaded
+    pub url: String,
+    /// Type of `checksum` (e.g. `sha256`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_type: Option<String>,
+    /// Checksum of the box file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Architecture of the guest (e.g. `amd64`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+}
+
+/// Read the metadata of the `.box` file at `path` and compute its SHA-256
+/// checksum, reading the (usually large) file only once
+pub(crate) fn read_box_file(path: &Path) -> Result<(Metadata, Checksum)> {
+    let mut reader = HashingReader::new(File::open(path)?, ChecksumType::Sha256);
+    let metadata = BoxFile::from_reader(&mut reader)?.metadata().clone();
+    // the rest of the file, e.g. the end of the tar archive
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok((metadata, reader.checksum()))
+}
+
+impl Catalog {
+    /// Create a catalog for the box `name` without any versions
+    pub fn new<S: Into<String>>(name: S) -> Catalog {
+        Catalog {
+            name: name.into(),
+            description: None,
+            versions: vec![],
+        }
+    }
+
+    /// Set the description of the box
+    pub fn with_description<S: Into<String>>(mut self, description: S) -> Catalog {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Create a catalog from the local `.box` files in `files`, each given as
+    /// `(version, path)`, that are served below `base_url` under their file
+    /// names.
+    ///
+    /// The provider of each file is read from its `metadata.json` and its
+    /// SHA-256 checksum is computed.
+    pub fn from_box_files<P: AsRef<Path>>(
+        name: &str,
+        base_url: &str,
+        files: &[(&str, P)],
+    ) -> Result<Catalog> {
+        let mut catalog = Catalog::new(name);
+        for (version, path) in files {
+            let path = path.as_ref();
+            let file_name = path.file_name().and_then(|n| n.to_str()).ok_or_else(|| {
+                Error::InvalidBoxFile(format!("'{}' has no valid file name", path.display()))
+            })?;
+            let url = format!(
+                "{}/{}",
+                base_url.trim_end_matches('/'),
+                utf8_percent_encode(file_name, FILE_NAME)
+            );
+            catalog.add_box_file(version, path, url)?;
+        }
+        Ok(catalog)
+    }
+
+    /// Add the local `.box` file at `path`, which can be downloaded from
+    /// `url`, as a provider of `version`, creating the version if necessary.
+    ///
+    /// The provider of the file is read from its `metadata.json` and its
+    /// SHA-256 checksum is computed. Returns an `Error::DuplicateProvider` if
+    /// `version` already contains this provider.
+    pub fn add_box_file<P: AsRef<Path>, S: Into<String>>(
+        &mut self,
+        version: &str,
+        path: P,
+        url: S,
+    ) -> Result<&CatalogProvider> {
+        let (metadata, checksum) = read_box_file(path.as_ref())?;
+        self.add_provider(
+            version,
+            CatalogProvider {
+                name: metadata.provider,
+                url: url.into(),
+                checksum_type: Some(checksum.checksum_type.to_string()),
+                checksum: Some(checksum.checksum),
+                architecture: metadata.architecture,
+            },
+        )
+    }
+
+    /// Add `provider` to `version`, creating the version if necessary.
+    ///
+    /// Versions are kept sorted by their version number. Returns an
+    /// `Error::DuplicateProvider` if `version` already contains a provider
+    /// with the same name and architecture.
+    pub fn add_provider(
+        &mut self,
+        version: &str,
+        provider: CatalogProvider,
+    ) -> Result<&CatalogProvider> {
+        let providers = &mut self.version_mut(version).providers;
+        if providers
+            .iter()
+            .any(|p| p.name == provider.name && p.architecture == provider.architecture)
+        {
+            return Err(Error::DuplicateProvider(provider.name));
+        }
+        providers.push(provider);
+        Ok(&providers[providers.len() - 1])
+    }
+
+    /// Add `provider` to `version` like
+    /// [`add_provider`](#method.add_provider), but replace the provider with
+    /// the same name and architecture if `version` already contains one.
+    pub fn set_provider(&mut self, version: &str, provider: CatalogProvider) -> &CatalogProvider {
+        let providers = &mut self.version_mut(version).providers;
+        let index = match providers
+            .iter()
+            .position(|p| p.name == provider.name && p.architecture == provider.architecture)
+        {
+            Some(i) => {
+                providers[i] = provider;
+                i
+            }
+            None => {
+                providers.push(provider);
+                providers.len() - 1
+            }
+        };
+        &providers[index]
+    }
+
+    /// The version `version`, which is created if necessary
+    fn version_mut(&mut self, version: &str) -> &mut CatalogVersion {
+        let index = match self.versions.iter().position(|v| v.version == version) {
+            Some(i) => i,
+            None => {
+                self.versions.push(CatalogVersion {
+                    version: version.to_string(),
+                    description: None,
+                    providers: vec![],
+                });
+                self.versions.sort_by(|lhs, rhs| {
+                    match (
+                        lhs.version.parse::<VersionNumber>(),
+                        rhs.version.parse::<VersionNumber>(),
+                    ) {
+                        (Ok(l), Ok(r)) => l.cmp(&r),
+                        // unparsable version numbers come first
+                        (Ok(_), Err(_)) => Ordering::Greater,
+                        (Err(_), Ok(_)) => Ordering::Less,
+                        (Err(_), Err(_)) => lhs.version.cmp(&rhs.version),
+                    }
+                });
+                self.versions
+                    .iter()
+                    .position(|v| v.version == version)
+                    .unwrap_or_default()
+            }
+        };
+        &mut self.versions[index]
+    }
+
+    /// Serialize this catalog into pretty printed JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| Error::InternalError(format!("cannot serialize the catalog: {}", e)))
+    }
+}
+
+impl From<&api::VagrantBox> for Catalog {
+    /// Only released versions are included, each provider with its download
+    /// URL on Vagrant Cloud.
+    fn from(vagrant_box: &api::VagrantBox) -> Catalog {
+        Catalog {
+            name: vagrant_box
+                .tag
+                .clone()
+                .unwrap_or_else(|| format!("{}/{}", vagrant_box.username, vagrant_box.name)),
+            description: vagrant_box.short_description.clone(),
+            versions: vagrant_box
+                .versions
+                .iter()
+                .filter(|ver| ver.status == "active")
+                .map(CatalogVersion::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<&api::Version> for CatalogVersion {
+    fn from(version: &api::Version) -> CatalogVersion {
+        CatalogVersion {
+            version: version.version.clone(),
+            description: version.description_markdown.clone(),
+            providers: version
+                .providers
+                .iter()
+                .map(CatalogProvider::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<&api::Provider> for CatalogProvider {
+    fn from(provider: &api::Provider) -> CatalogProvider {
+        CatalogProvider {
+            name: provider.name.clone(),
+            url: provider.download_url.clone(),
+            checksum_type: provider.checksum_type.clone(),
+            checksum: provider.checksum.clone(),
+            architecture: provider.architecture.clone(),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! # Checksum module
+//!
+//! Vagrant verifies downloaded boxes against the `checksum` and
+//! `checksum_type` of their provider. This module computes these for
+//! (potentially multi-gigabyte) box files in a streaming fashion, reporting
+//! the progress along the way:
+//!
+//! ```no_run
+//! # use vagabond::checksum::{self, ChecksumType};
+//! let checksum = checksum::checksum_file(
+//!     "output/awesome_box.libvirt.box",
+//!     ChecksumType::Sha256,
+//!     |processed, total| println!("hashed {} of {:?} bytes", processed, total),
+//! )
+//! .unwrap();
+//! println!("{}: {}", checksum.checksum_type, checksum.checksum);
+//! ```
+//!
+//! A [`HashingReader`](struct.HashingReader.html) computes the checksum of
+//! everything that is read through it, so that a box file only has to be
+//! read once when it is hashed while being uploaded:
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use vagabond::*;
+//! # use vagabond::checksum::{ChecksumType, HashingReader};
+//! # let client = Client::new(Some("my_api_key_here".to_string()));
+//! # let username = "my_vagrant_cloud_user_name".to_string();
+//! # let box_name = "awesome_box".to_string();
+//! # let ver = "1.2.3".to_string();
+//! # let descr = "Release from today!".to_string();
+//! let file = File::open("output/awesome_box.libvirt.box").unwrap();
+//! let size = file.metadata().unwrap().len();
+//! let reader = HashingReader::new(file, ChecksumType::Sha256);
+//! let checksum = reader.checksum_handle();
+//!
+//! client
+//!     .upload_provider(
+//!         &VagrantBox::new(&username, &box_name),
+//!         &BoxVersion { version: &ver, description: &descr },
+//!         &ProviderName::Libvirt,
+//!         reader,
+//!         Some(size),
+//!     )
+//!     .unwrap();
+//! println!("uploaded a box with the sha256 {}", checksum.checksum().checksum);
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use sha2::digest::DynDigest;
+
+use super::{Error, Result};
+
+/// Size of the chunks in which files are read
+pub(crate) const CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The checksum types supported by Vagrant Cloud
+pub enum ChecksumType {
+    /// MD5, only for compatibility with old boxes
+    Md5,
+    /// SHA-1, only for compatibility with old boxes
+    Sha1,
+    /// SHA-256
+    Sha256,
+    /// SHA-384
+    Sha384,
+    /// SHA-512
+    Sha512,
+}
+
+impl ChecksumType {
+    /// The name of this type as used by Vagrant Cloud, e.g. `sha256`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumType::Md5 => "md5",
+            ChecksumType::Sha1 => "sha1",
+            ChecksumType::Sha256 => "sha256",
+            ChecksumType::Sha384 => "sha384",
+            ChecksumType::Sha512 => "sha512",
+        }
+    }
+
+    fn digest(&self) -> Box<dyn DynDigest + Send> {
+        match self {
+            ChecksumType::Md5 => Box::new(md5::Md5::default()),
+            ChecksumType::Sha1 => Box::new(sha1::Sha1::default()),
+            ChecksumType::Sha256 => Box::new(sha2::Sha256::default()),
+            ChecksumType::Sha384 => Box::new(sha2::Sha384::default()),
+            ChecksumType::Sha512 => Box::new(sha2::Sha512::default()),
+        }
+    }
+}
+
+impl FromStr for ChecksumType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ChecksumType> {
+        match s.to_lowercase().as_str() {
+            "md5" => Ok(ChecksumType::Md5),
+            "sha1" => Ok(ChecksumType::Sha1),
+            "sha256" => Ok(ChecksumType::Sha256),
+            "sha384" => Ok(ChecksumType::Sha384),
+            "sha512" => Ok(ChecksumType::Sha512),
+            _ => Err(Error::UnsupportedChecksumType(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A checksum as expected by Vagrant Cloud
+pub struct Checksum {
+    /// The type of the checksum, use its `to_string()` for the provider's
+    /// `checksum_type`
+    pub checksum_type: ChecksumType,
+    /// The lower case hex encoded checksum
+    pub checksum: String,
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.checksum_type, self.checksum)
+    }
+}
+
+/// Incrementally computes a [`Checksum`](struct.Checksum.html)
+pub struct Hasher {
+    checksum_type: ChecksumType,
+    digest: Box<dyn DynDigest + Send>,
+    processed: u64,
+}
+
+impl fmt::Debug for Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Hasher({}, {} bytes processed)",
+            self.checksum_type, self.processed
+        )
+    }
+}
+
+impl Hasher {
+    /// Create a hasher computing a checksum of the type `checksum_type`
+    pub fn new(checksum_type: ChecksumType) -> Hasher {
+        Hasher {
+            checksum_type,
+            digest: checksum_type.digest(),
+            processed: 0,
+        }
+    }
+
+    /// Hash `data`
+    pub fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+        self.processed += data.len() as u64;
+    }
+
+    /// The number of bytes hashed so far
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    /// The checksum of all data hashed so far
+    pub fn checksum(&self) -> Checksum {
+        let digest = self.digest.box_clone().finalize();
+        Checksum {
+            checksum_type: self.checksum_type,
+            checksum: digest.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Callback receiving the number of bytes processed so far and the total
+/// number of bytes, if known
+type Progress = Box<dyn FnMut(u64, Option<u64>) + Send>;
+
+/// Reader that hashes everything that is read through it
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Arc<Mutex<Hasher>>,
+    total: Option<u64>,
+    progress: Option<Progress>,
+}
+
+impl<R> fmt::Debug for HashingReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HashingReader({:?})", self.hasher)
+    }
+}
+
+/// Lock `hasher`, even if a thread panicked while holding the lock (the
+/// hasher cannot be left in an inconsistent state)
+fn lock(hasher: &Mutex<Hasher>) -> MutexGuard<'_, Hasher> {
+    hasher
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl<R: Read> HashingReader<R> {
+    /// Hash everything read from `inner` with `checksum_type`
+    pub fn new(inner: R, checksum_type: ChecksumType) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: Arc::new(Mutex::new(Hasher::new(checksum_type))),
+            total: None,
+            progress: None,
+        }
+    }
+
+    /// Call `progress` after each read with the number of bytes read so far
+    /// and `total`.
+    pub fn with_progress<F>(mut self, total: Option<u64>, progress: F) -> HashingReader<R>
+    where
+        F: FnMut(u64, Option<u64>) + Send + 'static,
+    {
+        self.total = total;
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// A handle to retrieve the checksum, which stays usable after this
+    /// reader has been moved away (e.g. into an upload)
+    pub fn checksum_handle(&self) -> ChecksumHandle {
+        ChecksumHandle(Arc::clone(&self.hasher))
+    }
+
+    /// The checksum of everything read so far
+    pub fn checksum(&self) -> Checksum {
+        lock(&self.hasher).checksum()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        let processed = {
+            let mut hasher = lock(&self.hasher);
+            hasher.update(&buf[..len]);
+            hasher.processed()
+        };
+        if let Some(progress) = &mut self.progress {
+            progress(processed, self.total);
+        }
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Retrieves the checksum computed by a
+/// [`HashingReader`](struct.HashingReader.html)
+pub struct ChecksumHandle(Arc<Mutex<Hasher>>);
+
+impl ChecksumHandle {
+    /// The checksum of everything that has been read so far
+    pub fn checksum(&self) -> Checksum {
+        lock(&self.0).checksum()
+    }
+
+    /// The number of bytes that have been read so far
+    pub fn processed(&self) -> u64 {
+        lock(&self.0).processed()
+    }
+}
+
+/// Compute the checksum of the file at `path`, calling `progress` with the
+/// number of bytes hashed so far and the size of the file after each chunk.
+pub fn checksum_file<P, F>(path: P, checksum_type: ChecksumType, progress: F) -> Result<Checksum>
+where
+    P: AsRef<Path>,
+    F: FnMut(u64, Option<u64>) + Send + 'static,
+{
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = HashingReader::new(file, checksum_type).with_progress(Some(size), progress);
+    let mut buf = vec![0; CHUNK_SIZE];
+    while reader.read(&mut buf)? > 0 {}
+    Ok(reader.checksum())
+}
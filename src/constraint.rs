@@ -0,0 +1,382 @@
+//! # Version constraint module
+//!
+//! This module implements the version constraints that Vagrant accepts for
+//! `config.vm.box_version`, e.g. `~> 20.1` or `>= 1.2, < 2.0`. Vagrant itself
+//! uses RubyGems' `Gem::Requirement` for these, vagabond follows its semantics
+//! as closely as possible.
+//!
+//! A constraint can be used to predict which version of a box `vagrant up`
+//! would pull:
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::constraint::VersionConstraint;
+//! let client = Client::new(None as Option<String>);
+//! let username = "opensuse".to_string();
+//! let box_name = "Tumbleweed.x86_64".to_string();
+//! let tumbleweed = client
+//!     .read_box(&VagrantBox::new(&username, &box_name))
+//!     .unwrap();
+//!
+//! let constraint: VersionConstraint = "~> 1.0, >= 1.0.2".parse().unwrap();
+//! match tumbleweed.resolve_version(&constraint, "libvirt", None) {
+//!     Some(ver) => println!("vagrant up would use {}", ver.version),
+//!     None => println!("no matching version found"),
+//! }
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use super::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single segment of a version number
+enum Segment {
+    Number(u64),
+    Text(String),
+}
+
+impl Segment {
+    fn is_text(&self) -> bool {
+        matches!(self, Segment::Text(_))
+    }
+}
+
+impl Ord for Segment {
+    /// Numeric segments are compared numerically, textual segments
+    /// lexicographically and textual segments are always smaller than numeric
+    /// ones (so that `1.0.a` < `1.0.0`), like RubyGems does it.
+    fn cmp(&self, other: &Segment) -> Ordering {
+        match (self, other) {
+            (Segment::Number(l), Segment::Number(r)) => l.cmp(r),
+            (Segment::Text(l), Segment::Text(r)) => l.cmp(r),
+            (Segment::Text(_), Segment::Number(_)) => Ordering::Less,
+            (Segment::Number(_), Segment::Text(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Segment {
+    fn partial_cmp(&self, other: &Segment) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A version number of a box, e.g. `1.2.3` or `20.1.0.beta1`
+///
+/// Version numbers consist of dot separated segments, which can either be
+/// numeric or alphabetic. Versions that contain an alphabetic segment are
+/// considered pre-releases and sort before the corresponding release.
+/// Trailing zeros are insignificant, i.e. `1.2` == `1.2.0`.
+pub struct VersionNumber {
+    original: String,
+    segments: Vec<Segment>,
+}
+
+impl VersionNumber {
+    /// Returns true if this version contains an alphabetic segment
+    pub fn is_prerelease(&self) -> bool {
+        self.segments.iter().any(Segment::is_text)
+    }
+
+    /// Segments without trailing zeros, used for comparisons
+    fn canonical_segments(&self) -> &[Segment] {
+        let mut len = self.segments.len();
+        while len > 0 && self.segments[len - 1] == Segment::Number(0) {
+            len -= 1;
+        }
+        &self.segments[..len]
+    }
+
+    /// The upper bound of a pessimistic constraint (`~>`) for this version
+    ///
+    /// Pre-release segments are dropped, then the last segment is dropped
+    /// (unless only one is left) and the new last one is incremented, e.g.
+    /// `1.2.3` => `1.3` and `1` => `2`.
+    fn bump(&self) -> VersionNumber {
+        let mut numbers: Vec<u64> = self
+            .segments
+            .iter()
+            .take_while(|s| !s.is_text())
+            .map(|s| match s {
+                Segment::Number(n) => *n,
+                Segment::Text(_) => unreachable!(),
+            })
+            .collect();
+        if numbers.len() > 1 {
+            numbers.pop();
+        }
+        match numbers.last_mut() {
+            Some(last) => *last += 1,
+            None => numbers.push(1),
+        }
+
+        let original = numbers
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>()
+            .join(".");
+        VersionNumber {
+            original,
+            segments: numbers.into_iter().map(Segment::Number).collect(),
+        }
+    }
+}
+
+impl FromStr for VersionNumber {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<VersionNumber> {
+        let original = s.trim();
+        let invalid = || Error::InvalidVersionConstraint(format!("invalid version '{}'", s));
+
+        if original.is_empty()
+            || !original
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        {
+            return Err(invalid());
+        }
+
+        let mut segments = vec![];
+        // a '-' denotes a pre-release, like RubyGems we treat it as '.pre.'
+        for part in original.replace('-', ".pre.").split('.') {
+            if part.is_empty() {
+                return Err(invalid());
+            }
+            // split mixed parts like "0rc1" into "0", "rc", "1"
+            let mut chars = part.chars().peekable();
+            while let Some(&c) = chars.peek() {
+                let is_digit = c.is_ascii_digit();
+                let mut seg = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() != is_digit {
+                        break;
+                    }
+                    seg.push(c);
+                    chars.next();
+                }
+                segments.push(if is_digit {
+                    Segment::Number(seg.parse().map_err(|_| invalid())?)
+                } else {
+                    Segment::Text(seg)
+                });
+            }
+        }
+
+        Ok(VersionNumber {
+            original: original.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for VersionNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.original)
+    }
+}
+
+impl Ord for VersionNumber {
+    fn cmp(&self, other: &VersionNumber) -> Ordering {
+        let lhs = self.canonical_segments();
+        let rhs = other.canonical_segments();
+        let zero = Segment::Number(0);
+
+        for i in 0..lhs.len().max(rhs.len()) {
+            let ord = lhs.get(i).unwrap_or(&zero).cmp(rhs.get(i).unwrap_or(&zero));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for VersionNumber {
+    fn partial_cmp(&self, other: &VersionNumber) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for VersionNumber {
+    fn eq(&self, other: &VersionNumber) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for VersionNumber {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Comparison operators that can be used in a version constraint
+pub enum Operator {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `~>`, the pessimistic operator: `~> 1.2.3` is equivalent to
+    /// `>= 1.2.3, < 1.3`
+    Pessimistic,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Operator::Equal => "=",
+                Operator::NotEqual => "!=",
+                Operator::Greater => ">",
+                Operator::GreaterOrEqual => ">=",
+                Operator::Less => "<",
+                Operator::LessOrEqual => "<=",
+                Operator::Pessimistic => "~>",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single requirement of a constraint, e.g. `>= 1.2`
+pub struct Requirement {
+    /// The comparison operator
+    pub operator: Operator,
+    /// The version the operator compares against
+    pub version: VersionNumber,
+}
+
+impl Requirement {
+    /// Check whether `version` satisfies this requirement
+    pub fn matches(&self, version: &VersionNumber) -> bool {
+        match self.operator {
+            Operator::Equal => version == &self.version,
+            Operator::NotEqual => version != &self.version,
+            Operator::Greater => version > &self.version,
+            Operator::GreaterOrEqual => version >= &self.version,
+            Operator::Less => version < &self.version,
+            Operator::LessOrEqual => version <= &self.version,
+            Operator::Pessimistic => version >= &self.version && version < &self.version.bump(),
+        }
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Requirement> {
+        let req = s.trim();
+        // longer operators have to come first, so that ">=" isn't parsed as ">"
+        let operators = [
+            ("~>", Operator::Pessimistic),
+            (">=", Operator::GreaterOrEqual),
+            ("<=", Operator::LessOrEqual),
+            ("!=", Operator::NotEqual),
+            ("=", Operator::Equal),
+            (">", Operator::Greater),
+            ("<", Operator::Less),
+        ];
+
+        let (operator, version) = operators
+            .iter()
+            .find(|(op, _)| req.starts_with(op))
+            .map_or((Operator::Equal, req), |(op, operator)| {
+                (*operator, &req[op.len()..])
+            });
+
+        Ok(Requirement {
+            operator,
+            version: version.parse().map_err(|_| {
+                Error::InvalidVersionConstraint(format!("invalid requirement '{}'", s.trim()))
+            })?,
+        })
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.operator, self.version)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A version constraint as accepted by Vagrant's `config.vm.box_version`
+///
+/// A constraint is a comma separated list of [`Requirement`]s, all of which
+/// have to be satisfied by a version for it to match. A requirement without
+/// an operator is treated as `=`.
+///
+/// ```
+/// # use vagabond::constraint::*;
+/// let constraint: VersionConstraint = ">= 1.2, < 2.0".parse().unwrap();
+/// assert!(constraint.matches(&"1.9.10".parse().unwrap()));
+/// assert!(!constraint.matches(&"2.0".parse().unwrap()));
+/// ```
+pub struct VersionConstraint {
+    requirements: Vec<Requirement>,
+}
+
+impl VersionConstraint {
+    /// Returns the individual requirements of this constraint
+    pub fn requirements(&self) -> &[Requirement] {
+        &self.requirements
+    }
+
+    /// Check whether `version` satisfies all requirements of this constraint
+    pub fn matches(&self, version: &VersionNumber) -> bool {
+        self.requirements.iter().all(|req| req.matches(version))
+    }
+
+    /// Convenience wrapper around `matches()`, which parses `version` first.
+    ///
+    /// Versions that cannot be parsed never match.
+    pub fn matches_str(&self, version: &str) -> bool {
+        version
+            .parse()
+            .map(|ver| self.matches(&ver))
+            .unwrap_or(false)
+    }
+}
+
+impl FromStr for VersionConstraint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<VersionConstraint> {
+        if s.trim().is_empty() {
+            return Err(Error::InvalidVersionConstraint(
+                "empty version constraint".to_string(),
+            ));
+        }
+        Ok(VersionConstraint {
+            requirements: s
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<Vec<Requirement>>>()?,
+        })
+    }
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.requirements
+                .iter()
+                .map(|req| req.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
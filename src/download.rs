@@ -0,0 +1,303 @@
+//! # Download module
+//!
+//! Box files are usually large, so downloading them (e.g. to re-host or to
+//! test them) has to survive flaky connections.
+//! [`Client::download_provider`](../struct.Client.html#method.download_provider)
+//! streams the box file of a provider to disk and verifies it against the
+//! checksum advertised by the provider:
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # let client = Client::new(None as Option<String>);
+//! # let username = "my_user".to_string();
+//! # let box_name = "awesome_box".to_string();
+//! let vagrant_box = client.read_box(&VagrantBox::new(&username, &box_name)).unwrap();
+//! let provider = &vagrant_box.current_version.unwrap().providers[0];
+//! let download = client
+//!     .download_provider(provider, "output/awesome_box.box", |done, total| {
+//!         println!("downloaded {} of {:?} bytes", done, total)
+//!     })
+//!     .unwrap();
+//! println!("{} ({})", download.path.display(), download.checksum);
+//! ```
+//!
+//! The file is downloaded to `<path>.partial` first and only moved to
+//! `<path>` once its checksum has been verified. If a download is
+//! interrupted, the next download to the same path resumes from the end of
+//! the partial file via a HTTP `Range` request (falling back to a complete
+//! download if the server doesn't support it). Redirects are followed.
+//!
+//! The `ETag` (or `Last-Modified` date) of the file is kept in
+//! `<path>.partial.validator` and sent as `If-Range`, so that the server
+//! sends the whole file again if it changed in the meantime. Downloads
+//! without such a validator are only resumed if the expected checksum is
+//! known.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use reqwest::header::{
+    HeaderMap, HeaderValue, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use reqwest::StatusCode;
+
+use super::checksum::{Checksum, ChecksumType, Hasher, CHUNK_SIZE};
+use super::observer::Entity;
+use super::{api, Client, Error, RequestType, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A completed download
+pub struct Download {
+    /// The path to which the file was downloaded
+    pub path: PathBuf,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Number of bytes that had been downloaded by an interrupted earlier
+    /// attempt, `0` if the download started from scratch
+    pub resumed_from: u64,
+    /// Checksum of the file, which matches the expected checksum if one was
+    /// given (`sha256` otherwise)
+    pub checksum: Checksum,
+}
+
+/// The path of the partial download of `path`
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+/// The path of the file storing the validator of the partial download of
+/// `path`
+fn validator_path(path: &Path) -> PathBuf {
+    let mut validator = partial_path(path).into_os_string();
+    validator.push(".validator");
+    PathBuf::from(validator)
+}
+
+/// The value for an `If-Range` header that ensures that a range of the file
+/// from the response with `headers` is only sent if the file is unchanged
+///
+/// Weak entity tags cannot be used in `If-Range`.
+fn validator(headers: &HeaderMap) -> Option<&HeaderValue> {
+    headers
+        .get(ETAG)
+        .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+        .or_else(|| headers.get(LAST_MODIFIED))
+}
+
+/// Remove the file at `path` if it exists
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Parse the start and the total size from the header
+/// `Content-Range: bytes <start>-<end>/<total or *>`
+fn content_range(headers: &HeaderMap) -> Option<(u64, Option<u64>)> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-')?.0.trim().parse().ok()?;
+    Some((start, total.trim().parse().ok()))
+}
+
+impl Client {
+    /// Download the box file of `provider` to `path` and verify it against
+    /// the checksum of `provider` (if it has one), see
+    /// [`download`](#method.download).
+    ///
+    /// Returns an `Error::UnsupportedChecksumType` or an
+    /// `Error::MissingChecksumType` without downloading anything if the
+    /// checksum of `provider` cannot be verified.
+    pub fn download_provider<P, F>(
+        &self,
+        provider: &api::Provider,
+        path: P,
+        progress: F,
+    ) -> Result<Download>
+    where
+        P: AsRef<Path>,
+        F: FnMut(u64, Option<u64>),
+    {
+        let expected = match (&provider.checksum_type, &provider.checksum) {
+            (Some(checksum_type), Some(checksum)) => Some(Checksum {
+                checksum_type: checksum_type.parse()?,
+                checksum: checksum.to_lowercase(),
+            }),
+            (None, Some(_)) => return Err(Error::MissingChecksumType(provider.name.clone())),
+            _ => None,
+        };
+        let entity = Entity {
+            provider: Some(provider.name.to_string()),
+            ..Default::default()
+        };
+        self.fetch(
+            &provider.download_url,
+            expected.as_ref(),
+            path.as_ref(),
+            &entity,
+            progress,
+        )
+    }
+
+    /// Download the file at `url` to `path`, calling `progress` with the
+    /// number of bytes downloaded so far and the size of the file (if known)
+    /// after each chunk.
+    ///
+    /// If `expected` is given, the file must match it, otherwise an
+    /// `Error::ChecksumMismatch` is returned and the partial download is
+    /// discarded. The file only appears at `path` once it is complete and
+    /// verified, an existing file at `path` is replaced.
+    pub fn download<P, F>(
+        &self,
+        url: &str,
+        expected: Option<&Checksum>,
+        path: P,
+        progress: F,
+    ) -> Result<Download>
+    where
+        P: AsRef<Path>,
+        F: FnMut(u64, Option<u64>),
+    {
+        self.fetch(url, expected, path.as_ref(), &Entity::default(), progress)
+    }
+
+    fn fetch<F>(
+        &self,
+        url: &str,
+        expected: Option<&Checksum>,
+        path: &Path,
+        entity: &Entity,
+        mut progress: F,
+    ) -> Result<Download>
+    where
+        F: FnMut(u64, Option<u64>),
+    {
+        let checksum_type = expected.map_or(ChecksumType::Sha256, |c| c.checksum_type);
+        let partial = partial_path(path);
+        let validator_file = validator_path(path);
+        let if_range = match fs::read_to_string(&validator_file) {
+            Ok(validator) => HeaderValue::from_str(validator.trim()).ok(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut hasher = Hasher::new(checksum_type);
+        // without a validator only the expected checksum would notice that
+        // the partial file belongs to an older version of the file
+        let mut resumed_from = match File::open(&partial) {
+            Ok(mut file) if if_range.is_some() || expected.is_some() => {
+                io::copy(&mut file, &mut hasher)?
+            }
+            Ok(_) => 0,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut response = None;
+        if resumed_from > 0 {
+            let mut headers = HeaderMap::new();
+            let range = format!("bytes={}-", resumed_from);
+            headers.insert(
+                RANGE,
+                HeaderValue::from_str(&range).map_err(|e| Error::InternalError(e.to_string()))?,
+            );
+            if let Some(if_range) = if_range {
+                headers.insert(IF_RANGE, if_range);
+            }
+            response = match self.transfer(url, RequestType::Get, entity, headers, None) {
+                Ok(r) => Some(r),
+                // the partial file is as large as the file or larger
+                Err(Error::UnexpectedStatus(details))
+                    if details.status == StatusCode::RANGE_NOT_SATISFIABLE =>
+                {
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+        }
+        let resumed = response.as_ref().is_some_and(|r| {
+            r.status() == StatusCode::PARTIAL_CONTENT
+                && content_range(r.headers()).map(|(start, _)| start) == Some(resumed_from)
+        });
+        if !resumed {
+            if resumed_from > 0 {
+                debug!("Cannot resume the download of {}, starting over", url);
+            }
+            resumed_from = 0;
+            hasher = Hasher::new(checksum_type);
+            // servers ignoring the Range header reply with the whole file
+            response = response.filter(|r| r.status() == StatusCode::OK);
+        }
+        let mut response = match response {
+            Some(r) => r,
+            None => self.transfer(url, RequestType::Get, entity, HeaderMap::new(), None)?,
+        };
+        let total = if resumed {
+            content_range(response.headers())
+                .and_then(|(_, total)| total)
+                .or_else(|| response.content_length().map(|len| len + resumed_from))
+        } else {
+            response.content_length()
+        };
+
+        let mut file = if resumed {
+            OpenOptions::new().append(true).open(&partial)?
+        } else {
+            match validator(response.headers()) {
+                Some(validator) => fs::write(&validator_file, validator.as_bytes())?,
+                None => remove_if_exists(&validator_file)?,
+            }
+            File::create(&partial)?
+        };
+        let mut size = resumed_from;
+        let mut buf = vec![0; CHUNK_SIZE];
+        progress(size, total);
+        loop {
+            // keep the partial file to resume from it
+            let len = response.read(&mut buf).map_err(|e| {
+                Error::IncompleteDownload(format!(
+                    "the download of {} failed after {} bytes: {}",
+                    url, size, e
+                ))
+            })?;
+            if len == 0 {
+                break;
+            }
+            file.write_all(&buf[..len])?;
+            hasher.update(&buf[..len]);
+            size += len as u64;
+            progress(size, total);
+        }
+        file.sync_all()?;
+        if let Some(total) = total.filter(|t| *t != size) {
+            return Err(Error::IncompleteDownload(format!(
+                "the download of {} ended after {} of {} bytes",
+                url, size, total
+            )));
+        }
+
+        let checksum = hasher.checksum();
+        if let Some(expected) = expected {
+            if !expected.checksum.eq_ignore_ascii_case(&checksum.checksum) {
+                let _ = fs::remove_file(&partial);
+                let _ = fs::remove_file(&validator_file);
+                return Err(Error::ChecksumMismatch {
+                    url: url.to_string(),
+                    expected: expected.clone(),
+                    actual: checksum,
+                });
+            }
+        }
+        fs::rename(&partial, path)?;
+        remove_if_exists(&validator_file)?;
+        Ok(Download {
+            path: path.to_path_buf(),
+            size,
+            resumed_from,
+            checksum,
+        })
+    }
+}
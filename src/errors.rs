@@ -2,59 +2,249 @@
 //!
 //! This module defines a common error type [`Error`](enum.Error.html) alongside
 //! with a [`Result`](type.Result.html) type, which should behave about as
-//! you'd expect from a Rust module.
+//! you'd expect from a Rust module. [`Error`](enum.Error.html) implements
+//! `std::error::Error`, so it can be used with any error handling library.
 //!
-//! A noteworthy convenience method of [`Error`](enum.Error.html) is
-//! `into_status()`, which can be used to extract the HTTP status code from a
-//! [`Error`](enum.Error.html) (provided it was caused by a HTTP error),
-//! resulting in the following (crudely simplified) error handling:
+//! Errors reported by the Vagrant Cloud API are mapped to dedicated variants
+//! depending on the HTTP status code of the reply (e.g.
+//! [`Error::NotFound`](enum.Error.html#variant.NotFound) for 404), so that
+//! they can be matched directly:
 //!
 //! ```no_run
-//! # extern crate reqwest;
 //! # use vagabond::*;
 //! # let username = "my_user_name".to_string();
 //! # let box_name = "none".to_string();
 //! let client = Client::new(None as Option<String>);
 //! let vagrant_box = VagrantBox::new(&username, &box_name);
-//! let box_res = client.read_box(&vagrant_box);
-//! if box_res.is_err() {
-//!     box_res.err()
-//!         .unwrap()
-//!         .into_status()
-//!         .and_then(|status| {
-//!             println!("oops, got {}, will pretend it was a 404", status);
-//!             Some(reqwest::StatusCode::NOT_FOUND)
-//!         });
+//! match client.read_box(&vagrant_box) {
+//!     Ok(b) => println!("Found the box {}", b.name),
+//!     Err(Error::NotFound(_)) => println!("This box does not exist"),
+//!     Err(Error::RateLimited { retry_after, .. }) => {
+//!         println!("Slow down, try again after {:?}", retry_after)
+//!     }
+//!     Err(e) => println!("Something else went wrong: {}", e),
 //! }
 //! ```
+//!
+//! Every error caused by a reply of the API carries an
+//! [`ApiError`](struct.ApiError.html) with the request method, URL, status
+//! code and the raw reply for diagnostic purposes. The convenience method
+//! `into_status()` can be used to extract the HTTP status code from an
+//! [`Error`](enum.Error.html) (provided it was caused by a HTTP error).
 
 extern crate reqwest;
 
+use std::fmt;
+use std::time::Duration;
+
+use super::checksum::Checksum;
+use super::schema::SchemaDeviation;
+use super::transaction::RollbackReport;
+use super::ProviderName;
+
 /// Default Result type as returned by most methods from vagabond
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Fail, Debug)]
+#[derive(Debug, Clone, PartialEq)]
+/// Details about a failed call to the Vagrant Cloud API
+pub struct ApiError {
+    /// HTTP method of the failed request (e.g. `GET`)
+    pub method: String,
+    /// URL to which the request was made
+    pub url: String,
+    /// HTTP status code with which the API replied
+    pub status: reqwest::StatusCode,
+    /// The human readable errors reported by the Vagrant Cloud API, empty if
+    /// the reply could not be parsed
+    pub errors: Vec<String>,
+    /// The raw body of the reply
+    pub body: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} failed with status {}",
+            self.method, self.url, self.status
+        )?;
+        if !self.errors.is_empty() {
+            write!(f, ": {}", self.errors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Details about a reply of the Vagrant Cloud API that couldn't be
+/// deserialized into the expected format
+pub struct DecodeError {
+    /// URL from which the reply was received
+    pub url: String,
+    /// Path to the offending element of the reply (e.g.
+    /// `versions[0].providers[1].download_url`), `.` if the error occurred at
+    /// the top level
+    pub path: String,
+    /// Line of the reply at which deserialization failed (0 if the reply was
+    /// empty)
+    pub line: usize,
+    /// Column of the reply at which deserialization failed (0 if the reply was
+    /// empty)
+    pub column: usize,
+    /// Description of what went wrong
+    pub message: String,
+    /// The raw body of the reply
+    pub body: Vec<u8>,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at '{}' (line {}, column {}) in the reply from {}",
+            self.message, self.path, self.line, self.column, self.url
+        )
+    }
+}
+
+#[derive(Debug)]
 /// Common error type for vagabond
 pub enum Error {
-    #[fail(display = "{}", _0)]
     /// Communication with the API failed due to an external reason
     /// (e.g. API down, no network connection)
-    Io(#[fail(cause)] reqwest::Error),
+    Io(reqwest::Error),
 
-    #[fail(display = "Request failed with status {}: {}", _0, _1)]
-    /// The VagrantCloud API reported an error
-    ///
-    /// The first element of this tuple contains the status code which the API
-    /// replied, the second is a semicolon separated list of the human readable
-    /// errors reported by the Vagrant Cloud API.
-    ApiCallFailure(reqwest::StatusCode, String),
+    /// The requested resource does not exist (404)
+    NotFound(Box<ApiError>),
+
+    /// No or an invalid API token was provided (401)
+    Unauthorized(Box<ApiError>),
+
+    /// The API token is not allowed to perform this operation (403)
+    Forbidden(Box<ApiError>),
+
+    /// The resource that should be created already exists (409)
+    Conflict(Box<ApiError>),
+
+    /// The API rejected the submitted data (400 or 422)
+    Validation {
+        /// The validation errors reported by the Vagrant Cloud API
+        errors: Vec<String>,
+        /// Details about the failed request
+        details: Box<ApiError>,
+    },
+
+    /// Too many requests have been made (429)
+    RateLimited {
+        /// How long to wait before retrying, if the API told us (via the
+        /// `Retry-After` header in seconds or as a date)
+        retry_after: Option<Duration>,
+        /// Details about the failed request
+        details: Box<ApiError>,
+    },
+
+    /// The Vagrant Cloud API encountered an internal error (5xx)
+    Server(Box<ApiError>),
+
+    /// The VagrantCloud API replied with a status code that is neither a
+    /// success nor covered by any of the other variants
+    UnexpectedStatus(Box<ApiError>),
 
-    #[fail(display = "Unexpected response from the API: {}", _0)]
     /// The VagrantCloud API replied with data that couldn't be deserialized
     /// into the expected format
-    UnexpectedResponse(String),
+    UnexpectedResponse(Box<DecodeError>),
+
+    /// The VagrantCloud API replied with data that deviates from the expected
+    /// schema (only reported in `DeserializationMode::Strict`)
+    SchemaViolation {
+        /// URL from which the reply was received
+        url: String,
+        /// All deviations from the schema
+        deviations: Vec<SchemaDeviation>,
+    },
+
+    /// A version or version constraint could not be parsed
+    InvalidVersionConstraint(String),
+
+    /// A box tag, username or box name is invalid
+    InvalidBoxTag(String),
+
+    /// A value cannot be used as a path segment of an API endpoint
+    InvalidPathSegment(String),
+
+    /// An operation of a plan failed and all previously performed operations
+    /// were rolled back (see the [`transaction`](../transaction/index.html)
+    /// module)
+    RolledBack {
+        /// The error of the failed operation
+        error: Box<Error>,
+        /// The outcome of the rollback
+        rollback: Box<RollbackReport>,
+    },
+
+    /// Multiple independent operations failed (see the
+    /// [`parallel`](../parallel/index.html) module)
+    Aggregate(Vec<Error>),
+
+    /// A provider was passed multiple times
+    DuplicateProvider(ProviderName),
+
+    /// A version has to be published with at least one provider
+    NoProviders,
+
+    /// A version with this provider hosted on Vagrant Cloud cannot be deleted
+    /// after promoting it, as its copy still downloads the hosted file
+    HostedSourceProvider(ProviderName),
+
+    /// A regular expression could not be parsed
+    InvalidPattern(String),
+
+    /// An audit log could not be parsed
+    InvalidAuditLog(String),
+
+    /// A manifest could not be parsed
+    InvalidManifest(String),
+
+    /// A `.box` file is malformed
+    InvalidBoxFile(String),
+
+    /// A box catalog could not be parsed
+    InvalidCatalog(String),
+
+    /// A checksum type that is not supported by Vagrant Cloud
+    UnsupportedChecksumType(String),
+
+    /// A provider has a checksum but no checksum type
+    MissingChecksumType(ProviderName),
+
+    /// A downloaded file does not match its advertised checksum
+    ChecksumMismatch {
+        /// URL from which the file was downloaded
+        url: String,
+        /// The advertised checksum
+        expected: Checksum,
+        /// The checksum of the downloaded file
+        actual: Checksum,
+    },
+
+    /// A download ended before the whole file was received, it is resumed
+    /// by the next download to the same path
+    IncompleteDownload(String),
+
+    /// A box is already installed in the local box store (see the `store`
+    /// module)
+    BoxAlreadyInstalled(String),
+
+    /// The storage of the registry server is corrupt or unavailable (see
+    /// the `server` module)
+    Storage(String),
+
+    /// The registry server cannot listen on the requested address
+    Listen(String),
+
+    /// Reading from or writing to the local filesystem failed
+    Filesystem(std::io::Error),
 
-    #[fail(display = "Internal error occurred: {}", _0)]
     /// An internal error inside vagabond occurred
     ///
     /// As a API consumer you **really** shouldn't be seeing this kind of
@@ -62,12 +252,128 @@ pub enum Error {
     InternalError(String),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::NotFound(d) => write!(f, "Resource not found: {}", d),
+            Error::Unauthorized(d) => write!(f, "Unauthorized: {}", d),
+            Error::Forbidden(d) => write!(f, "Forbidden: {}", d),
+            Error::Conflict(d) => write!(f, "Conflict: {}", d),
+            Error::Validation { details, .. } => write!(f, "Validation failed: {}", details),
+            Error::RateLimited { details, .. } => write!(f, "Rate limited: {}", details),
+            Error::Server(d) => write!(f, "Server error: {}", d),
+            Error::UnexpectedStatus(d) => write!(f, "Request failed: {}", d),
+            Error::UnexpectedResponse(e) => write!(f, "Unexpected response from the API: {}", e),
+            Error::SchemaViolation { url, deviations } => write!(
+                f,
+                "The reply from {} deviates from the schema: {}",
+                url,
+                deviations
+                    .iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            ),
+            Error::InvalidVersionConstraint(msg) => {
+                write!(f, "Invalid version constraint: {}", msg)
+            }
+            Error::InvalidBoxTag(msg) => write!(f, "Invalid box tag: {}", msg),
+            Error::InvalidPathSegment(seg) => {
+                write!(f, "'{}' cannot be used in the path of a request", seg)
+            }
+            Error::RolledBack { error, rollback } => write!(
+                f,
+                "Operation '{}' failed: {}, {}",
+                rollback.failed_operation, error, rollback
+            ),
+            Error::Aggregate(errors) => write!(
+                f,
+                "{} operations failed: {}",
+                errors.len(),
+                errors
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            ),
+            Error::DuplicateProvider(name) => write!(f, "Provider '{}' was given twice", name),
+            Error::NoProviders => write!(f, "No provider was given"),
+            Error::HostedSourceProvider(name) => write!(
+                f,
+                "The provider '{}' is hosted on the source box, deleting it would break its promoted copy",
+                name
+            ),
+            Error::InvalidPattern(msg) => write!(f, "Invalid pattern: {}", msg),
+            Error::InvalidAuditLog(msg) => write!(f, "Invalid audit log: {}", msg),
+            Error::InvalidManifest(msg) => write!(f, "Invalid manifest: {}", msg),
+            Error::InvalidBoxFile(msg) => write!(f, "Invalid box file: {}", msg),
+            Error::InvalidCatalog(msg) => write!(f, "Invalid catalog: {}", msg),
+            Error::ChecksumMismatch {
+                url,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The file downloaded from {} has the checksum {} instead of {}",
+                url, actual, expected
+            ),
+            Error::BoxAlreadyInstalled(b) => write!(f, "The box {} is already installed", b),
+            Error::Storage(msg) => write!(f, "Registry storage error: {}", msg),
+            Error::Listen(msg) => write!(f, "Cannot start the registry server: {}", msg),
+            Error::UnsupportedChecksumType(t) => write!(f, "Unsupported checksum type '{}'", t),
+            Error::MissingChecksumType(name) => write!(
+                f,
+                "The provider '{}' has a checksum but no checksum type",
+                name
+            ),
+            Error::IncompleteDownload(msg) => write!(f, "Incomplete download: {}", msg),
+            Error::Filesystem(e) => write!(f, "Filesystem error: {}", e),
+            Error::InternalError(msg) => write!(f, "Internal error occurred: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Filesystem(e) => Some(e),
+            Error::RolledBack { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Error {
         Error::Io(err)
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Filesystem(err)
+    }
+}
+
+/// Parse the value of a `Retry-After` header, which is either a number of
+/// seconds or a HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`)
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // a date in the past means that the request can be retried right away
+    Some(
+        date.with_timezone(&chrono::Utc)
+            .signed_duration_since(chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
 impl Error {
     /// Extract the status code of this Error if it was caused by an API call
     /// failure, otherwise return None.
@@ -77,8 +383,14 @@ impl Error {
     /// If the error originates from an API call failure:
     /// ```
     /// # use vagabond::errors::*;
-    /// let status = reqwest::StatusCode::OK;
-    /// let err = Error::ApiCallFailure(status, "error".to_string());
+    /// let status = reqwest::StatusCode::NOT_FOUND;
+    /// let err = Error::NotFound(Box::new(ApiError {
+    ///     method: "GET".to_string(),
+    ///     url: "https://app.vagrantup.com/api/v1/box/foo/bar".to_string(),
+    ///     status,
+    ///     errors: vec![],
+    ///     body: "".to_string(),
+    /// }));
     /// assert_eq!(err.into_status(), Some(status));
     /// ```
     ///
@@ -89,11 +401,69 @@ impl Error {
     /// assert_eq!(other_error.into_status(), None);
     /// ```
     pub fn into_status(&self) -> Option<reqwest::StatusCode> {
-        match &self {
-            Error::ApiCallFailure(st, _) => Some(*st),
+        self.details().map(|d| d.status)
+    }
+
+    /// Returns the details of the failed request if this Error was caused by
+    /// an API call failure, otherwise None.
+    pub fn details(&self) -> Option<&ApiError> {
+        match self {
+            Error::NotFound(d)
+            | Error::Unauthorized(d)
+            | Error::Forbidden(d)
+            | Error::Conflict(d)
+            | Error::Validation { details: d, .. }
+            | Error::RateLimited { details: d, .. }
+            | Error::Server(d)
+            | Error::UnexpectedStatus(d) => Some(d),
             _ => None,
         }
     }
+
+    /// Create an [`Error`](enum.Error.html) from the reply to a `method`
+    /// request that was not successful
+    pub(crate) fn from_response(method: &str, resp: reqwest::blocking::Response) -> Error {
+        let status = resp.status();
+        let url = resp.url().to_string();
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|val| val.to_str().ok())
+            .and_then(parse_retry_after);
+
+        let body = resp.text().unwrap_or_default();
+        let errors = match serde_json::from_str::<VagrantCloudErrorPayload>(&body) {
+            Ok(rpl) => rpl.errors,
+            Err(_) => vec![],
+        };
+
+        let details = Box::new(ApiError {
+            method: method.to_string(),
+            url,
+            status,
+            errors,
+            body,
+        });
+
+        match status {
+            reqwest::StatusCode::NOT_FOUND => Error::NotFound(details),
+            reqwest::StatusCode::UNAUTHORIZED => Error::Unauthorized(details),
+            reqwest::StatusCode::FORBIDDEN => Error::Forbidden(details),
+            reqwest::StatusCode::CONFLICT => Error::Conflict(details),
+            reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::UNPROCESSABLE_ENTITY => {
+                Error::Validation {
+                    errors: details.errors.clone(),
+                    details,
+                }
+            }
+            reqwest::StatusCode::TOO_MANY_REQUESTS => Error::RateLimited {
+                retry_after,
+                details,
+            },
+            st if st.is_server_error() => Error::Server(details),
+            _ => Error::UnexpectedStatus(details),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -105,16 +475,3 @@ struct VagrantCloudErrorPayload {
     /// this should be false, otherwise something is **really** weird
     success: bool,
 }
-
-impl From<reqwest::blocking::Response> for Error {
-    /// Create a [`Error`](enum.Error.html) from a `reqwest::Response`
-    fn from(resp: reqwest::blocking::Response) -> Error {
-        let status = resp.status();
-        let msg: reqwest::Result<VagrantCloudErrorPayload> = resp.json();
-        let err_msg: String = match msg {
-            Ok(rpl) => rpl.errors.join(", "),
-            Err(_) => "".to_string(),
-        };
-        Error::ApiCallFailure(status, err_msg)
-    }
-}
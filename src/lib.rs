@@ -65,12 +65,9 @@
 //! client.create_version(&vagrant_box, &box_version);
 //!
 //! // 3. create a provider
-//! let provider_name = "libvirt".to_string();
+//! let provider_name = ProviderName::Libvirt;
 //! let url = "https://foo.bar.baz/path/to/my/awesome.box".to_string();
-//! let provider = BoxProvider {
-//!     name: &provider_name,
-//!     url: &url,
-//! };
+//! let provider = BoxProvider::new(&provider_name, &url);
 //! client.create_provider(&vagrant_box, &box_version, &provider);
 //!
 //! // 4. release the version
@@ -95,8 +92,6 @@ extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
-extern crate failure;
-#[macro_use]
 extern crate log;
 
 #[cfg(test)]
@@ -106,9 +101,37 @@ extern crate lazy_static;
 use std::fmt;
 
 pub mod api;
+pub mod audit;
+pub mod boxfile;
+pub mod catalog;
+pub mod checksum;
+pub mod constraint;
+pub mod download;
 pub mod errors;
+pub mod manifest;
+pub mod mirror;
+pub mod observer;
+pub mod packager;
+pub mod parallel;
+pub mod plan;
+pub mod promote;
+pub mod provider;
+pub mod retention;
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod site;
+pub mod store;
+pub mod tag;
+pub mod transaction;
 
 pub use errors::*;
+pub use provider::ProviderName;
+pub use tag::BoxTag;
+
+use observer::{Entity, Event, Observed, Observers};
+use parallel::RateLimiter;
+use schema::{DeserializationMode, Schema};
 
 #[cfg(test)]
 mod tests;
@@ -116,10 +139,10 @@ mod tests;
 #[derive(Debug)]
 /// Available HTTP request types
 enum RequestType {
-    GET,
-    POST,
-    DELETE,
-    PUT,
+    Get,
+    Post,
+    Delete,
+    Put,
 }
 
 impl fmt::Display for RequestType {
@@ -128,19 +151,51 @@ impl fmt::Display for RequestType {
             f,
             "{}",
             match *self {
-                RequestType::GET => "GET",
-                RequestType::POST => "POST",
-                RequestType::DELETE => "DELETE",
-                RequestType::PUT => "PUT",
+                RequestType::Get => "GET",
+                RequestType::Post => "POST",
+                RequestType::Delete => "DELETE",
+                RequestType::Put => "PUT",
             }
         )
     }
 }
 
+/// A successful reply of the Vagrant Cloud API
+struct Reply {
+    status: reqwest::StatusCode,
+    url: String,
+    body: Vec<u8>,
+}
+
+impl Observed for reqwest::blocking::Response {
+    fn status(&self) -> Option<reqwest::StatusCode> {
+        Some(self.status())
+    }
+}
+
+impl Observed for Reply {
+    fn status(&self) -> Option<reqwest::StatusCode> {
+        Some(self.status)
+    }
+
+    fn body(&self) -> Option<&[u8]> {
+        Some(&self.body)
+    }
+}
+
+/// Base URL of the Vagrant Cloud API
+const VAGRANT_CLOUD_API_URL: &str = "https://app.vagrantup.com/api/v1";
+
 #[derive(Debug)]
 /// Client for communication with the Vagrant Cloud API
 pub struct Client {
     token: Option<String>,
+    base_url: String,
+    deserialization_mode: DeserializationMode,
+    rollback: bool,
+    observers: Observers,
+    max_concurrency: usize,
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl Client {
@@ -159,18 +214,85 @@ impl Client {
     {
         Client {
             token: token.map(|s| s.into()),
+            base_url: VAGRANT_CLOUD_API_URL.to_string(),
+            deserialization_mode: DeserializationMode::default(),
+            rollback: false,
+            observers: Observers::default(),
+            max_concurrency: 1,
+            rate_limiter: None,
+        }
+    }
+
+    /// Set how replies from the API are deserialized (see the
+    /// [`schema`](schema/index.html) module), defaults to
+    /// `DeserializationMode::Lenient`.
+    pub fn with_deserialization_mode(mut self, mode: DeserializationMode) -> Client {
+        self.deserialization_mode = mode;
+        self
+    }
+
+    /// Send all requests to the Vagrant Cloud compatible API at `base_url`,
+    /// e.g. an internal registry, defaults to
+    /// `https://app.vagrantup.com/api/v1`.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Client {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Roll back all performed operations if applying a plan fails (see the
+    /// [`transaction`](transaction/index.html) module), defaults to `false`.
+    ///
+    /// This affects [`apply`](#method.apply) and all functions built on top
+    /// of it, like [`ensure_provider_present`](#method.ensure_provider_present).
+    pub fn with_rollback(mut self, enabled: bool) -> Client {
+        self.rollback = enabled;
+        self
+    }
+
+    /// Build the URL of the API endpoint consisting of the path `segments`
+    ///
+    /// Each segment is percent-encoded, so that e.g. a `/` in a segment
+    /// cannot alter the path of the request. Segments consisting only of `.`
+    /// or `..` would be normalized away by the URL parser and are therefore
+    /// rejected with an `Error::InvalidPathSegment`.
+    fn endpoint(&self, segments: &[&str]) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&self.base_url)
+            .map_err(|e| Error::InternalError(format!("error parsing the url, got: '{}'", e)))?;
+
+        if let Some(seg) = segments.iter().find(|seg| *seg == &"." || *seg == &"..") {
+            return Err(Error::InvalidPathSegment(seg.to_string()));
         }
+
+        url.path_segments_mut()
+            .map_err(|_| {
+                Error::InternalError(format!("'{}' cannot be used as a base URL", self.base_url))
+            })?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// Build the URL of an API endpoint belonging to `vagrant_box`, which is
+    /// followed by the path `segments`
+    ///
+    /// Returns an `Error::InvalidBoxTag` if the username or name of
+    /// `vagrant_box` are invalid.
+    fn box_endpoint(&self, vagrant_box: &VagrantBox, segments: &[&str]) -> Result<reqwest::Url> {
+        let tag = vagrant_box.box_tag()?;
+        let mut all_segments = tag.path_segments().to_vec();
+        all_segments.extend_from_slice(segments);
+        self.endpoint(&all_segments)
     }
 
     /// General purpose method to perform a call to the Vagrant Cloud API
     ///
     /// Parameters:
     /// - `api_url`: URL to which the call will be made. Must be convertible to
-    ///     a `String`. If it cannot be converted to a valid reqwest::Url, then
-    ///     this function returns a `Error::IntenralError`.
+    ///   a `String`. If it cannot be converted to a valid reqwest::Url, then
+    ///   this function returns a `Error::IntenralError`.
     /// - `request_type`: type of HTTP request to be performed
     /// - `payload`: Optional payload, will be send as serialized as json with
-    ///     the request (must thus support the Deserialize trait from serde)
+    ///   the request (must thus support the Deserialize trait from serde)
     ///
     /// This function performs a call to the specified `api_url` with the
     /// specified `request_type`.
@@ -186,7 +308,12 @@ impl Client {
     /// - 204 No Content
     ///
     /// Then received data are deserialized from json into a new instance of
-    /// type `R`.
+    /// type `R`. Replies without a body (e.g. 204 No Content) are treated like
+    /// a JSON `null`, so they can only be received into types like `()` or
+    /// `Option<T>`. Any other type results in an `Error::UnexpectedResponse`.
+    /// If the client uses `DeserializationMode::Strict`, then the reply is
+    /// checked against the schema of `R` first and an `Error::SchemaViolation`
+    /// is returned if it deviates from it.
     ///
     /// Returns:
     /// - Result<R>: where R is some type that can be deserialized:
@@ -199,12 +326,10 @@ impl Client {
         payload: Option<P>,
     ) -> Result<R>
     where
-        for<'de> R: serde::Deserialize<'de>,
+        for<'de> R: serde::Deserialize<'de> + Schema,
         S: Into<String>,
         P: serde::Serialize,
     {
-        let client = reqwest::blocking::Client::new();
-
         let url = match reqwest::Url::parse(&api_url.into()) {
             Ok(u) => u,
             Err(e) => {
@@ -215,13 +340,53 @@ impl Client {
             }
         };
 
+        let payload = match payload {
+            Some(p) => Some(serde_json::to_string(&p).map_err(|e| {
+                Error::InternalError(format!("error serializing the payload, got: '{}'", e))
+            })?),
+            None => None,
+        };
+
+        let method = request_type.to_string();
+        let url_str = url.to_string();
+        let entity = Entity::from_request(&self.base_url, &url, payload.as_deref());
+        let event = Event::ApiCall {
+            method: &method,
+            url: &url_str,
+            entity: &entity,
+            payload: payload.as_deref().map(observer::summarize),
+            transfer: false,
+        };
+        let reply = self.observe(&event, || {
+            self.send_request(url, request_type, payload.as_deref())
+        })?;
+
+        if self.deserialization_mode == DeserializationMode::Strict {
+            check_schema::<R>(&reply.url, &reply.body)?;
+        }
+
+        decode_response(&reply.url, &reply.body).map_err(|e| {
+            debug!("Received unexpected response: {}", e);
+            e
+        })
+    }
+
+    /// Perform the request for `api_call` and return the successful reply
+    fn send_request(
+        &self,
+        url: reqwest::Url,
+        request_type: RequestType,
+        payload: Option<&str>,
+    ) -> Result<Reply> {
+        let client = reqwest::blocking::Client::new();
+
         debug!("Performing a {} request to {}", request_type, url);
 
         let mut builder = match request_type {
-            RequestType::GET => client.get(url),
-            RequestType::POST => client.post(url),
-            RequestType::DELETE => client.delete(url),
-            RequestType::PUT => client.put(url),
+            RequestType::Get => client.get(url),
+            RequestType::Post => client.post(url),
+            RequestType::Delete => client.delete(url),
+            RequestType::Put => client.put(url),
         };
         builder = match &self.token {
             Some(t) => {
@@ -232,71 +397,117 @@ impl Client {
         };
         builder = match payload {
             Some(p) => {
-                debug!(
-                    "Sending the following payload: {}",
-                    serde_json::to_string(&p)
-                        .or(Ok("Error serializing payload".to_string())
-                            as std::result::Result<String, serde_json::Error>)
-                        .unwrap()
-                );
-                builder.json(&p)
+                debug!("Sending the following payload: {}", p);
+                builder
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(p.to_string())
             }
             _ => builder,
         };
 
-        let mut response = builder.send()?;
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.wait();
+        }
+        let response = builder.send()?;
 
         debug!("Received status {}", response.status());
         match response.status() {
             reqwest::StatusCode::OK
             | reqwest::StatusCode::CREATED
-            | reqwest::StatusCode::NO_CONTENT => {
-                let mut recv_data: Vec<u8> = vec![];
-                response.copy_to(&mut recv_data)?;
-
-                match serde_json::from_slice(&recv_data) {
-                    Ok(r) => Ok(r),
-                    Err(e) => {
-                        debug!("Received unexpected response: {:?}", e);
-                        Err(Error::UnexpectedResponse(response.text()?.into()))
-                    }
-                }
+            | reqwest::StatusCode::NO_CONTENT => Ok(Reply {
+                status: response.status(),
+                url: response.url().to_string(),
+                body: response.bytes()?.to_vec(),
+            }),
+            _ => {
+                let err = Error::from_response(&request_type.to_string(), response);
+                self.back_off(&err);
+                Err(err)
             }
-            _ => Err(response)?,
         }
     }
 
+    /// Transfer a box file from or to `url`, which is not part of the API
+    /// (e.g. a download URL or an upload path), and return the response
+    /// without reading its body.
+    ///
+    /// The API token is **not** sent along, as `url` may point to a
+    /// different host, but `headers` are (e.g. a `Range`). Transfers are not
+    /// subject to the default timeout of API calls, as box files are usually
+    /// large.
+    fn transfer(
+        &self,
+        url: &str,
+        request_type: RequestType,
+        entity: &Entity,
+        headers: reqwest::header::HeaderMap,
+        body: Option<reqwest::blocking::Body>,
+    ) -> Result<reqwest::blocking::Response> {
+        let url = reqwest::Url::parse(url)
+            .map_err(|e| Error::InternalError(format!("error parsing the url, got: '{}'", e)))?;
+        let method = request_type.to_string();
+        let url_str = url.to_string();
+        let event = Event::ApiCall {
+            method: &method,
+            url: &url_str,
+            entity,
+            payload: None,
+            transfer: true,
+        };
+
+        self.observe(&event, || {
+            let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+            debug!("Transferring a box file via {} {}", request_type, url);
+            let mut builder = match request_type {
+                RequestType::Get => client.get(url),
+                RequestType::Post => client.post(url),
+                RequestType::Delete => client.delete(url),
+                RequestType::Put => client.put(url),
+            };
+            builder = builder.headers(headers);
+            if let Some(b) = body {
+                builder = builder.body(b);
+            }
+
+            if let Some(limiter) = &self.rate_limiter {
+                limiter.wait();
+            }
+            let response = builder.send()?;
+            debug!("Received status {}", response.status());
+            if response.status().is_success() {
+                Ok(response)
+            } else {
+                let err = Error::from_response(&method, response);
+                self.back_off(&err);
+                Err(err)
+            }
+        })
+    }
+
     pub fn create_box(&self, vagrant_box: &VagrantBox) -> Result<api::VagrantBox> {
-        let url = "https://app.vagrantup.com/api/v1/boxes/";
+        let url = self.endpoint(&["boxes"])?;
 
-        self.api_call(url, RequestType::POST, Some(vagrant_box)) as Result<api::VagrantBox>
+        self.api_call(url, RequestType::Post, Some(vagrant_box)) as Result<api::VagrantBox>
     }
 
-    pub fn delete_box(&self, vagrant_box: &VagrantBox) -> Result<api::VagrantBox> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{}/{}",
-            vagrant_box.username, vagrant_box.name
-        );
+    /// Deletes the box `vagrant_box` including all of its versions and
+    /// providers and returns the deleted box as reported by the API, or
+    /// `None` if the API replied with no content.
+    pub fn delete_box(&self, vagrant_box: &VagrantBox) -> Result<Option<api::VagrantBox>> {
+        let url = self.box_endpoint(vagrant_box, &[])?;
 
-        self.api_call(url, RequestType::DELETE, None as Option<VagrantBox>)
-            as Result<api::VagrantBox>
+        self.api_call(url, RequestType::Delete, None as Option<VagrantBox>)
+            as Result<Option<api::VagrantBox>>
     }
 
     pub fn read_box(&self, vagrant_box: &VagrantBox) -> Result<api::VagrantBox> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{}/{}",
-            vagrant_box.username, vagrant_box.name
-        );
+        let url = self.box_endpoint(vagrant_box, &[])?;
 
-        self.api_call(url, RequestType::GET, None as Option<VagrantBox>) as Result<api::VagrantBox>
+        self.api_call(url, RequestType::Get, None as Option<VagrantBox>) as Result<api::VagrantBox>
     }
 
     pub fn update_box(&self, vagrant_box: &VagrantBox) -> Result<api::VagrantBox> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{username}/{box_name}",
-            username = vagrant_box.username,
-            box_name = vagrant_box.name
-        );
+        let url = self.box_endpoint(vagrant_box, &[])?;
 
         let update_box = UpdateBox {
             name: vagrant_box.name,
@@ -307,7 +518,7 @@ impl Client {
 
         self.api_call(
             url,
-            RequestType::PUT,
+            RequestType::Put,
             Some(UpdateBoxPayload {
                 update_box: &update_box,
             }),
@@ -319,16 +530,13 @@ impl Client {
         vagrant_box: &VagrantBox,
         box_version: &BoxVersion,
     ) -> Result<api::Version> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{}/{}/versions",
-            vagrant_box.username, vagrant_box.name
-        );
+        let url = self.box_endpoint(vagrant_box, &["versions"])?;
 
         let ver: Version = Version {
             version: box_version,
         };
 
-        self.api_call(url, RequestType::POST, Some(ver)) as Result<api::Version>
+        self.api_call(url, RequestType::Post, Some(ver)) as Result<api::Version>
     }
 
     pub fn read_version(
@@ -336,49 +544,41 @@ impl Client {
         vagrant_box: &VagrantBox,
         box_version: &BoxVersion,
     ) -> Result<api::Version> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{username}/{box_name}/version/{box_version}",
-            username = vagrant_box.username,
-            box_name = vagrant_box.name,
-            box_version = box_version.version
-        );
-        self.api_call(url, RequestType::GET, None as Option<Version>) as Result<api::Version>
+        let url = self.box_endpoint(vagrant_box, &["version", box_version.version])?;
+        self.api_call(url, RequestType::Get, None as Option<Version>) as Result<api::Version>
     }
 
+    /// Deletes the version `box_version` of `vagrant_box` including all of its
+    /// providers and returns the deleted version as reported by the API, or
+    /// `None` if the API replied with no content.
     pub fn delete_version(
         &self,
         vagrant_box: &VagrantBox,
         box_version: &BoxVersion,
-    ) -> Result<api::Version> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{username}/{box_name}/version/{box_version}",
-            username = vagrant_box.username,
-            box_name = vagrant_box.name,
-            box_version = box_version.version
-        );
+    ) -> Result<Option<api::Version>> {
+        let url = self.box_endpoint(vagrant_box, &["version", box_version.version])?;
 
-        self.api_call(url, RequestType::DELETE, None as Option<Version>) as Result<api::Version>
+        self.api_call(url, RequestType::Delete, None as Option<Version>)
+            as Result<Option<api::Version>>
     }
 
-    /// this might not work
+    /// Updates the description of the version `box_version` of `vagrant_box`.
+    ///
+    /// This function is a wrapper around the [PUT
+    /// /api/v1/box/:username/:name/version/:version](https://www.vagrantup.com/docs/vagrant-cloud/api.html#update-a-version)
+    /// API endpoint.
     pub fn update_version(
         &self,
         vagrant_box: &VagrantBox,
         box_version: &BoxVersion,
     ) -> Result<api::Version> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{username}/{box_name}/version/{box_version}",
-            username = vagrant_box.username,
-            box_name = vagrant_box.name,
-            box_version = box_version.version
-        );
-
-        let payload = BoxVersion {
-            version: box_version.version,
-            description: box_version.description,
+        let url = self.box_endpoint(vagrant_box, &["version", box_version.version])?;
+
+        let ver = Version {
+            version: box_version,
         };
 
-        self.api_call(url, RequestType::PUT, Some(payload)) as Result<api::Version>
+        self.api_call(url, RequestType::Put, Some(ver)) as Result<api::Version>
     }
 
     pub fn release_version(
@@ -386,14 +586,25 @@ impl Client {
         vagrant_box: &VagrantBox,
         box_version: &BoxVersion,
     ) -> Result<api::Version> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{username}/{name}/version/{box_version}/release",
-            username = vagrant_box.username,
-            name = vagrant_box.name,
-            box_version = box_version.version
-        );
+        let url = self.box_endpoint(vagrant_box, &["version", box_version.version, "release"])?;
+
+        self.api_call(url, RequestType::Put, None as Option<Version>) as Result<api::Version>
+    }
+
+    /// Revokes the released version `box_version` of `vagrant_box`, so that
+    /// it is no longer offered to users.
+    ///
+    /// This function is a wrapper around the [PUT
+    /// /api/v1/box/:username/:name/version/:version/revoke](https://www.vagrantup.com/docs/vagrant-cloud/api.html#revoke-a-version)
+    /// API endpoint.
+    pub fn revoke_version(
+        &self,
+        vagrant_box: &VagrantBox,
+        box_version: &BoxVersion,
+    ) -> Result<api::Version> {
+        let url = self.box_endpoint(vagrant_box, &["version", box_version.version, "revoke"])?;
 
-        self.api_call(url, RequestType::PUT, None as Option<Version>) as Result<api::Version>
+        self.api_call(url, RequestType::Put, None as Option<Version>) as Result<api::Version>
     }
 
     /// Creates a new provider for the given `vagrant_box` and `box_version`.
@@ -409,18 +620,17 @@ impl Client {
         box_version: &BoxVersion,
         box_provider: &BoxProvider,
     ) -> Result<api::Provider> {
-        let url = format!(
-            "https://app.vagrantup.com/api/v1/box/{username}/{box_name}/version/{box_version}/providers",
-            username = vagrant_box.username,
-            box_name = vagrant_box.name,
-            box_version = box_version.version
-        );
+        let url = self.box_endpoint(vagrant_box, &["version", box_version.version, "providers"])?;
+
+        if let Some(warning) = box_provider.name.warning() {
+            warn!("Creating a provider with a {}", warning);
+        }
 
         let prov = Provider {
             provider: box_provider,
         };
 
-        self.api_call(url, RequestType::POST, Some(prov)) as Result<api::Provider>
+        self.api_call(url, RequestType::Post, Some(prov)) as Result<api::Provider>
     }
 
     pub fn update_provider(
@@ -429,23 +639,79 @@ impl Client {
         box_version: &BoxVersion,
         box_provider: &BoxProvider,
     ) -> Result<api::Provider> {
-        let url = format!(
-       "https://app.vagrantup.com/api/v1/box/{username}/{box_name}/version/{box_version}/provider/{provider}",
-            username = vagrant_box.username,
-            box_name = vagrant_box.name,
-            box_version = box_version.version,
-            provider = box_provider.name
-        );
+        let url = self.box_endpoint(
+            vagrant_box,
+            &[
+                "version",
+                box_version.version,
+                "provider",
+                box_provider.name.as_str(),
+            ],
+        )?;
 
         let prov = Provider {
             provider: box_provider,
         };
 
-        self.api_call(url, RequestType::PUT, Some(prov)) as Result<api::Provider>
+        self.api_call(url, RequestType::Put, Some(prov)) as Result<api::Provider>
+    }
+
+    /// Uploads the box file read from `file` (of `size` bytes, if known) for
+    /// the existing provider `provider_name` of `box_version`, so that the
+    /// box is hosted on Vagrant Cloud (or the registry behind the client).
+    ///
+    /// This function is a wrapper around the [GET
+    /// /api/v1/box/:username/:name/version/:version/provider/:provider/upload](https://www.vagrantup.com/docs/vagrant-cloud/api.html#upload-a-provider)
+    /// API endpoint followed by a `PUT` of the file to the returned upload
+    /// path.
+    pub fn upload_provider<R>(
+        &self,
+        vagrant_box: &VagrantBox,
+        box_version: &BoxVersion,
+        provider_name: &ProviderName,
+        file: R,
+        size: Option<u64>,
+    ) -> Result<()>
+    where
+        R: std::io::Read + Send + 'static,
+    {
+        let url = self.box_endpoint(
+            vagrant_box,
+            &[
+                "version",
+                box_version.version,
+                "provider",
+                provider_name.as_str(),
+                "upload",
+            ],
+        )?;
+        let target: api::UploadTarget =
+            self.api_call(url, RequestType::Get, None as Option<Provider>)?;
+
+        let body = match size {
+            Some(s) => reqwest::blocking::Body::sized(file, s),
+            None => reqwest::blocking::Body::new(file),
+        };
+        let entity = Entity {
+            username: Some(vagrant_box.username.clone()),
+            name: Some(vagrant_box.name.clone()),
+            version: Some(box_version.version.clone()),
+            provider: Some(provider_name.to_string()),
+        };
+        self.transfer(
+            &target.upload_path,
+            RequestType::Put,
+            &entity,
+            reqwest::header::HeaderMap::new(),
+            Some(body),
+        )
+        .map(|_| ())
     }
 
     /// Deletes the `box_provider` belonging to the `box_version` of
     /// `vagrant_box`, but does not touch the version or the box itself.
+    /// Returns the deleted provider as reported by the API, or `None` if the
+    /// API replied with no content.
     ///
     /// This function is a wrapper around the [DELETE
     /// /api/v1/box/:username/:name/version/:version/provider/:provider](https://www.vagrantup.com/docs/vagrant-cloud/api.html#delete-a-provider)
@@ -455,21 +721,25 @@ impl Client {
         vagrant_box: &VagrantBox,
         box_version: &BoxVersion,
         box_provider: &BoxProvider,
-    ) -> Result<api::Provider> {
-        let url = format!(
-       "https://app.vagrantup.com/api/v1/box/{username}/{box_name}/version/{box_version}/provider/{provider}",
-            username = vagrant_box.username,
-            box_name = vagrant_box.name,
-            box_version = box_version.version,
-            provider = box_provider.name
-        );
-
-        self.api_call(url, RequestType::DELETE, None as Option<Provider>) as Result<api::Provider>
+    ) -> Result<Option<api::Provider>> {
+        let url = self.box_endpoint(
+            vagrant_box,
+            &[
+                "version",
+                box_version.version,
+                "provider",
+                box_provider.name.as_str(),
+            ],
+        )?;
+
+        self.api_call(url, RequestType::Delete, None as Option<Provider>)
+            as Result<Option<api::Provider>>
     }
 
     /// Creates the provider `box_provider`, belonging to the version
     /// `box_version` of the box `vagrant_box`, creating all required elements
-    /// if they should not exist and releasing `box_version`.
+    /// if they should not exist and releasing `box_version` (unless it is
+    /// already released).
     ///
     /// This function is a high level wrapper around the low-level API endpoints
     /// like create_provider, create_box, etc. and can be used to directly
@@ -517,6 +787,11 @@ impl Client {
     ///
     /// This function will also delete versions for which it deleted the last
     /// provider if `delete_other_version=true`.
+    ///
+    /// Use [`plan_provider_present`](#method.plan_provider_present) to find out
+    /// which operations this function would perform without executing them.
+    /// If the client was configured via [`with_rollback`](#method.with_rollback),
+    /// all modifications are reverted if one of them fails.
     pub fn ensure_provider_present(
         &self,
         vagrant_box: &VagrantBox,
@@ -524,92 +799,75 @@ impl Client {
         box_provider: &BoxProvider,
         delete_other_version: bool,
     ) -> Result<api::VagrantBox> {
-        // does this box exist?
-        // no => create it and return the result of that operation
-        // yes => just return the result
-        let box_res = match self.read_box(&vagrant_box) {
-            Err(e) => match e.into_status() {
-                Some(st) => match st {
-                    reqwest::StatusCode::NOT_FOUND => self.create_box(&vagrant_box),
-                    _ => Err(e),
-                },
-                None => Err(e),
-            },
-            Ok(res) => Ok(res),
-        }?;
-
-        // update the box if it some settings aren't matching
-        let box_res = if vagrant_box != box_res {
-            self.update_box(vagrant_box)?
-        } else {
-            box_res
+        let options = plan::EnsureOptions {
+            delete_other_version,
+            ..Default::default()
         };
+        self.ensure_provider_present_with_options(vagrant_box, box_version, box_provider, &options)
+    }
 
-        let mut version_present = false;
-
-        // check all versions if their version matches the one we seek to add
-        //
-        // if the delete_other_version flag is set: delete providers with the
-        // same name as box_provider (and cleanup empty versions)
-        for ver in box_res.versions.iter() {
-            if &ver.version == box_version.version {
-                version_present = true;
-                continue;
-            }
-            if delete_other_version {
-                match &ver
-                    .providers
-                    .iter()
-                    .find(|prov| &prov.name == box_provider.name)
-                {
-                    None => (),
-                    Some(_) => {
-                        let version_to_delete = BoxVersion {
-                            version: &ver.version,
-                            description: ver
-                                .description_markdown
-                                .as_ref()
-                                .unwrap_or(&box_version.description),
-                        };
-                        self.delete_provider(vagrant_box, &version_to_delete, box_provider)?;
-                        // was that the only provider for this version?
-                        // => delete the version too
-                        if ver.providers.len() == 1 {
-                            self.delete_version(vagrant_box, &version_to_delete)?;
-                        }
-                    }
-                };
-            }
-        }
-
-        let matching_version = if !version_present {
-            self.create_version(vagrant_box, box_version)?
-        } else {
-            box_res
-                .versions
-                .into_iter()
-                .find(|ver| &ver.version == box_version.version)
-                .expect("A matching Version should have been found")
-        };
-
-        // redo the same for the provider
-        let matching_provider = match matching_version
-            .providers
-            .into_iter()
-            .find(|prov| &prov.name == box_provider.name)
-        {
-            None => self.create_provider(vagrant_box, box_version, box_provider),
-            Some(prov) => Ok(prov) as Result<api::Provider>,
-        }?;
-
-        // adjust the provider optionally
-        if box_provider != matching_provider {
-            self.update_provider(vagrant_box, box_version, box_provider)?;
-        }
-
-        self.release_version(vagrant_box, box_version)?;
+    /// Same as [`ensure_provider_present`](#method.ensure_provider_present),
+    /// but with more control via `options`, e.g. to upload a provider to a
+    /// version that stays unreleased:
+    ///
+    /// ```no_run
+    /// # use vagabond::*;
+    /// # use vagabond::plan::{EnsureOptions, ReleaseMode};
+    /// # let client = Client::new(Some("my_api_key_here".to_string()));
+    /// # let username = "my_vagrant_cloud_user_name".to_string();
+    /// # let box_name = "awesome_box".to_string();
+    /// # let ver = "1.2.3".to_string();
+    /// # let descr = "Release candidate".to_string();
+    /// # let url = "https://foo.bar.baz/path/to/my/awesome.box".to_string();
+    /// let options = EnsureOptions {
+    ///     release: ReleaseMode::Never,
+    ///     ..Default::default()
+    /// };
+    /// client.ensure_provider_present_with_options(
+    ///     &VagrantBox::new(&username, &box_name),
+    ///     &BoxVersion { version: &ver, description: &descr },
+    ///     &BoxProvider::new(&ProviderName::Libvirt, &url),
+    ///     &options,
+    /// ).unwrap();
+    /// ```
+    pub fn ensure_provider_present_with_options(
+        &self,
+        vagrant_box: &VagrantBox,
+        box_version: &BoxVersion,
+        box_provider: &BoxProvider,
+        options: &plan::EnsureOptions,
+    ) -> Result<api::VagrantBox> {
+        let plan = self.plan_provider_present_with_options(
+            vagrant_box,
+            box_version,
+            box_provider,
+            options,
+        )?;
+        self.apply(&plan)
+    }
 
-        self.read_box(vagrant_box)
+    /// Publishes multiple providers of the same version at once, e.g. the
+    /// `libvirt`, `virtualbox` and `vmware_desktop` builds of a CI run.
+    ///
+    /// This behaves like
+    /// [`ensure_provider_present_with_options`](#method.ensure_provider_present_with_options)
+    /// for each of `box_providers`, but `box_version` is only released once
+    /// all providers have been created or updated. Combine this with
+    /// [`with_rollback`](#method.with_rollback) to revert all modifications
+    /// if one of the providers cannot be published.
+    ///
+    /// Use [`plan_providers_present`](#method.plan_providers_present) to find
+    /// out which operations this function would perform without executing
+    /// them.
+    pub fn ensure_providers_present(
+        &self,
+        vagrant_box: &VagrantBox,
+        box_version: &BoxVersion,
+        box_providers: &[BoxProvider],
+        options: &plan::EnsureOptions,
+    ) -> Result<api::VagrantBox> {
+        let plan = self.plan_providers_present(vagrant_box, box_version, box_providers, options)?;
+        self.apply(&plan)
     }
 }
 
@@ -652,16 +910,35 @@ struct UpdateBoxPayload<'a, 'b, 'c, 'd> {
 /// virtualization environment, e.g. virtualbox or libvirt.
 pub struct BoxProvider<'a, 'b> {
     /// The name of the provider (e.g. libvirt, virtualbox)
-    pub name: &'a String,
+    pub name: &'a ProviderName,
     /// A valid URL to download this provider.
     ///
     /// If omitted, you must upload the Vagrant box image for this provider to
     /// Vagrant Cloud before the provider can be used.
     pub url: &'b String,
+    /// Type of `checksum` (e.g. `sha256`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_type: Option<&'b String>,
+    /// Checksum of the box file, which Vagrant verifies after downloading it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<&'b String>,
+}
+
+impl<'a, 'b> BoxProvider<'a, 'b> {
+    /// Create a new provider named `name` that can be downloaded from `url`,
+    /// without a checksum
+    pub fn new(name: &'a ProviderName, url: &'b String) -> BoxProvider<'a, 'b> {
+        BoxProvider {
+            name,
+            url,
+            checksum_type: None,
+            checksum: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, PartialEq)]
-///
+/// struct representing a version of a box on Vagrant Cloud
 pub struct BoxVersion<'a, 'b> {
     /// The version number of this version.
     pub version: &'a String,
@@ -686,29 +963,102 @@ pub struct VagrantBox<'a, 'b, 'c, 'd> {
 impl<'a, 'b, 'c, 'd> VagrantBox<'a, 'b, 'c, 'd> {
     pub fn new(username: &'a String, box_name: &'b String) -> VagrantBox<'a, 'b, 'c, 'd> {
         VagrantBox {
-            username: username,
+            username,
             name: box_name,
             short_description: None,
             description: None,
             is_private: None,
         }
     }
+
+    /// Returns the validated [`BoxTag`](tag/struct.BoxTag.html) of this box
+    pub fn box_tag(&self) -> Result<BoxTag> {
+        BoxTag::new(self.username.as_str(), self.name.as_str())
+    }
 }
 
-/// Compare first with second if second is Some(s), otherwise return false
-fn compare_strings(first: &String, second: &Option<String>) -> bool {
-    match second {
-        Some(val) => first == val,
-        None => false,
+/// Deserialize the body of a successful reply from `url` into `R`
+///
+/// Empty bodies are deserialized like a JSON `null`. If the body cannot be
+/// deserialized, then the returned `Error::UnexpectedResponse` contains the
+/// raw body and the location at which deserialization failed.
+fn decode_response<R>(url: &str, body: &[u8]) -> Result<R>
+where
+    for<'de> R: serde::Deserialize<'de>,
+{
+    let empty = body.iter().all(u8::is_ascii_whitespace);
+    let decode_error = |path: String, e: serde_json::Error| {
+        Error::UnexpectedResponse(Box::new(DecodeError {
+            url: url.to_string(),
+            path,
+            line: e.line(),
+            column: e.column(),
+            message: if empty {
+                format!("received no content, {}", e)
+            } else {
+                e.to_string()
+            },
+            body: body.to_vec(),
+        }))
+    };
+
+    if empty {
+        return serde_path_to_error::deserialize(serde_json::Value::Null)
+            .map_err(|e| decode_error(e.path().to_string(), e.into_inner()));
+    }
+
+    let mut de = serde_json::Deserializer::from_slice(body);
+    let res = serde_path_to_error::deserialize(&mut de)
+        .map_err(|e| decode_error(e.path().to_string(), e.into_inner()))?;
+    de.end().map_err(|e| decode_error(".".to_string(), e))?;
+
+    Ok(res)
+}
+
+/// Check the non-empty `body` received from `url` against the schema of `R`
+///
+/// Bodies that aren't valid JSON are left to `decode_response()` to report.
+fn check_schema<R: Schema>(url: &str, body: &[u8]) -> Result<()> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(());
+    }
+    let value: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+
+    let mut deviations = vec![];
+    R::check_schema(&value, "", &mut deviations);
+    if deviations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::SchemaViolation {
+            url: url.to_string(),
+            deviations,
+        })
     }
 }
 
+/// Compare first with second, treating a missing second like an empty string
+///
+/// The API reports empty descriptions and the URL of hosted providers as
+/// `null`.
+fn compare_strings(first: &String, second: &Option<String>) -> bool {
+    first == second.as_deref().unwrap_or_default()
+}
+
 fn cmp_vagrant_providers<'a, 'b>(
     box_provider: &BoxProvider<'a, 'b>,
     api_provider: &api::Provider,
 ) -> bool {
     (box_provider.name == &api_provider.name)
-        && compare_strings(&box_provider.url, &api_provider.original_url)
+        && compare_strings(box_provider.url, &api_provider.original_url)
+        && box_provider
+            .checksum
+            .is_none_or(|c| Some(c) == api_provider.checksum.as_ref())
+        && box_provider
+            .checksum_type
+            .is_none_or(|t| Some(t) == api_provider.checksum_type.as_ref())
 }
 
 fn cmp_vagrant_versions<'a, 'b>(
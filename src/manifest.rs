@@ -0,0 +1,323 @@
+//! # Manifest module
+//!
+//! A [`Manifest`](struct.Manifest.html) declaratively describes a set of
+//! boxes with their metadata, versions and providers. It can be written in
+//! TOML, YAML or JSON, e.g.:
+//!
+//! ```toml
+//! [[boxes]]
+//! username = "my_user"
+//! name = "awesome_box"
+//! short_description = "An awesome box"
+//!
+//! [[boxes.versions]]
+//! version = "1.2.3"
+//! description = "Release from today!"
+//!
+//! [[boxes.versions.providers]]
+//! name = "libvirt"
+//! url = "https://foo.bar.baz/1.2.3/libvirt.box"
+//! ```
+//!
+//! The [`Client`](../struct.Client.html) can then converge Vagrant Cloud to
+//! the state described by the manifest:
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::manifest::Manifest;
+//! let client = Client::new(Some("my_api_key_here".to_string()));
+//! let manifest = Manifest::from_file("boxes.toml").unwrap();
+//!
+//! // review the changes first
+//! for plan in client.plan_manifest(&manifest, true).unwrap() {
+//!     println!("{}", plan);
+//! }
+//! client.reconcile(&manifest, true).unwrap();
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use super::plan::{BoxSpec, Operation, Plan, ProviderSpec, VersionSpec};
+use super::{api, Client, Error, ProviderName, Result};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+/// A set of boxes and their desired state
+pub struct Manifest {
+    /// All boxes managed by this manifest
+    #[serde(default)]
+    pub boxes: Vec<BoxManifest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Desired state of a single box
+pub struct BoxManifest {
+    /// The username of the organization that owns this box
+    pub username: String,
+    /// The name of the box
+    pub name: String,
+    /// A short summary of the box
+    pub short_description: Option<String>,
+    /// A longer description of the box. Can be formatted with Markdown.
+    pub description: Option<String>,
+    /// Whether or not this box is private.
+    pub private: Option<bool>,
+    /// The versions of this box
+    #[serde(default)]
+    pub versions: Vec<VersionManifest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Desired state of a version of a box
+pub struct VersionManifest {
+    /// The version number of this version.
+    pub version: String,
+    /// A description for this version. Can be formatted with Markdown.
+    #[serde(default)]
+    pub description: String,
+    /// Should this version be released? Defaults to true.
+    #[serde(default = "default_released")]
+    pub released: bool,
+    /// The providers of this version
+    #[serde(default)]
+    pub providers: Vec<ProviderManifest>,
+}
+
+fn default_released() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Desired state of a provider of a version
+pub struct ProviderManifest {
+    /// The name of the provider (e.g. libvirt, virtualbox)
+    pub name: ProviderName,
+    /// A valid URL to download this provider.
+    pub url: String,
+    /// Type of `checksum` (e.g. `sha256`)
+    pub checksum_type: Option<String>,
+    /// Checksum of the box file
+    pub checksum: Option<String>,
+}
+
+impl Manifest {
+    /// Parse a manifest in the TOML format
+    pub fn from_toml_str(manifest: &str) -> Result<Manifest> {
+        toml::from_str::<Manifest>(manifest)
+            .map_err(|e| Error::InvalidManifest(e.to_string()))?
+            .validated()
+    }
+
+    /// Parse a manifest in the YAML format
+    pub fn from_yaml_str(manifest: &str) -> Result<Manifest> {
+        serde_yaml::from_str::<Manifest>(manifest)
+            .map_err(|e| Error::InvalidManifest(e.to_string()))?
+            .validated()
+    }
+
+    /// Parse a manifest in the JSON format
+    pub fn from_json_str(manifest: &str) -> Result<Manifest> {
+        serde_json::from_str::<Manifest>(manifest)
+            .map_err(|e| Error::InvalidManifest(e.to_string()))?
+            .validated()
+    }
+
+    /// Check that no version of a box and no provider of a version is listed
+    /// twice, as the plans would contain conflicting operations otherwise
+    ///
+    /// Manifests are validated when they are parsed and planned.
+    pub fn validate(&self) -> Result<()> {
+        for vagrant_box in &self.boxes {
+            let mut versions = HashSet::new();
+            for version in &vagrant_box.versions {
+                if !versions.insert(&version.version) {
+                    return Err(Error::InvalidManifest(format!(
+                        "version {} of {}/{} is listed twice",
+                        version.version, vagrant_box.username, vagrant_box.name
+                    )));
+                }
+                let mut providers = HashSet::new();
+                for provider in &version.providers {
+                    if !providers.insert(&provider.name) {
+                        return Err(Error::InvalidManifest(format!(
+                            "provider {} of version {} of {}/{} is listed twice",
+                            provider.name, version.version, vagrant_box.username, vagrant_box.name
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validated(self) -> Result<Manifest> {
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Read a manifest from the file at `path`, whose format is deduced from
+    /// its extension (`.toml`, `.yaml`/`.yml` or `.json`).
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Manifest> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Manifest::from_toml_str(&contents),
+            Some("yaml") | Some("yml") => Manifest::from_yaml_str(&contents),
+            Some("json") => Manifest::from_json_str(&contents),
+            _ => Err(Error::InvalidManifest(format!(
+                "cannot deduce the format of {} from its extension",
+                path.display()
+            ))),
+        }
+    }
+}
+
+impl BoxManifest {
+    fn box_spec(&self) -> BoxSpec {
+        BoxSpec {
+            username: self.username.clone(),
+            name: self.name.clone(),
+            short_description: self.short_description.clone(),
+            description: self.description.clone(),
+            is_private: self.private,
+        }
+    }
+
+    /// Compute the plan that converges `current`, the state of this box on
+    /// Vagrant Cloud (`None` if it doesn't exist), to this manifest.
+    ///
+    /// If `prune` is true, then versions and providers that are not part of
+    /// the manifest are deleted.
+    pub fn plan(&self, current: Option<&api::VagrantBox>, prune: bool) -> Plan {
+        let mut operations = vec![];
+        let vagrant_box = self.box_spec();
+
+        let versions = match current {
+            None => {
+                operations.push(Operation::CreateBox);
+                &[] as &[api::Version]
+            }
+            Some(b) => {
+                if &vagrant_box.as_vagrant_box() != *b {
+                    operations.push(Operation::UpdateBox {
+                        current: BoxSpec::from(b),
+                    });
+                }
+                &b.versions
+            }
+        };
+
+        if prune {
+            for ver in versions {
+                let version_spec = VersionSpec::from(ver);
+                match self.versions.iter().find(|v| v.version == ver.version) {
+                    None => operations.push(Operation::DeleteVersion(version_spec)),
+                    Some(desired) => ver
+                        .providers
+                        .iter()
+                        .filter(|prov| !desired.providers.iter().any(|p| p.name == prov.name))
+                        .for_each(|prov| {
+                            operations.push(Operation::DeleteProvider {
+                                version: version_spec.clone(),
+                                provider: ProviderSpec::from(prov),
+                            })
+                        }),
+                }
+            }
+        }
+
+        for desired in &self.versions {
+            let version_spec = VersionSpec {
+                version: desired.version.clone(),
+                description: desired.description.clone(),
+            };
+            let current_version = versions.iter().find(|v| v.version == desired.version);
+
+            match current_version {
+                None => operations.push(Operation::CreateVersion(version_spec.clone())),
+                Some(ver) if &version_spec.as_box_version() != *ver => {
+                    operations.push(Operation::UpdateVersion {
+                        version: version_spec.clone(),
+                        current: VersionSpec::from(ver),
+                    })
+                }
+                Some(_) => (),
+            };
+
+            for prov in &desired.providers {
+                let provider_spec = ProviderSpec {
+                    name: prov.name.clone(),
+                    url: prov.url.clone(),
+                    checksum_type: prov.checksum_type.clone(),
+                    checksum: prov.checksum.clone(),
+                };
+                match current_version
+                    .and_then(|ver| ver.providers.iter().find(|p| p.name == prov.name))
+                {
+                    None => operations.push(Operation::CreateProvider {
+                        version: version_spec.clone(),
+                        provider: provider_spec,
+                    }),
+                    Some(p) if &provider_spec.as_box_provider() != *p => {
+                        operations.push(Operation::UpdateProvider {
+                            version: version_spec.clone(),
+                            provider: provider_spec,
+                            current: ProviderSpec::from(p),
+                        })
+                    }
+                    Some(_) => (),
+                }
+            }
+
+            let active = current_version.is_some_and(|ver| ver.status == "active");
+            if desired.released && !active {
+                operations.push(Operation::ReleaseVersion(version_spec));
+            } else if !desired.released && active {
+                operations.push(Operation::RevokeVersion(version_spec));
+            }
+        }
+
+        Plan {
+            vagrant_box,
+            operations,
+        }
+    }
+}
+
+impl Client {
+    /// Compute the plans that converge the boxes on Vagrant Cloud to the
+    /// state described in `manifest`, one plan per box.
+    ///
+    /// If `prune` is true, then versions and providers of the boxes in the
+    /// manifest, that are not part of the manifest, will be deleted. Boxes
+    /// that are not part of the manifest are never touched. Invalid
+    /// manifests are rejected with an `Error::InvalidManifest`.
+    pub fn plan_manifest(&self, manifest: &Manifest, prune: bool) -> Result<Vec<Plan>> {
+        manifest.validate()?;
+        manifest
+            .boxes
+            .iter()
+            .map(|desired| {
+                let current = self.read_box_if_present(&desired.box_spec().as_vagrant_box())?;
+                Ok(desired.plan(current.as_ref(), prune))
+            })
+            .collect()
+    }
+
+    /// Converge the boxes on Vagrant Cloud to the state described in
+    /// `manifest` and return their resulting state.
+    ///
+    /// See [`plan_manifest`](#method.plan_manifest) for the meaning of
+    /// `prune`. All plans are computed before any of them is applied.
+    pub fn reconcile(&self, manifest: &Manifest, prune: bool) -> Result<Vec<api::VagrantBox>> {
+        self.plan_manifest(manifest, prune)?
+            .iter()
+            .map(|plan| self.apply(plan))
+            .collect()
+    }
+}
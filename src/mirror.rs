@@ -0,0 +1,334 @@
+//! # Mirror module
+//!
+//! A [`Mirror`](struct.Mirror.html) copies boxes from one registry to another,
+//! e.g. selected public boxes from Vagrant Cloud to an internal registry that
+//! implements the same API. Which boxes, versions and providers are copied is
+//! configured via a [`Selection`](struct.Selection.html):
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::mirror::{Mirror, Selection};
+//! let vagrant_cloud = Client::new(None as Option<String>);
+//! let registry = Client::new(Some("my_api_key_here".to_string()))
+//!     .with_base_url("https://boxes.example.com/api/v1");
+//!
+//! let selection = Selection::new()
+//!     .with_box("opensuse/Tumbleweed.x86_64".parse().unwrap())
+//!     .with_constraint(">= 1.0.20240101".parse().unwrap())
+//!     .with_provider(ProviderName::Libvirt);
+//!
+//! let mirror = Mirror::new(&vagrant_cloud, &registry).with_rehost(true);
+//! for sync in mirror.plan(&selection).unwrap() {
+//!     println!("{}", sync);
+//! }
+//! mirror.sync(&selection).unwrap();
+//! ```
+//!
+//! Only released versions are mirrored and only versions and providers that
+//! are missing on the destination are copied, existing ones are never
+//! modified or deleted. Mirroring is therefore idempotent and an interrupted
+//! mirror run can simply be repeated.
+//!
+//! By default the mirrored providers point to the download URL on the source.
+//! With [`with_rehost`](struct.Mirror.html#method.with_rehost) the box files
+//! are downloaded from the source and uploaded to the destination instead.
+
+use std::fmt;
+
+use super::constraint::VersionConstraint;
+use super::observer::Entity;
+use super::plan::{BoxSpec, Operation, Plan, ProviderSpec, VersionSpec};
+use super::{api, BoxTag, Client, ProviderName, RequestType, Result};
+
+#[derive(Debug, Clone, Default)]
+/// The boxes, versions and providers that are mirrored
+pub struct Selection {
+    boxes: Vec<BoxTag>,
+    constraint: Option<VersionConstraint>,
+    providers: Vec<ProviderName>,
+}
+
+impl Selection {
+    /// Create an empty selection
+    pub fn new() -> Selection {
+        Selection::default()
+    }
+
+    /// Mirror the box `tag`
+    pub fn with_box(mut self, tag: BoxTag) -> Selection {
+        self.boxes.push(tag);
+        self
+    }
+
+    /// Only mirror versions satisfying `constraint`, defaults to all
+    /// versions.
+    ///
+    /// Versions whose version number cannot be parsed are skipped if a
+    /// constraint is set.
+    pub fn with_constraint(mut self, constraint: VersionConstraint) -> Selection {
+        self.constraint = Some(constraint);
+        self
+    }
+
+    /// Mirror the provider `provider`. If no provider is added, then all
+    /// providers are mirrored.
+    pub fn with_provider(mut self, provider: ProviderName) -> Selection {
+        self.providers.push(provider);
+        self
+    }
+
+    /// The boxes that are mirrored
+    pub fn boxes(&self) -> &[BoxTag] {
+        &self.boxes
+    }
+
+    fn includes_version(&self, version: &api::Version) -> bool {
+        version.status == "active"
+            && self.constraint.as_ref().is_none_or(|c| {
+                version
+                    .version
+                    .parse()
+                    .map(|num| c.matches(&num))
+                    .unwrap_or(false)
+            })
+    }
+
+    fn includes_provider(&self, provider: &api::Provider) -> bool {
+        self.providers.is_empty() || self.providers.contains(&provider.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A box file that is copied from the source to the destination
+pub struct Upload {
+    /// The version to which the provider belongs
+    pub version: VersionSpec,
+    /// The name of the provider
+    pub provider: ProviderName,
+    /// URL from which the box file is downloaded
+    pub download_url: String,
+}
+
+impl fmt::Display for Upload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "upload provider {} of version {} from {}",
+            self.provider, self.version.version, self.download_url
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Everything that has to be done to mirror a single box
+pub struct BoxSync {
+    /// The operations on the destination box, all releases come last
+    pub plan: Plan,
+    /// The box files to copy, which are uploaded before any version is
+    /// released (always empty unless re-hosting)
+    pub uploads: Vec<Upload>,
+}
+
+impl BoxSync {
+    /// Returns true if the box is already mirrored completely
+    pub fn is_empty(&self) -> bool {
+        self.plan.is_empty() && self.uploads.is_empty()
+    }
+}
+
+impl fmt::Display for BoxSync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.plan)?;
+        for upload in &self.uploads {
+            write!(f, "\n  - {}", upload)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Copies boxes from the `source` to the `destination` client
+pub struct Mirror<'a> {
+    source: &'a Client,
+    destination: &'a Client,
+    rehost: bool,
+}
+
+impl<'a> Mirror<'a> {
+    /// Create a mirror from `source` to `destination` that doesn't re-host
+    /// box files.
+    pub fn new(source: &'a Client, destination: &'a Client) -> Mirror<'a> {
+        Mirror {
+            source,
+            destination,
+            rehost: false,
+        }
+    }
+
+    /// Download the box files from the source and upload them to the
+    /// destination, defaults to `false`.
+    pub fn with_rehost(mut self, rehost: bool) -> Mirror<'a> {
+        self.rehost = rehost;
+        self
+    }
+
+    /// Computes what has to be done to mirror the box `tag`, without
+    /// modifying anything.
+    pub fn plan_box(&self, tag: &BoxTag, selection: &Selection) -> Result<BoxSync> {
+        let source = self.source.read_box(&tag.vagrant_box())?;
+        let destination = self.destination.read_box_if_present(&tag.vagrant_box())?;
+
+        let mut operations = vec![];
+        let mut releases = vec![];
+        let mut uploads = vec![];
+        let dest_versions: &[api::Version] = match &destination {
+            None => {
+                operations.push(Operation::CreateBox);
+                &[]
+            }
+            Some(d) => &d.versions,
+        };
+
+        for src_ver in source
+            .versions
+            .iter()
+            .filter(|v| selection.includes_version(v))
+        {
+            let providers: Vec<&api::Provider> = src_ver
+                .providers
+                .iter()
+                .filter(|p| selection.includes_provider(p))
+                .collect();
+            if providers.is_empty() {
+                continue;
+            }
+
+            let version = VersionSpec::from(src_ver);
+            let dest_ver = dest_versions.iter().find(|v| v.version == src_ver.version);
+            match dest_ver {
+                // deliberately revoked on the destination
+                Some(d) if d.status == "revoked" => continue,
+                Some(d) if d.status == "active" => (),
+                Some(_) => releases.push(Operation::ReleaseVersion(version.clone())),
+                None => {
+                    operations.push(Operation::CreateVersion(version.clone()));
+                    releases.push(Operation::ReleaseVersion(version.clone()));
+                }
+            }
+
+            for src_prov in providers {
+                let dest_prov =
+                    dest_ver.and_then(|d| d.providers.iter().find(|p| p.name == src_prov.name));
+                if dest_prov.is_none() {
+                    let mut provider = ProviderSpec::from(src_prov);
+                    if self.rehost {
+                        provider.url = src_prov.download_url.clone();
+                    }
+                    operations.push(Operation::CreateProvider {
+                        version: version.clone(),
+                        provider,
+                    });
+                }
+                if self.rehost && dest_prov.is_none_or(|p| !p.hosted) {
+                    uploads.push(Upload {
+                        version: version.clone(),
+                        provider: src_prov.name.clone(),
+                        download_url: src_prov.download_url.clone(),
+                    });
+                }
+            }
+        }
+
+        operations.extend(releases);
+        Ok(BoxSync {
+            plan: Plan {
+                vagrant_box: BoxSpec::from(&source),
+                operations,
+            },
+            uploads,
+        })
+    }
+
+    /// Computes what has to be done to mirror all boxes of `selection`,
+    /// without modifying anything.
+    pub fn plan(&self, selection: &Selection) -> Result<Vec<BoxSync>> {
+        selection
+            .boxes
+            .iter()
+            .map(|tag| self.plan_box(tag, selection))
+            .collect()
+    }
+
+    /// Perform `sync` on the destination and return the mirrored box.
+    pub fn execute(&self, sync: &BoxSync) -> Result<api::VagrantBox> {
+        let vagrant_box = sync.plan.vagrant_box.as_vagrant_box();
+        let first_release = sync
+            .plan
+            .operations
+            .iter()
+            .position(|op| matches!(op, Operation::ReleaseVersion(_)))
+            .unwrap_or(sync.plan.operations.len());
+        let (creations, releases) = sync.plan.operations.split_at(first_release);
+
+        let in_box = |operations: &[Operation]| Plan {
+            vagrant_box: sync.plan.vagrant_box.clone(),
+            operations: operations.to_vec(),
+        };
+        if !creations.is_empty() {
+            self.destination.apply(&in_box(creations))?;
+        }
+        for upload in &sync.uploads {
+            self.copy_box_file(&sync.plan.vagrant_box, upload)?;
+        }
+        if !releases.is_empty() {
+            self.destination.apply(&in_box(releases))?;
+        }
+        self.destination.read_box(&vagrant_box)
+    }
+
+    /// Mirror the box `tag` and return the mirrored box.
+    pub fn sync_box(&self, tag: &BoxTag, selection: &Selection) -> Result<api::VagrantBox> {
+        let sync = self.plan_box(tag, selection)?;
+        self.execute(&sync)
+    }
+
+    /// Mirror all boxes of `selection` and return the mirrored boxes.
+    ///
+    /// Boxes are mirrored concurrently if the destination client was
+    /// configured via
+    /// [`with_max_concurrency`](../struct.Client.html#method.with_max_concurrency).
+    /// All boxes are processed even if some of them fail, the errors are
+    /// returned as an `Error::Aggregate`.
+    pub fn sync(&self, selection: &Selection) -> Result<Vec<api::VagrantBox>> {
+        self.destination
+            .execute_concurrently(selection.boxes.iter().collect(), |_, tag| {
+                self.sync_box(tag, selection)
+            })
+    }
+
+    /// Download the box file of `upload` from the source and stream it to
+    /// the destination
+    fn copy_box_file(&self, vagrant_box: &BoxSpec, upload: &Upload) -> Result<()> {
+        let entity = Entity {
+            username: Some(vagrant_box.username.clone()),
+            name: Some(vagrant_box.name.clone()),
+            version: Some(upload.version.version.clone()),
+            provider: Some(upload.provider.to_string()),
+        };
+        let download = self.source.transfer(
+            &upload.download_url,
+            RequestType::Get,
+            &entity,
+            reqwest::header::HeaderMap::new(),
+            None,
+        )?;
+        let size = download.content_length();
+        self.destination.upload_provider(
+            &vagrant_box.as_vagrant_box(),
+            &upload.version.as_box_version(),
+            &upload.provider,
+            download,
+            size,
+        )
+    }
+}
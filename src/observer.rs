@@ -0,0 +1,311 @@
+//! # Observer module
+//!
+//! [`Observer`](trait.Observer.html)s are notified before and after every
+//! request to the Vagrant Cloud API and every operation of a
+//! [`Plan`](../plan/struct.Plan.html) (and thus every step of
+//! `ensure_provider_present`). They can be used to feed the modifications
+//! performed by vagabond into an audit system or a notification service:
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::observer::{Event, LogObserver, Observer, Outcome};
+//! struct Notifier;
+//!
+//! impl Observer for Notifier {
+//!     fn after(&self, event: &Event, outcome: &Outcome) {
+//!         if event.is_mutation() {
+//!             match outcome.error {
+//!                 None => println!("{} took {:?}", event, outcome.duration),
+//!                 Some(e) => println!("{} failed: {}", event, e),
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! let client = Client::new(Some("my_api_key_here".to_string()))
+//!     .with_observer(LogObserver)
+//!     .with_observer(Notifier);
+//! ```
+//!
+//! Events never contain the API token.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::plan::{BoxSpec, Operation};
+use super::{Client, Error, Result};
+
+/// Maximum length of the payload and response summaries of an API call
+const PAYLOAD_SUMMARY_LEN: usize = 256;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Identifiers of the entity on Vagrant Cloud that an API call refers to
+pub struct Entity {
+    /// The name of the user or organization owning the box
+    pub username: Option<String>,
+    /// The name of the box
+    pub name: Option<String>,
+    /// The version number
+    pub version: Option<String>,
+    /// The name of the provider
+    pub provider: Option<String>,
+}
+
+impl Entity {
+    /// Extract the entity from the `url` of a request to the API at
+    /// `base_url`, or from its JSON `payload` when creating a box
+    pub(crate) fn from_request(
+        base_url: &str,
+        url: &reqwest::Url,
+        payload: Option<&str>,
+    ) -> Entity {
+        let base_len = reqwest::Url::parse(base_url)
+            .ok()
+            .and_then(|base| {
+                base.path_segments()
+                    .map(|s| s.filter(|s| !s.is_empty()).count())
+            })
+            .unwrap_or(0);
+        let segments: Vec<String> = url
+            .path_segments()
+            .map(|segments| {
+                segments
+                    .skip(base_len)
+                    .map(|seg| {
+                        percent_encoding::percent_decode_str(seg)
+                            .decode_utf8_lossy()
+                            .into_owned()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let segment = |i: usize| segments.get(i).cloned();
+
+        match segments.first().map(String::as_str) {
+            Some("box") => Entity {
+                username: segment(1),
+                name: segment(2),
+                version: segment(4).filter(|_| segments[3] == "version"),
+                provider: segment(6).filter(|_| segments[5] == "provider"),
+            },
+            Some("boxes") => {
+                let payload: serde_json::Value = payload
+                    .and_then(|p| serde_json::from_str(p).ok())
+                    .unwrap_or_default();
+                let vagrant_box = payload.get("box").unwrap_or(&payload);
+                let field = |name: &str| {
+                    vagrant_box
+                        .get(name)
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string)
+                };
+                Entity {
+                    username: field("username"),
+                    name: field("name"),
+                    ..Default::default()
+                }
+            }
+            _ => Entity::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Something that vagabond is about to do or has done
+pub enum Event<'a> {
+    /// A request to the Vagrant Cloud API
+    ApiCall {
+        /// The HTTP method of the request, e.g. `PUT`
+        method: &'a str,
+        /// The URL of the requested entity
+        url: &'a str,
+        /// The entity that is requested
+        entity: &'a Entity,
+        /// The beginning of the JSON payload of the request, if any
+        payload: Option<&'a str>,
+        /// Whether a box file is transferred from or to a URL outside of the
+        /// API (e.g. a download URL or an upload path)
+        transfer: bool,
+    },
+    /// An operation of a plan, which consists of one or more API calls
+    Operation {
+        /// The box that is modified
+        vagrant_box: &'a BoxSpec,
+        /// The performed operation
+        operation: &'a Operation,
+    },
+}
+
+impl Event<'_> {
+    /// Returns true if this event modifies data on Vagrant Cloud
+    pub fn is_mutation(&self) -> bool {
+        match self {
+            Event::ApiCall { method, .. } => *method != "GET",
+            Event::Operation { .. } => true,
+        }
+    }
+}
+
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::ApiCall { method, url, .. } => write!(f, "{} {}", method, url),
+            Event::Operation {
+                vagrant_box,
+                operation,
+            } => write!(
+                f,
+                "{}/{}: {}",
+                vagrant_box.username, vagrant_box.name, operation
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The result of an [`Event`](enum.Event.html)
+pub struct Outcome<'a> {
+    /// The error that occurred, `None` on success
+    pub error: Option<&'a Error>,
+    /// How long it took
+    pub duration: Duration,
+    /// The HTTP status of the reply, if a reply was received
+    pub status: Option<reqwest::StatusCode>,
+    /// The beginning of the reply, if a reply was received
+    pub response: Option<&'a str>,
+}
+
+impl Outcome<'_> {
+    /// Returns true if the event finished successfully
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Receives notifications about everything the
+/// [`Client`](../struct.Client.html) does
+///
+/// Both methods do nothing by default, so that implementors only need to
+/// override the ones they are interested in.
+pub trait Observer: Send + Sync {
+    /// Called before `event` happens
+    fn before(&self, _event: &Event) {}
+
+    /// Called after `event` happened
+    fn after(&self, _event: &Event, _outcome: &Outcome) {}
+}
+
+impl<T: Observer + ?Sized> Observer for Arc<T> {
+    fn before(&self, event: &Event) {
+        (**self).before(event)
+    }
+
+    fn after(&self, event: &Event, outcome: &Outcome) {
+        (**self).after(event, outcome)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Observer that reports all modifications via the `log` crate
+///
+/// Successful modifications are logged at the level `info`, failed ones at
+/// the level `warn` and read-only requests only at the level `debug`.
+pub struct LogObserver;
+
+impl Observer for LogObserver {
+    fn before(&self, event: &Event) {
+        debug!("Starting: {}", event);
+    }
+
+    fn after(&self, event: &Event, outcome: &Outcome) {
+        match outcome.error {
+            Some(e) => warn!("Failed after {:?}: {}: {}", outcome.duration, event, e),
+            None if event.is_mutation() => {
+                info!("Finished in {:?}: {}", outcome.duration, event)
+            }
+            None => debug!("Finished in {:?}: {}", outcome.duration, event),
+        }
+    }
+}
+
+#[derive(Default)]
+/// The observers registered with a client
+pub(crate) struct Observers(Vec<Box<dyn Observer>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+/// Results of observed actions, from which details for the
+/// [`Outcome`](struct.Outcome.html) can be extracted
+pub(crate) trait Observed {
+    /// The HTTP status of the reply
+    fn status(&self) -> Option<reqwest::StatusCode> {
+        None
+    }
+
+    /// The body of the reply
+    fn body(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl Observed for () {}
+
+/// Truncate `payload` to at most `PAYLOAD_SUMMARY_LEN` bytes
+pub(crate) fn summarize(payload: &str) -> &str {
+    if payload.len() <= PAYLOAD_SUMMARY_LEN {
+        return payload;
+    }
+    let mut end = PAYLOAD_SUMMARY_LEN;
+    while !payload.is_char_boundary(end) {
+        end -= 1;
+    }
+    &payload[..end]
+}
+
+impl Client {
+    /// Register `observer`, which is notified about all API calls and
+    /// operations of this client in addition to the already registered ones.
+    pub fn with_observer<O: Observer + 'static>(mut self, observer: O) -> Client {
+        self.observers.0.push(Box::new(observer));
+        self
+    }
+
+    /// Notify all observers about `event`, which is performed by `action`
+    pub(crate) fn observe<T, F>(&self, event: &Event, action: F) -> Result<T>
+    where
+        T: Observed,
+        F: FnOnce() -> Result<T>,
+    {
+        self.observers.0.iter().for_each(|o| o.before(event));
+        let start = Instant::now();
+        let res = action();
+        let duration = start.elapsed();
+        let outcome = match &res {
+            Ok(reply) => Outcome {
+                error: None,
+                duration,
+                status: reply.status(),
+                response: reply
+                    .body()
+                    .and_then(|body| std::str::from_utf8(body).ok())
+                    .map(summarize),
+            },
+            Err(e) => Outcome {
+                error: Some(e),
+                duration,
+                status: e.into_status(),
+                response: e.details().map(|d| summarize(&d.body)),
+            },
+        };
+        self.observers
+            .0
+            .iter()
+            .for_each(|o| o.after(event, &outcome));
+        res
+    }
+}
@@ -0,0 +1,298 @@
+//! # Packager module
+//!
+//! A [`Packager`](struct.Packager.html) builds a `.box` file from disk images,
+//! generating its `metadata.json` and optionally adding a `Vagrantfile` and an
+//! `info.json`:
+//!
+//! ```no_run
+//! # use vagabond::packager::Packager;
+//! let mut info = serde_json::Map::new();
+//! info.insert("author".to_string(), "my_user".into());
+//!
+//! Packager::libvirt("output/awesome_box.qcow2", 40)
+//!     .with_vagrantfile("Vagrant.configure(\"2\") do |config|\nend\n")
+//!     .with_info(info)
+//!     .write_to_file("output/awesome_box.libvirt.box")
+//!     .unwrap();
+//! ```
+//!
+//! The archive is streamed to its destination, disk images are never read
+//! into memory as a whole. Packaging is deterministic: the same inputs always
+//! result in the same `.box` file, as the files are stored in a fixed order
+//! (`metadata.json`, `info.json`, `Vagrantfile`, then all other files sorted
+//! by name) with the same timestamp, owner and permissions.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use super::api::ExtraFields;
+use super::boxfile::{Compression, Metadata};
+use super::{Error, ProviderName, Result};
+
+/// Names of the files that the packager generates itself
+const GENERATED_FILES: [&str; 3] = ["metadata.json", "info.json", "Vagrantfile"];
+
+/// Reads exactly the limit of `file`, fails if the file ends earlier
+///
+/// tar pads short entries with zeros, so a file that shrinks while it is
+/// archived would otherwise silently end up corrupted in the archive.
+struct ExactReader<'p> {
+    file: io::Take<File>,
+    path: &'p Path,
+}
+
+impl Read for ExactReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.file.read(buf)?;
+        if len == 0 && self.file.limit() > 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{} shrank by {} bytes while it was archived",
+                    self.path.display(),
+                    self.file.limit()
+                ),
+            ));
+        }
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Builder for a `.box` file
+pub struct Packager {
+    metadata: Metadata,
+    /// (name in the archive, path on disk), sorted by name on write
+    files: Vec<(String, PathBuf)>,
+    vagrantfile: Option<String>,
+    info: Option<ExtraFields>,
+    compression: Compression,
+    mtime: u64,
+}
+
+impl Packager {
+    /// Create a packager for a box with the given `metadata` and no files.
+    ///
+    /// Files are stored with gzip compression and the modification time
+    /// `0` (i.e. the epoch) by default.
+    pub fn new(metadata: Metadata) -> Packager {
+        Packager {
+            metadata,
+            files: vec![],
+            vagrantfile: None,
+            info: None,
+            compression: Compression::Gzip,
+            mtime: 0,
+        }
+    }
+
+    /// Create a packager for a `libvirt` box consisting of the qcow2 image
+    /// at `image` with a disk of `virtual_size` GB, which is stored as
+    /// `box.img`, as expected by vagrant-libvirt.
+    pub fn libvirt<P: AsRef<Path>>(image: P, virtual_size: u64) -> Packager {
+        Packager::new(Metadata {
+            provider: ProviderName::Libvirt,
+            format: Some("qcow2".to_string()),
+            virtual_size: Some(virtual_size),
+            ..Default::default()
+        })
+        .with_file_as("box.img", image)
+    }
+
+    /// Create a packager for a `virtualbox` box consisting of the appliance
+    /// description at `ovf`, which is stored as `box.ovf`, and the `disks`
+    /// that it references, which are stored under their file names.
+    pub fn virtualbox<P, D>(ovf: P, disks: &[D]) -> Packager
+    where
+        P: AsRef<Path>,
+        D: AsRef<Path>,
+    {
+        disks.iter().fold(
+            Packager::new(Metadata {
+                provider: ProviderName::VirtualBox,
+                ..Default::default()
+            })
+            .with_file_as("box.ovf", ovf),
+            |packager, disk| packager.with_file(disk),
+        )
+    }
+
+    /// Add the file at `path` under its file name.
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> Packager {
+        let path = path.as_ref();
+        // invalid names are reported by write()
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.files.push((name, path.to_path_buf()));
+        self
+    }
+
+    /// Add the file at `path` under the name `name`.
+    pub fn with_file_as<S: Into<String>, P: AsRef<Path>>(mut self, name: S, path: P) -> Packager {
+        self.files.push((name.into(), path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Bundle `vagrantfile` as the box's `Vagrantfile`.
+    pub fn with_vagrantfile<S: Into<String>>(mut self, vagrantfile: S) -> Packager {
+        self.vagrantfile = Some(vagrantfile.into());
+        self
+    }
+
+    /// Bundle `info` as the box's `info.json`.
+    pub fn with_info(mut self, info: ExtraFields) -> Packager {
+        self.info = Some(info);
+        self
+    }
+
+    /// Compress the archive with `compression`, defaults to
+    /// `Compression::Gzip`.
+    pub fn with_compression(mut self, compression: Compression) -> Packager {
+        self.compression = compression;
+        self
+    }
+
+    /// Store all files with the modification time `mtime` (in seconds since
+    /// the epoch), defaults to `0`.
+    pub fn with_mtime(mut self, mtime: u64) -> Packager {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Check the names of all added files and return them sorted by name
+    fn sorted_files(&self) -> Result<Vec<&(String, PathBuf)>> {
+        let mut files: Vec<&(String, PathBuf)> = self.files.iter().collect();
+        files.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+
+        for (i, (name, path)) in files.iter().enumerate() {
+            if name.is_empty() {
+                return Err(Error::InvalidBoxFile(format!(
+                    "'{}' has no file name",
+                    path.display()
+                )));
+            }
+            let is_plain = Path::new(name)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+            if !is_plain {
+                return Err(Error::InvalidBoxFile(format!(
+                    "'{}' is not a valid name for a file in a box",
+                    name
+                )));
+            }
+            if GENERATED_FILES.contains(&name.as_str()) {
+                return Err(Error::InvalidBoxFile(format!(
+                    "{} is generated and cannot be added as a file",
+                    name
+                )));
+            }
+            if i > 0 && files[i - 1].0 == *name {
+                return Err(Error::InvalidBoxFile(format!(
+                    "{} was added more than once",
+                    name
+                )));
+            }
+        }
+        Ok(files)
+    }
+
+    /// A header for a regular file of `size` bytes
+    fn header(&self, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(self.mtime);
+        header
+    }
+
+    /// Write the uncompressed tar archive to `out` and return `out`
+    fn write_tar<W: Write>(&self, out: W) -> Result<W> {
+        let files = self.sorted_files()?;
+        let mut builder = tar::Builder::new(out);
+
+        let mut generated = vec![(
+            GENERATED_FILES[0],
+            serde_json::to_vec(&self.metadata).map_err(|e| {
+                Error::InternalError(format!("cannot serialize metadata.json: {}", e))
+            })?,
+        )];
+        if let Some(info) = &self.info {
+            generated.push((
+                GENERATED_FILES[1],
+                serde_json::to_vec_pretty(info).map_err(|e| {
+                    Error::InternalError(format!("cannot serialize info.json: {}", e))
+                })?,
+            ));
+        }
+        if let Some(vagrantfile) = &self.vagrantfile {
+            generated.push((GENERATED_FILES[2], vagrantfile.clone().into_bytes()));
+        }
+        for (name, contents) in generated {
+            let mut header = self.header(contents.len() as u64);
+            builder.append_data(&mut header, name, contents.as_slice())?;
+        }
+
+        for (name, path) in files {
+            let file = File::open(path)?;
+            let size = file.metadata()?.len();
+            let mut header = self.header(size);
+            // guard against the file changing while it is archived
+            let reader = ExactReader {
+                file: file.take(size),
+                path,
+            };
+            builder.append_data(&mut header, name, reader)?;
+        }
+
+        Ok(builder.into_inner()?)
+    }
+
+    /// Write the `.box` file to `out`.
+    pub fn write<W: Write>(&self, out: W) -> Result<()> {
+        match self.compression {
+            Compression::None => self.write_tar(out)?.flush()?,
+            Compression::Gzip => {
+                let encoder = flate2::GzBuilder::new()
+                    .mtime(self.mtime as u32)
+                    .write(out, flate2::Compression::default());
+                self.write_tar(encoder)?.finish()?.flush()?
+            }
+            Compression::Bzip2 => {
+                let encoder = bzip2::write::BzEncoder::new(out, bzip2::Compression::default());
+                self.write_tar(encoder)?.finish()?.flush()?
+            }
+            Compression::Xz => {
+                let encoder = xz2::write::XzEncoder::new(out, 6);
+                self.write_tar(encoder)?.finish()?.flush()?
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the `.box` file to `path`.
+    ///
+    /// The archive is written to a temporary file next to `path` first,
+    /// which is renamed to `path` once it is complete, so that `path` never
+    /// contains a partial box.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+
+        let res = File::create(&partial)
+            .map_err(Error::from)
+            .and_then(|file| self.write(BufWriter::new(file)))
+            .and_then(|()| fs::rename(&partial, path).map_err(Error::from));
+        if res.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        res
+    }
+}
@@ -0,0 +1,224 @@
+//! # Parallel execution module
+//!
+//! Many API calls are independent of each other, e.g. reading multiple boxes
+//! or deleting a provider from multiple versions. The
+//! [`Client`](../struct.Client.html) can perform such calls concurrently with
+//! up to [`with_max_concurrency`](../struct.Client.html#method.with_max_concurrency)
+//! calls in flight at once, while all calls (including the sequential ones)
+//! are spaced according to
+//! [`with_rate_limit`](../struct.Client.html#method.with_rate_limit):
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use vagabond::*;
+//! let client = Client::new(Some("my_api_key_here".to_string()))
+//!     .with_max_concurrency(8)
+//!     .with_rate_limit(10, Duration::from_secs(1));
+//!
+//! let user = "my_user".to_string();
+//! let names: Vec<String> = (1..=20).map(|i| format!("box_{}", i)).collect();
+//! let boxes: Vec<VagrantBox> = names.iter().map(|n| VagrantBox::new(&user, n)).collect();
+//!
+//! match client.read_boxes(&boxes) {
+//!     Ok(boxes) => boxes.iter().for_each(|b| println!("{}", b.name)),
+//!     Err(Error::Aggregate(errors)) => errors.iter().for_each(|e| println!("{}", e)),
+//!     Err(e) => println!("{}", e),
+//! }
+//! ```
+//!
+//! When applying a [`Plan`](../plan/struct.Plan.html), consecutive deletions
+//! of providers and versions are grouped by version and the groups are
+//! executed concurrently, everything else is executed in order. Plans applied
+//! with [`with_rollback`](../struct.Client.html#method.with_rollback) are
+//! always executed sequentially.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::plan::Operation;
+use super::{api, Client, Error, Result, VagrantBox};
+
+#[derive(Debug)]
+/// Spaces requests evenly so that at most a fixed number of them is started
+/// per time interval
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    /// earliest time at which the next request may be started
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(max_requests: u32, per: Duration) -> RateLimiter {
+        RateLimiter {
+            interval: per / max_requests.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until the next request may be started
+    pub(crate) fn wait(&self) {
+        let mut next = match self.next.lock() {
+            Ok(n) => n,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let now = Instant::now();
+        if *next > now {
+            thread::sleep(*next - now);
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+
+    /// Start no further request within the next `duration`
+    pub(crate) fn pause(&self, duration: Duration) {
+        let mut next = match self.next.lock() {
+            Ok(n) => n,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *next = (*next).max(Instant::now() + duration);
+    }
+}
+
+impl Client {
+    /// Perform up to `max_concurrency` independent API calls at once,
+    /// defaults to `1` (i.e. everything is performed sequentially).
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Client {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Start at most `max_requests` API calls per `per`, defaults to no
+    /// limit.
+    ///
+    /// If a call is rejected with an `Error::RateLimited` that carries a
+    /// `retry_after` duration, then no further call is started before it has
+    /// passed. The rejected call itself is not retried.
+    pub fn with_rate_limit(mut self, max_requests: u32, per: Duration) -> Client {
+        self.rate_limiter = Some(RateLimiter::new(max_requests, per));
+        self
+    }
+
+    /// Pause the rate limiter if `error` asks to retry later
+    pub(crate) fn back_off(&self, error: &Error) {
+        if let (
+            Some(limiter),
+            Error::RateLimited {
+                retry_after: Some(retry_after),
+                ..
+            },
+        ) = (&self.rate_limiter, error)
+        {
+            limiter.pause(*retry_after);
+        }
+    }
+
+    /// Call `task` for each of `items` with up to
+    /// [`max_concurrency`](#method.with_max_concurrency) calls running at
+    /// once and return their results in the order of `items`.
+    ///
+    /// All items are processed, even if some of them fail. If any of them
+    /// failed, an `Error::Aggregate` with all errors (in the order of
+    /// `items`) is returned. If `task` panics, the panic is propagated once
+    /// all calls have finished.
+    pub fn execute_concurrently<T, R, F>(&self, items: Vec<T>, task: F) -> Result<Vec<R>>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(&Client, T) -> Result<R> + Sync,
+    {
+        let count = items.len();
+        let queue = Mutex::new(items.into_iter().enumerate());
+        let results: Mutex<Vec<Option<Result<R>>>> = Mutex::new((0..count).map(|_| None).collect());
+
+        thread::scope(|scope| {
+            for _ in 0..self.max_concurrency.min(count) {
+                scope.spawn(|| loop {
+                    let next = queue.lock().map(|mut q| q.next()).unwrap_or(None);
+                    let (i, item) = match next {
+                        Some(n) => n,
+                        None => break,
+                    };
+                    let res = task(self, item);
+                    if let Ok(mut results) = results.lock() {
+                        results[i] = Some(res);
+                    }
+                });
+            }
+        });
+
+        // thread::scope() propagates panics of the workers, so all items have
+        // been processed when it returns
+        let results = match results.into_inner() {
+            Ok(r) => r,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut values = Vec::with_capacity(count);
+        let mut errors = vec![];
+        for res in results.into_iter().flatten() {
+            match res {
+                Ok(v) => values.push(v),
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok(values)
+        } else {
+            Err(Error::Aggregate(errors))
+        }
+    }
+
+    /// Read all `boxes` concurrently, see
+    /// [`execute_concurrently`](#method.execute_concurrently).
+    pub fn read_boxes(&self, boxes: &[VagrantBox]) -> Result<Vec<api::VagrantBox>> {
+        self.execute_concurrently(boxes.iter().collect(), |client, vagrant_box| {
+            client.read_box(vagrant_box)
+        })
+    }
+
+    /// Perform `operations` on `vagrant_box`, running independent operations
+    /// concurrently.
+    pub(crate) fn apply_operations_concurrently(
+        &self,
+        vagrant_box: &VagrantBox,
+        operations: &[Operation],
+    ) -> Result<()> {
+        let mut i = 0;
+        while i < operations.len() {
+            let stage_len = operations[i..]
+                .iter()
+                .take_while(|op| deleted_version(op).is_some())
+                .count();
+            if stage_len == 0 {
+                self.apply_operation(vagrant_box, &operations[i])?;
+                i += 1;
+                continue;
+            }
+
+            // deletions in different versions are independent of each other
+            let mut by_version: BTreeMap<&str, Vec<&Operation>> = BTreeMap::new();
+            for op in &operations[i..i + stage_len] {
+                if let Some(version) = deleted_version(op) {
+                    by_version.entry(version).or_default().push(op);
+                }
+            }
+            self.execute_concurrently(by_version.into_values().collect(), |client, ops| {
+                ops.iter()
+                    .try_for_each(|op| client.apply_operation(vagrant_box, op))
+            })?;
+            i += stage_len;
+        }
+        Ok(())
+    }
+}
+
+/// The version from which `operation` deletes something, `None` if it is no
+/// deletion of a version or provider
+fn deleted_version(operation: &Operation) -> Option<&str> {
+    match operation {
+        Operation::DeleteProvider { version, .. } | Operation::DeleteVersion(version) => {
+            Some(&version.version)
+        }
+        _ => None,
+    }
+}
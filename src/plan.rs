@@ -0,0 +1,600 @@
+//! # Plan module
+//!
+//! A [`Plan`](struct.Plan.html) is an ordered list of
+//! [`Operation`](enum.Operation.html)s that modify a single box on Vagrant
+//! Cloud. Plans are computed from the current state of the box without
+//! modifying anything, so that they can be reviewed before they are executed
+//! via [`Client::apply`](../struct.Client.html#method.apply):
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # let client = Client::new(Some("my_api_key_here".to_string()));
+//! # let username = "my_vagrant_cloud_user_name".to_string();
+//! # let box_name = "awesome_box".to_string();
+//! # let ver = "1.2.3".to_string();
+//! # let descr = "Release from today!".to_string();
+//! # let url = "https://foo.bar.baz/path/to/my/awesome.box".to_string();
+//! let vagrant_box = VagrantBox::new(&username, &box_name);
+//! let box_version = BoxVersion { version: &ver, description: &descr };
+//! let provider = BoxProvider::new(&ProviderName::Libvirt, &url);
+//!
+//! let plan = client
+//!     .plan_provider_present(&vagrant_box, &box_version, &provider, true)
+//!     .unwrap();
+//! println!("{}", plan);
+//!
+//! // after the plan has been reviewed:
+//! client.apply(&plan).unwrap();
+//! ```
+
+use std::fmt;
+
+use super::observer::Event;
+use super::{api, BoxProvider, BoxVersion, Client, Error, ProviderName, Result, VagrantBox};
+
+#[derive(Debug, Clone, PartialEq)]
+/// Owned counterpart of [`VagrantBox`](../struct.VagrantBox.html)
+pub struct BoxSpec {
+    /// The username of the organization that owns this box
+    pub username: String,
+    /// The name of the box
+    pub name: String,
+    /// A short summary of the box
+    pub short_description: Option<String>,
+    /// A longer description of the box. Can be formatted with Markdown.
+    pub description: Option<String>,
+    /// Whether or not this box is private.
+    pub is_private: Option<bool>,
+}
+
+impl BoxSpec {
+    /// Borrow this box as a [`VagrantBox`](../struct.VagrantBox.html)
+    pub fn as_vagrant_box(&self) -> VagrantBox<'_, '_, '_, '_> {
+        VagrantBox {
+            username: &self.username,
+            name: &self.name,
+            short_description: self.short_description.as_ref(),
+            description: self.description.as_ref(),
+            is_private: self.is_private,
+        }
+    }
+}
+
+impl From<&VagrantBox<'_, '_, '_, '_>> for BoxSpec {
+    fn from(vagrant_box: &VagrantBox) -> BoxSpec {
+        BoxSpec {
+            username: vagrant_box.username.clone(),
+            name: vagrant_box.name.clone(),
+            short_description: vagrant_box.short_description.cloned(),
+            description: vagrant_box.description.cloned(),
+            is_private: vagrant_box.is_private,
+        }
+    }
+}
+
+impl From<&api::VagrantBox> for BoxSpec {
+    fn from(vagrant_box: &api::VagrantBox) -> BoxSpec {
+        BoxSpec {
+            username: vagrant_box.username.clone(),
+            name: vagrant_box.name.clone(),
+            short_description: vagrant_box.short_description.clone(),
+            description: vagrant_box.description_markdown.clone(),
+            is_private: vagrant_box.private,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Owned counterpart of [`BoxVersion`](../struct.BoxVersion.html)
+pub struct VersionSpec {
+    /// The version number of this version.
+    pub version: String,
+    /// A description for this version. Can be formatted with Markdown.
+    pub description: String,
+}
+
+impl VersionSpec {
+    /// Borrow this version as a [`BoxVersion`](../struct.BoxVersion.html)
+    pub fn as_box_version(&self) -> BoxVersion<'_, '_> {
+        BoxVersion {
+            version: &self.version,
+            description: &self.description,
+        }
+    }
+}
+
+impl From<&BoxVersion<'_, '_>> for VersionSpec {
+    fn from(box_version: &BoxVersion) -> VersionSpec {
+        VersionSpec {
+            version: box_version.version.clone(),
+            description: box_version.description.clone(),
+        }
+    }
+}
+
+impl From<&api::Version> for VersionSpec {
+    fn from(version: &api::Version) -> VersionSpec {
+        VersionSpec {
+            version: version.version.clone(),
+            description: version.description_markdown.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Owned counterpart of [`BoxProvider`](../struct.BoxProvider.html)
+pub struct ProviderSpec {
+    /// The name of the provider (e.g. libvirt, virtualbox)
+    pub name: ProviderName,
+    /// A valid URL to download this provider.
+    pub url: String,
+    /// Type of `checksum` (e.g. `sha256`)
+    pub checksum_type: Option<String>,
+    /// Checksum of the box file
+    pub checksum: Option<String>,
+}
+
+impl ProviderSpec {
+    /// Borrow this provider as a [`BoxProvider`](../struct.BoxProvider.html)
+    pub fn as_box_provider(&self) -> BoxProvider<'_, '_> {
+        BoxProvider {
+            name: &self.name,
+            url: &self.url,
+            checksum_type: self.checksum_type.as_ref(),
+            checksum: self.checksum.as_ref(),
+        }
+    }
+}
+
+impl From<&BoxProvider<'_, '_>> for ProviderSpec {
+    fn from(box_provider: &BoxProvider) -> ProviderSpec {
+        ProviderSpec {
+            name: box_provider.name.clone(),
+            url: box_provider.url.clone(),
+            checksum_type: box_provider.checksum_type.cloned(),
+            checksum: box_provider.checksum.cloned(),
+        }
+    }
+}
+
+impl From<&api::Provider> for ProviderSpec {
+    /// The url is the provider's `original_url` or, for boxes hosted on
+    /// Vagrant Cloud, its `download_url`.
+    fn from(provider: &api::Provider) -> ProviderSpec {
+        ProviderSpec {
+            name: provider.name.clone(),
+            url: provider
+                .original_url
+                .clone()
+                .unwrap_or_else(|| provider.download_url.clone()),
+            checksum_type: provider.checksum_type.clone(),
+            checksum: provider.checksum.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single modification of a box
+pub enum Operation {
+    /// Create the box of the plan
+    CreateBox,
+    /// Delete the box of the plan with all its versions
+    DeleteBox,
+    /// Update the settings of the box of the plan
+    UpdateBox {
+        /// The settings of the box before the update
+        current: BoxSpec,
+    },
+    /// Create a new version
+    CreateVersion(VersionSpec),
+    /// Update the description of an existing version
+    UpdateVersion {
+        /// The new settings of the version
+        version: VersionSpec,
+        /// The settings of the version before the update
+        current: VersionSpec,
+    },
+    /// Delete a provider of a version
+    DeleteProvider {
+        /// The version to which the provider belongs
+        version: VersionSpec,
+        /// The provider that will be deleted
+        provider: ProviderSpec,
+    },
+    /// Delete a version with all its providers
+    DeleteVersion(VersionSpec),
+    /// Create a new provider for an existing version
+    CreateProvider {
+        /// The version to which the provider will be added
+        version: VersionSpec,
+        /// The provider to create
+        provider: ProviderSpec,
+    },
+    /// Update an existing provider
+    UpdateProvider {
+        /// The version to which the provider belongs
+        version: VersionSpec,
+        /// The new settings of the provider
+        provider: ProviderSpec,
+        /// The settings of the provider before the update
+        current: ProviderSpec,
+    },
+    /// Release a version
+    ReleaseVersion(VersionSpec),
+    /// Revoke a released version
+    RevokeVersion(VersionSpec),
+}
+
+/// Render an optional value for a human
+fn describe<T: fmt::Debug>(value: &Option<T>) -> String {
+    match value {
+        Some(v) => format!("{:?}", v),
+        None => "unset".to_string(),
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::CreateBox => write!(f, "create the box"),
+            Operation::DeleteBox => write!(f, "delete the box"),
+            Operation::UpdateBox { current } => write!(
+                f,
+                "update the box (was: private: {}, short description: {}, description: {})",
+                describe(&current.is_private),
+                describe(&current.short_description),
+                describe(&current.description)
+            ),
+            Operation::CreateVersion(ver) => write!(f, "create version {}", ver.version),
+            Operation::UpdateVersion { version, current } => write!(
+                f,
+                "update the description of version {}: {:?} -> {:?}",
+                version.version, current.description, version.description
+            ),
+            Operation::DeleteProvider { version, provider } => write!(
+                f,
+                "delete provider {} of version {}",
+                provider.name, version.version
+            ),
+            Operation::DeleteVersion(ver) => write!(f, "delete version {}", ver.version),
+            Operation::CreateProvider { version, provider } => write!(
+                f,
+                "create provider {} of version {} with the url {}",
+                provider.name, version.version, provider.url
+            ),
+            Operation::UpdateProvider {
+                version,
+                provider,
+                current,
+            } => write!(
+                f,
+                "update provider {} of version {}: url {} -> {}",
+                provider.name, version.version, current.url, provider.url
+            ),
+            Operation::ReleaseVersion(ver) => write!(f, "release version {}", ver.version),
+            Operation::RevokeVersion(ver) => write!(f, "revoke version {}", ver.version),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// When the version of a provider should be released
+pub enum ReleaseMode {
+    /// Always release the version, even if it is already released
+    Always,
+    /// Never release the version, e.g. to stage a new version for QA
+    Never,
+    /// Only release the version if it is not released yet
+    #[default]
+    IfUnreleased,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Options for
+/// [`ensure_provider_present_with_options`](../struct.Client.html#method.ensure_provider_present_with_options)
+pub struct EnsureOptions {
+    /// Delete the provider from all other versions (see
+    /// [`ensure_provider_present`](../struct.Client.html#method.ensure_provider_present)),
+    /// defaults to `false`
+    pub delete_other_version: bool,
+    /// When to release the version, defaults to `ReleaseMode::IfUnreleased`
+    pub release: ReleaseMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An ordered list of operations modifying a single box
+pub struct Plan {
+    /// The box which is modified by this plan, in the state it should have
+    /// after the plan has been applied
+    pub vagrant_box: BoxSpec,
+    /// The operations in the order in which they will be performed
+    pub operations: Vec<Operation>,
+}
+
+impl Plan {
+    /// Returns true if this plan doesn't modify anything
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Plan for {}/{}:",
+            self.vagrant_box.username, self.vagrant_box.name
+        )?;
+        if self.operations.is_empty() {
+            return write!(f, " nothing to do");
+        }
+        for (i, op) in self.operations.iter().enumerate() {
+            write!(f, "\n{:>3}. {}", i + 1, op)?;
+        }
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Read the state of `vagrant_box` from Vagrant Cloud, returning `None`
+    /// if it doesn't exist.
+    pub(crate) fn read_box_if_present(
+        &self,
+        vagrant_box: &VagrantBox,
+    ) -> Result<Option<api::VagrantBox>> {
+        match self.read_box(vagrant_box) {
+            Ok(b) => Ok(Some(b)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Computes the operations that
+    /// [`ensure_provider_present`](../struct.Client.html#method.ensure_provider_present)
+    /// would perform with the same parameters, without modifying anything.
+    ///
+    /// The resulting [`Plan`](plan/struct.Plan.html) can be rendered for a
+    /// human via its `Display` implementation and executed via
+    /// [`apply`](#method.apply).
+    pub fn plan_provider_present(
+        &self,
+        vagrant_box: &VagrantBox,
+        box_version: &BoxVersion,
+        box_provider: &BoxProvider,
+        delete_other_version: bool,
+    ) -> Result<Plan> {
+        let options = EnsureOptions {
+            delete_other_version,
+            ..Default::default()
+        };
+        self.plan_provider_present_with_options(vagrant_box, box_version, box_provider, &options)
+    }
+
+    /// Computes the operations that
+    /// [`ensure_provider_present_with_options`](../struct.Client.html#method.ensure_provider_present_with_options)
+    /// would perform with the same parameters, without modifying anything.
+    pub fn plan_provider_present_with_options(
+        &self,
+        vagrant_box: &VagrantBox,
+        box_version: &BoxVersion,
+        box_provider: &BoxProvider,
+        options: &EnsureOptions,
+    ) -> Result<Plan> {
+        self.plan_providers_present(
+            vagrant_box,
+            box_version,
+            std::slice::from_ref(box_provider),
+            options,
+        )
+    }
+
+    /// Computes the operations that
+    /// [`ensure_providers_present`](../struct.Client.html#method.ensure_providers_present)
+    /// would perform with the same parameters, without modifying anything.
+    ///
+    /// Returns an `Error::NoProviders` if `box_providers` is empty and an
+    /// `Error::DuplicateProvider` if two of them have the same name.
+    pub fn plan_providers_present(
+        &self,
+        vagrant_box: &VagrantBox,
+        box_version: &BoxVersion,
+        box_providers: &[BoxProvider],
+        options: &EnsureOptions,
+    ) -> Result<Plan> {
+        if box_providers.is_empty() {
+            return Err(Error::NoProviders);
+        }
+        for (i, box_provider) in box_providers.iter().enumerate() {
+            if box_providers[..i]
+                .iter()
+                .any(|prov| prov.name == box_provider.name)
+            {
+                return Err(Error::DuplicateProvider(box_provider.name.clone()));
+            }
+        }
+
+        let mut operations = vec![];
+        let version_spec = VersionSpec::from(box_version);
+        let is_published = |name: &ProviderName| box_providers.iter().any(|p| p.name == name);
+
+        let box_res = self.read_box_if_present(vagrant_box)?;
+        let versions = match &box_res {
+            None => {
+                operations.push(Operation::CreateBox);
+                &[] as &[api::Version]
+            }
+            Some(b) => {
+                if vagrant_box != *b {
+                    operations.push(Operation::UpdateBox {
+                        current: BoxSpec::from(b),
+                    });
+                }
+                &b.versions
+            }
+        };
+
+        // if the delete_other_version flag is set: delete providers with the
+        // same name as one of box_providers (and cleanup empty versions)
+        if options.delete_other_version {
+            for ver in versions
+                .iter()
+                .filter(|ver| &ver.version != box_version.version)
+            {
+                let version_to_delete = VersionSpec {
+                    version: ver.version.clone(),
+                    description: ver
+                        .description_markdown
+                        .clone()
+                        .unwrap_or_else(|| box_version.description.clone()),
+                };
+                let to_delete: Vec<&api::Provider> = ver
+                    .providers
+                    .iter()
+                    .filter(|prov| is_published(&prov.name))
+                    .collect();
+                for prov in &to_delete {
+                    operations.push(Operation::DeleteProvider {
+                        version: version_to_delete.clone(),
+                        provider: ProviderSpec::from(*prov),
+                    });
+                }
+                // were these all providers of this version?
+                // => delete the version too
+                if !to_delete.is_empty() && to_delete.len() == ver.providers.len() {
+                    operations.push(Operation::DeleteVersion(version_to_delete));
+                }
+            }
+        }
+
+        let current_version = versions
+            .iter()
+            .find(|ver| &ver.version == box_version.version);
+        if current_version.is_none() {
+            operations.push(Operation::CreateVersion(version_spec.clone()));
+        }
+        for box_provider in box_providers {
+            let provider_spec = ProviderSpec::from(box_provider);
+            match current_version.and_then(|ver| {
+                ver.providers
+                    .iter()
+                    .find(|prov| &prov.name == box_provider.name)
+            }) {
+                None => operations.push(Operation::CreateProvider {
+                    version: version_spec.clone(),
+                    provider: provider_spec,
+                }),
+                Some(prov) if box_provider != *prov => operations.push(Operation::UpdateProvider {
+                    version: version_spec.clone(),
+                    provider: provider_spec,
+                    current: ProviderSpec::from(prov),
+                }),
+                Some(_) => (),
+            }
+        }
+
+        // release only once all providers are in place, so that users never
+        // see the version with a subset of the providers
+        let release = match options.release {
+            ReleaseMode::Always => true,
+            ReleaseMode::Never => false,
+            ReleaseMode::IfUnreleased => current_version.is_none_or(|ver| ver.status != "active"),
+        };
+        if release {
+            operations.push(Operation::ReleaseVersion(version_spec));
+        }
+
+        Ok(Plan {
+            vagrant_box: BoxSpec::from(vagrant_box),
+            operations,
+        })
+    }
+
+    /// Perform a single operation of a plan for the box `vagrant_box`
+    pub(crate) fn apply_operation(
+        &self,
+        vagrant_box: &VagrantBox,
+        operation: &Operation,
+    ) -> Result<()> {
+        let vagrant_box_spec = BoxSpec::from(vagrant_box);
+        let event = Event::Operation {
+            vagrant_box: &vagrant_box_spec,
+            operation,
+        };
+        self.observe(&event, || self.perform_operation(vagrant_box, operation))
+    }
+
+    /// Perform `operation` without notifying the observers
+    fn perform_operation(&self, vagrant_box: &VagrantBox, operation: &Operation) -> Result<()> {
+        debug!("Applying operation: {}", operation);
+        match operation {
+            Operation::CreateBox => self.create_box(vagrant_box).map(|_| ()),
+            Operation::DeleteBox => self.delete_box(vagrant_box).map(|_| ()),
+            Operation::UpdateBox { .. } => self.update_box(vagrant_box).map(|_| ()),
+            Operation::CreateVersion(ver) => self
+                .create_version(vagrant_box, &ver.as_box_version())
+                .map(|_| ()),
+            Operation::UpdateVersion { version, .. } => self
+                .update_version(vagrant_box, &version.as_box_version())
+                .map(|_| ()),
+            Operation::DeleteProvider { version, provider } => self
+                .delete_provider(
+                    vagrant_box,
+                    &version.as_box_version(),
+                    &provider.as_box_provider(),
+                )
+                .map(|_| ()),
+            Operation::DeleteVersion(ver) => self
+                .delete_version(vagrant_box, &ver.as_box_version())
+                .map(|_| ()),
+            Operation::CreateProvider { version, provider } => self
+                .create_provider(
+                    vagrant_box,
+                    &version.as_box_version(),
+                    &provider.as_box_provider(),
+                )
+                .map(|_| ()),
+            Operation::UpdateProvider {
+                version, provider, ..
+            } => self
+                .update_provider(
+                    vagrant_box,
+                    &version.as_box_version(),
+                    &provider.as_box_provider(),
+                )
+                .map(|_| ()),
+            Operation::ReleaseVersion(ver) => self
+                .release_version(vagrant_box, &ver.as_box_version())
+                .map(|_| ()),
+            Operation::RevokeVersion(ver) => self
+                .revoke_version(vagrant_box, &ver.as_box_version())
+                .map(|_| ()),
+        }
+    }
+
+    /// Executes exactly the operations of `plan` in their order and returns
+    /// the resulting state of the box.
+    ///
+    /// The first failing operation aborts the execution. Operations that
+    /// have already been performed are **not** reverted, unless the client
+    /// was configured via [`with_rollback`](#method.with_rollback), in which
+    /// case [`apply_transactional`](#method.apply_transactional) is used.
+    ///
+    /// If the client was configured via
+    /// [`with_max_concurrency`](#method.with_max_concurrency), deletions in
+    /// different versions are performed concurrently (see the
+    /// [`parallel`](parallel/index.html) module).
+    pub fn apply(&self, plan: &Plan) -> Result<api::VagrantBox> {
+        if self.rollback {
+            return self.apply_transactional(plan);
+        }
+        let vagrant_box = plan.vagrant_box.as_vagrant_box();
+        if self.max_concurrency > 1 {
+            self.apply_operations_concurrently(&vagrant_box, &plan.operations)?;
+            return self.read_box(&vagrant_box);
+        }
+        for op in &plan.operations {
+            self.apply_operation(&vagrant_box, op)?;
+        }
+        self.read_box(&vagrant_box)
+    }
+}
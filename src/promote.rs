@@ -0,0 +1,215 @@
+//! # Promotion module
+//!
+//! Boxes are often published to a staging box first and only copied to the
+//! public box once they passed QA.
+//! [`Client::promote_version`](../struct.Client.html#method.promote_version)
+//! copies a version with its description and all providers (including their
+//! URLs and checksums) from one box to another and releases it there:
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::promote::SourceAction;
+//! # let client = Client::new(Some("my_api_key_here".to_string()));
+//! let user = "my_user".to_string();
+//! let staging = "awesome_box-staging".to_string();
+//! let public = "awesome_box".to_string();
+//!
+//! client
+//!     .promote_version(
+//!         &VagrantBox::new(&user, &staging),
+//!         &VagrantBox::new(&user, &public),
+//!         "1.2.3",
+//!         SourceAction::Revoke,
+//!     )
+//!     .unwrap();
+//! ```
+//!
+//! Promoting is idempotent: only the differences between the source and the
+//! target version are applied, so an interrupted promotion can simply be
+//! repeated.
+
+use super::plan::{BoxSpec, Operation, Plan, ProviderSpec, VersionSpec};
+use super::{api, BoxVersion, Client, Error, Result, VagrantBox};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// What happens with the source version after it has been promoted
+pub enum SourceAction {
+    /// Leave the source version untouched
+    #[default]
+    Keep,
+    /// Revoke the source version (if it is released)
+    Revoke,
+    /// Delete the source version with all its providers
+    Delete,
+}
+
+impl Client {
+    /// Read the version `version` of `vagrant_box`
+    fn read_version_by_number(
+        &self,
+        vagrant_box: &VagrantBox,
+        version: &str,
+    ) -> Result<api::Version> {
+        let version = version.to_string();
+        let description = String::new();
+        self.read_version(
+            vagrant_box,
+            &BoxVersion {
+                version: &version,
+                description: &description,
+            },
+        )
+    }
+
+    /// Read the version `version` of `vagrant_box`, returning `None` if it
+    /// doesn't exist.
+    fn read_version_if_present(
+        &self,
+        vagrant_box: &VagrantBox,
+        version: &str,
+    ) -> Result<Option<api::Version>> {
+        match self.read_version_by_number(vagrant_box, version) {
+            Ok(v) => Ok(Some(v)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Computes the operations on `target_box` that
+    /// [`promote_version`](#method.promote_version) would perform to make
+    /// its version `version` identical to the one of `source_box`, without
+    /// modifying anything.
+    ///
+    /// Providers of the target version that the source version lacks are
+    /// deleted. The target version is released unless it already is.
+    pub fn plan_promote_version(
+        &self,
+        source_box: &VagrantBox,
+        target_box: &VagrantBox,
+        version: &str,
+    ) -> Result<Plan> {
+        let target = self.read_version_if_present(target_box, version)?;
+        let source = match self.read_version_by_number(source_box, version) {
+            Ok(source) => source,
+            // the source is gone after a completed promotion with
+            // SourceAction::Delete => nothing left to do
+            Err(Error::NotFound(_)) if target.as_ref().is_some_and(|t| t.status == "active") => {
+                return Ok(Plan {
+                    vagrant_box: BoxSpec::from(target_box),
+                    operations: vec![],
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut operations = vec![];
+        let version = VersionSpec::from(&source);
+        let target_providers: &[api::Provider] = match &target {
+            None => {
+                operations.push(Operation::CreateVersion(version.clone()));
+                &[]
+            }
+            Some(t) => {
+                if &version.as_box_version() != *t {
+                    operations.push(Operation::UpdateVersion {
+                        version: version.clone(),
+                        current: VersionSpec::from(t),
+                    });
+                }
+                &t.providers
+            }
+        };
+
+        for prov in target_providers
+            .iter()
+            .filter(|prov| !source.providers.iter().any(|p| p.name == prov.name))
+        {
+            operations.push(Operation::DeleteProvider {
+                version: version.clone(),
+                provider: ProviderSpec::from(prov),
+            });
+        }
+        for prov in &source.providers {
+            let provider = ProviderSpec::from(prov);
+            match target_providers.iter().find(|p| p.name == prov.name) {
+                None => operations.push(Operation::CreateProvider {
+                    version: version.clone(),
+                    provider,
+                }),
+                Some(p) if &provider.as_box_provider() != *p => {
+                    operations.push(Operation::UpdateProvider {
+                        version: version.clone(),
+                        provider,
+                        current: ProviderSpec::from(p),
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+
+        if target.is_none_or(|t| t.status != "active") {
+            operations.push(Operation::ReleaseVersion(version));
+        }
+
+        Ok(Plan {
+            vagrant_box: BoxSpec::from(target_box),
+            operations,
+        })
+    }
+
+    /// Copies the version `version` of `source_box` with its description and
+    /// all its providers to `target_box` and releases it there. Afterwards
+    /// the source version is kept, revoked or deleted depending on
+    /// `source_action`.
+    ///
+    /// `target_box` must already exist. Providers hosted on Vagrant Cloud
+    /// are promoted with their download URL on the source box, so versions
+    /// with hosted providers cannot be promoted with `SourceAction::Delete`
+    /// and an `Error::HostedSourceProvider` is returned before anything is
+    /// modified.
+    ///
+    /// Returns the promoted version of `target_box`.
+    pub fn promote_version(
+        &self,
+        source_box: &VagrantBox,
+        target_box: &VagrantBox,
+        version: &str,
+        source_action: SourceAction,
+    ) -> Result<api::Version> {
+        if source_action == SourceAction::Delete {
+            let source = self.read_version_if_present(source_box, version)?;
+            if let Some(hosted) = source
+                .iter()
+                .flat_map(|s| s.providers.iter())
+                .find(|p| p.hosted)
+            {
+                return Err(Error::HostedSourceProvider(hosted.name.clone()));
+            }
+        }
+
+        let plan = self.plan_promote_version(source_box, target_box, version)?;
+        if !plan.is_empty() {
+            self.apply(&plan)?;
+        }
+
+        let source = self.read_version_if_present(source_box, version)?;
+        let source_operation = match (source_action, &source) {
+            (SourceAction::Revoke, Some(s)) if s.status == "active" => {
+                Some(Operation::RevokeVersion(VersionSpec::from(s)))
+            }
+            (SourceAction::Delete, Some(s)) => Some(Operation::DeleteVersion(VersionSpec::from(s))),
+            _ => None,
+        };
+        if let Some(op) = source_operation {
+            self.apply_operation(source_box, &op)?;
+        }
+
+        self.read_version_if_present(target_box, version)?
+            .ok_or_else(|| {
+                Error::InternalError(format!(
+                    "version {} of {}/{} vanished after promoting it",
+                    version, target_box.username, target_box.name
+                ))
+            })
+    }
+}
@@ -0,0 +1,203 @@
+//! # Provider name module
+//!
+//! Vagrant Cloud accepts arbitrary provider names, so a typo like `virtualBox`
+//! silently creates a new provider instead of updating the existing
+//! `virtualbox` one. [`ProviderName`](enum.ProviderName.html) therefore has a
+//! dedicated variant for each provider known to vagabond and reports a warning
+//! for everything else:
+//!
+//! ```
+//! # use vagabond::provider::ProviderName;
+//! assert_eq!(ProviderName::from("libvirt"), ProviderName::Libvirt);
+//!
+//! let typo = ProviderName::from("virtualBox");
+//! assert_eq!(typo, ProviderName::Custom("virtualBox".to_string()));
+//! assert_eq!(
+//!     typo.warning().unwrap(),
+//!     "unknown provider 'virtualBox', did you mean 'virtualbox'?"
+//! );
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone)]
+/// Name of a provider of a Vagrant box
+///
+/// Provider names are compared, hashed and ordered by their name as used by
+/// Vagrant, so `ProviderName::Custom("libvirt".to_string())` is equal to
+/// `ProviderName::Libvirt`.
+pub enum ProviderName {
+    /// `virtualbox`
+    VirtualBox,
+    /// `libvirt`
+    Libvirt,
+    /// `vmware_desktop`
+    VmwareDesktop,
+    /// `hyperv`
+    HyperV,
+    /// `parallels`
+    Parallels,
+    /// `docker`
+    Docker,
+    /// `qemu`
+    Qemu,
+    /// Any other provider name
+    Custom(String),
+}
+
+/// All known providers
+const KNOWN_PROVIDERS: [ProviderName; 7] = [
+    ProviderName::VirtualBox,
+    ProviderName::Libvirt,
+    ProviderName::VmwareDesktop,
+    ProviderName::HyperV,
+    ProviderName::Parallels,
+    ProviderName::Docker,
+    ProviderName::Qemu,
+];
+
+/// Strip everything but alphanumeric characters and convert to lowercase, so
+/// that `VMware-Desktop` and `vmware_desktop` are considered similar
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl ProviderName {
+    /// The name of this provider as used by Vagrant
+    pub fn as_str(&self) -> &str {
+        match self {
+            ProviderName::VirtualBox => "virtualbox",
+            ProviderName::Libvirt => "libvirt",
+            ProviderName::VmwareDesktop => "vmware_desktop",
+            ProviderName::HyperV => "hyperv",
+            ProviderName::Parallels => "parallels",
+            ProviderName::Docker => "docker",
+            ProviderName::Qemu => "qemu",
+            ProviderName::Custom(name) => name,
+        }
+    }
+
+    /// Returns true if this is one of the providers known to vagabond
+    pub fn is_known(&self) -> bool {
+        KNOWN_PROVIDERS
+            .iter()
+            .any(|known| known.as_str() == self.as_str())
+    }
+
+    /// Returns a warning if this is not a known provider, including a
+    /// suggestion if the name looks like a misspelled known provider
+    pub fn warning(&self) -> Option<String> {
+        if self.is_known() {
+            return None;
+        }
+        let name = self.as_str();
+        let normalized = normalize(name);
+        Some(
+            match KNOWN_PROVIDERS
+                .iter()
+                .find(|known| normalize(known.as_str()) == normalized)
+            {
+                Some(known) => format!(
+                    "unknown provider '{}', did you mean '{}'?",
+                    name,
+                    known.as_str()
+                ),
+                None => format!("unknown provider '{}'", name),
+            },
+        )
+    }
+}
+
+impl Default for ProviderName {
+    fn default() -> ProviderName {
+        ProviderName::Custom(String::new())
+    }
+}
+
+impl From<&str> for ProviderName {
+    fn from(name: &str) -> ProviderName {
+        KNOWN_PROVIDERS
+            .iter()
+            .find(|known| known.as_str() == name)
+            .cloned()
+            .unwrap_or_else(|| ProviderName::Custom(name.to_string()))
+    }
+}
+
+impl From<String> for ProviderName {
+    fn from(name: String) -> ProviderName {
+        match ProviderName::from(name.as_str()) {
+            ProviderName::Custom(_) => ProviderName::Custom(name),
+            known => known,
+        }
+    }
+}
+
+impl PartialEq for ProviderName {
+    fn eq(&self, other: &ProviderName) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for ProviderName {}
+
+impl Hash for ProviderName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl PartialOrd for ProviderName {
+    fn partial_cmp(&self, other: &ProviderName) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ProviderName {
+    fn cmp(&self, other: &ProviderName) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialEq<str> for ProviderName {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for ProviderName {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for ProviderName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for ProviderName {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ProviderName {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<ProviderName, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(ProviderName::from)
+    }
+}
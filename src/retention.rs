@@ -0,0 +1,192 @@
+//! # Retention module
+//!
+//! A [`RetentionPolicy`](struct.RetentionPolicy.html) decides which versions
+//! and providers of a box are no longer needed. Policies are evaluated against
+//! the current state of a box and result in a [`Plan`](../plan/struct.Plan.html)
+//! that only contains deletions. The plan doubles as a dry-run report: it can
+//! be printed for review and is only executed once it is passed to
+//! [`Client::apply`](../struct.Client.html#method.apply).
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use vagabond::*;
+//! # use vagabond::retention::RetentionPolicy;
+//! # let client = Client::new(Some("my_api_key_here".to_string()));
+//! # let username = "my_vagrant_cloud_user_name".to_string();
+//! # let box_name = "awesome_box".to_string();
+//! let policy = RetentionPolicy::new()
+//!     // keep the three newest released versions of every provider
+//!     .keep_last_released(3)
+//!     // drop versions that were never released within 30 days
+//!     .delete_unreleased_older_than(Duration::from_secs(30 * 24 * 60 * 60))
+//!     // but never touch the LTS releases
+//!     .protect(r"^1\.0\.")
+//!     .unwrap();
+//!
+//! let plan = client
+//!     .plan_retention(&VagrantBox::new(&username, &box_name), &policy)
+//!     .unwrap();
+//! println!("{}", plan);
+//! client.apply(&plan).unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+use super::constraint::VersionNumber;
+use super::plan::{BoxSpec, Operation, Plan, ProviderSpec, VersionSpec};
+use super::{api, Client, Error, ProviderName, Result, VagrantBox};
+
+#[derive(Debug, Clone, Default)]
+/// A set of rules deciding which versions and providers of a box to delete
+///
+/// A policy without any rules deletes nothing.
+pub struct RetentionPolicy {
+    keep_last_released: Option<usize>,
+    delete_unreleased_older_than: Option<Duration>,
+    protected: Vec<Regex>,
+}
+
+impl RetentionPolicy {
+    /// Create a policy that deletes nothing
+    pub fn new() -> RetentionPolicy {
+        RetentionPolicy::default()
+    }
+
+    /// Keep only the `count` newest released versions of each provider.
+    ///
+    /// Providers of older released versions are deleted. Once a version
+    /// would lose all its providers, the whole version is deleted instead.
+    /// Versions whose version number cannot be parsed are left untouched.
+    /// Protected versions (see [`protect`](#method.protect)) are kept in
+    /// addition to the `count` newest unprotected ones.
+    pub fn keep_last_released(mut self, count: usize) -> RetentionPolicy {
+        self.keep_last_released = Some(count);
+        self
+    }
+
+    /// Delete unreleased versions that were created more than `age` ago.
+    ///
+    /// Versions without a (valid) creation date are left untouched.
+    pub fn delete_unreleased_older_than(mut self, age: Duration) -> RetentionPolicy {
+        self.delete_unreleased_older_than = Some(age);
+        self
+    }
+
+    /// Never delete versions (or their providers) whose version number
+    /// matches the regular expression `pattern`.
+    ///
+    /// The pattern is not anchored, use `^` and `$` to match the whole
+    /// version number. Returns an `Error::InvalidPattern` if `pattern` is not
+    /// a valid regular expression.
+    pub fn protect(mut self, pattern: &str) -> Result<RetentionPolicy> {
+        let re = Regex::new(pattern).map_err(|e| Error::InvalidPattern(e.to_string()))?;
+        self.protected.push(re);
+        Ok(self)
+    }
+
+    fn is_protected(&self, version: &api::Version) -> bool {
+        self.protected
+            .iter()
+            .any(|re| re.is_match(&version.version))
+    }
+
+    /// Is `version` an unreleased version that is older than permitted at
+    /// the time `now`?
+    fn is_expired(&self, version: &api::Version, now: DateTime<Utc>) -> bool {
+        let age = match self.delete_unreleased_older_than {
+            Some(age) => age,
+            None => return false,
+        };
+        if version.status != "unreleased" {
+            return false;
+        }
+        version
+            .created_at
+            .as_deref()
+            .and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+            .and_then(|created| {
+                now.signed_duration_since(created.with_timezone(&Utc))
+                    .to_std()
+                    .ok()
+            })
+            .is_some_and(|elapsed| elapsed > age)
+    }
+
+    /// Compute the deletions that this policy requires for `vagrant_box` at
+    /// the time `now`.
+    ///
+    /// This does not contact Vagrant Cloud, see
+    /// [`Client::plan_retention`](../struct.Client.html#method.plan_retention)
+    /// for a convenience wrapper.
+    pub fn evaluate(&self, vagrant_box: &api::VagrantBox, now: DateTime<Utc>) -> Plan {
+        // index into vagrant_box.versions => providers to delete
+        let mut superfluous: BTreeMap<usize, Vec<&api::Provider>> = BTreeMap::new();
+
+        if let Some(count) = self.keep_last_released {
+            let mut released: Vec<(VersionNumber, usize)> = vagrant_box
+                .versions
+                .iter()
+                .enumerate()
+                .filter(|(_, ver)| ver.status == "active" && !self.is_protected(ver))
+                .filter_map(|(i, ver)| ver.version.parse().ok().map(|num| (num, i)))
+                .collect();
+            released.sort_by(|(lhs, _), (rhs, _)| rhs.cmp(lhs));
+
+            let mut seen: BTreeMap<&ProviderName, usize> = BTreeMap::new();
+            for (_, i) in released {
+                for prov in &vagrant_box.versions[i].providers {
+                    let newer = seen.entry(&prov.name).or_insert(0);
+                    *newer += 1;
+                    if *newer > count {
+                        superfluous.entry(i).or_default().push(prov);
+                    }
+                }
+            }
+        }
+
+        let mut operations = vec![];
+        for (i, ver) in vagrant_box.versions.iter().enumerate() {
+            if self.is_protected(ver) {
+                continue;
+            }
+            let version_spec = VersionSpec::from(ver);
+            let providers = superfluous.remove(&i).unwrap_or_default();
+
+            let loses_all_providers =
+                !providers.is_empty() && providers.len() == ver.providers.len();
+            if self.is_expired(ver, now) || loses_all_providers {
+                operations.push(Operation::DeleteVersion(version_spec));
+                continue;
+            }
+            operations.extend(providers.into_iter().map(|prov| Operation::DeleteProvider {
+                version: version_spec.clone(),
+                provider: ProviderSpec::from(prov),
+            }));
+        }
+
+        Plan {
+            vagrant_box: BoxSpec::from(vagrant_box),
+            operations,
+        }
+    }
+}
+
+impl Client {
+    /// Computes the deletions that `policy` requires for `vagrant_box` in its
+    /// current state, without modifying anything.
+    ///
+    /// The resulting [`Plan`](plan/struct.Plan.html) can be executed via
+    /// [`apply`](#method.apply).
+    pub fn plan_retention(
+        &self,
+        vagrant_box: &VagrantBox,
+        policy: &RetentionPolicy,
+    ) -> Result<Plan> {
+        let current = self.read_box(vagrant_box)?;
+        Ok(policy.evaluate(&current, Utc::now()))
+    }
+}
@@ -0,0 +1,344 @@
+//! # Schema module
+//!
+//! The replies of the Vagrant Cloud API are not always complete: fields may be
+//! missing or `null`, or new fields might get added at any time. vagabond
+//! therefore supports two ways of dealing with replies, selected via
+//! [`DeserializationMode`](enum.DeserializationMode.html):
+//!
+//! - `Lenient` (the default): missing or `null` fields are replaced with their
+//!   default value and unknown fields are collected in the `extra` map of the
+//!   structs in the [`api`](../api/index.html) module.
+//! - `Strict`: every reply is checked against the expected schema first and
+//!   all deviations are reported via `Error::SchemaViolation`, each with the
+//!   JSON path at which it occurred. This is intended for contract tests
+//!   against the API.
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::schema::DeserializationMode;
+//! let client = Client::new(None as Option<String>)
+//!     .with_deserialization_mode(DeserializationMode::Strict);
+//! let username = "ubuntu".to_string();
+//! let box_name = "trusty64".to_string();
+//! match client.read_box(&VagrantBox::new(&username, &box_name)) {
+//!     Err(Error::SchemaViolation { deviations, .. }) => {
+//!         deviations.iter().for_each(|d| println!("{}", d))
+//!     }
+//!     res => println!("{:?}", res),
+//! }
+//! ```
+
+use std::fmt;
+
+use serde_json::Value;
+
+use super::api;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How replies from the API are deserialized
+pub enum DeserializationMode {
+    /// Tolerate missing, `null` and unknown fields
+    #[default]
+    Lenient,
+    /// Report every deviation from the expected schema as an error
+    Strict,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The ways in which a reply can deviate from the expected schema
+pub enum DeviationKind {
+    /// A field is missing
+    MissingField,
+    /// A field that must not be `null` is `null`
+    UnexpectedNull,
+    /// A field has the wrong type
+    WrongType {
+        /// The expected JSON type
+        expected: &'static str,
+        /// The JSON type that was received
+        found: &'static str,
+    },
+    /// A field is not part of the schema
+    UnknownField,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single deviation of a reply from the expected schema
+pub struct SchemaDeviation {
+    /// JSON path to the offending element, e.g. `versions[0].providers[1]`,
+    /// `.` for the top level
+    pub path: String,
+    /// What is wrong with the element
+    pub kind: DeviationKind,
+}
+
+impl fmt::Display for SchemaDeviation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "."
+        } else {
+            &self.path
+        };
+        match &self.kind {
+            DeviationKind::MissingField => write!(f, "{}: missing field", path),
+            DeviationKind::UnexpectedNull => write!(f, "{}: unexpected null", path),
+            DeviationKind::WrongType { expected, found } => {
+                write!(f, "{}: expected {}, found {}", path, expected, found)
+            }
+            DeviationKind::UnknownField => write!(f, "{}: unknown field", path),
+        }
+    }
+}
+
+/// Types whose JSON representation can be checked against a schema
+pub trait Schema {
+    /// Append all deviations of `value` from the schema of `Self` to
+    /// `deviations`, where `path` is the JSON path of `value`.
+    fn check_schema(value: &Value, path: &str, deviations: &mut Vec<SchemaDeviation>);
+}
+
+impl Schema for () {
+    fn check_schema(_: &Value, _: &str, _: &mut Vec<SchemaDeviation>) {}
+}
+
+impl<T: Schema> Schema for Option<T> {
+    fn check_schema(value: &Value, path: &str, deviations: &mut Vec<SchemaDeviation>) {
+        if !value.is_null() {
+            T::check_schema(value, path, deviations);
+        }
+    }
+}
+
+impl<T: Schema> Schema for Vec<T> {
+    fn check_schema(value: &Value, path: &str, deviations: &mut Vec<SchemaDeviation>) {
+        match value {
+            Value::Array(elements) => elements.iter().enumerate().for_each(|(i, elem)| {
+                T::check_schema(elem, &format!("{}[{}]", path, i), deviations)
+            }),
+            _ => deviations.push(SchemaDeviation {
+                path: path.to_string(),
+                kind: DeviationKind::WrongType {
+                    expected: "array",
+                    found: json_type(value),
+                },
+            }),
+        }
+    }
+}
+
+/// Name of the JSON type of `value`
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+type CheckFn = fn(&Value, &str, &mut Vec<SchemaDeviation>);
+
+/// Expected type of a field
+enum FieldType {
+    String,
+    Boolean,
+    UnsignedInteger,
+    Nested(CheckFn),
+}
+
+/// Description of a single field of an object
+struct Field {
+    name: &'static str,
+    field_type: FieldType,
+    /// may the field be `null`?
+    nullable: bool,
+    /// must the field be present?
+    required: bool,
+}
+
+impl Field {
+    const fn new(name: &'static str, field_type: FieldType) -> Field {
+        Field {
+            name,
+            field_type,
+            nullable: false,
+            required: true,
+        }
+    }
+
+    const fn nullable(mut self) -> Field {
+        self.nullable = true;
+        self
+    }
+
+    const fn optional(mut self) -> Field {
+        self.required = false;
+        self
+    }
+}
+
+/// Check that `value` is an object consisting of exactly `fields`
+fn check_object(
+    value: &Value,
+    path: &str,
+    fields: &[Field],
+    deviations: &mut Vec<SchemaDeviation>,
+) {
+    let object = match value {
+        Value::Object(o) => o,
+        _ => {
+            deviations.push(SchemaDeviation {
+                path: path.to_string(),
+                kind: DeviationKind::WrongType {
+                    expected: "object",
+                    found: json_type(value),
+                },
+            });
+            return;
+        }
+    };
+
+    let field_path = |name: &str| {
+        if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", path, name)
+        }
+    };
+
+    for field in fields {
+        let path = field_path(field.name);
+        let val = match object.get(field.name) {
+            None => {
+                if field.required {
+                    deviations.push(SchemaDeviation {
+                        path,
+                        kind: DeviationKind::MissingField,
+                    });
+                }
+                continue;
+            }
+            Some(Value::Null) => {
+                if !field.nullable {
+                    deviations.push(SchemaDeviation {
+                        path,
+                        kind: DeviationKind::UnexpectedNull,
+                    });
+                }
+                continue;
+            }
+            Some(val) => val,
+        };
+
+        let expected = match &field.field_type {
+            FieldType::String if !val.is_string() => Some("string"),
+            FieldType::Boolean if !val.is_boolean() => Some("boolean"),
+            FieldType::UnsignedInteger if !val.is_u64() => Some("unsigned integer"),
+            FieldType::Nested(check) => {
+                check(val, &path, deviations);
+                None
+            }
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            deviations.push(SchemaDeviation {
+                path,
+                kind: DeviationKind::WrongType {
+                    expected,
+                    found: json_type(val),
+                },
+            });
+        }
+    }
+
+    object
+        .keys()
+        .filter(|key| !fields.iter().any(|field| field.name == key.as_str()))
+        .for_each(|key| {
+            deviations.push(SchemaDeviation {
+                path: field_path(key),
+                kind: DeviationKind::UnknownField,
+            })
+        });
+}
+
+impl Schema for api::Provider {
+    fn check_schema(value: &Value, path: &str, deviations: &mut Vec<SchemaDeviation>) {
+        let fields = [
+            Field::new("name", FieldType::String),
+            Field::new("hosted", FieldType::Boolean),
+            Field::new("hosted_token", FieldType::String).nullable(),
+            Field::new("original_url", FieldType::String).nullable(),
+            Field::new("created_at", FieldType::String),
+            Field::new("updated_at", FieldType::String),
+            Field::new("download_url", FieldType::String),
+            Field::new("architecture", FieldType::String)
+                .nullable()
+                .optional(),
+            Field::new("checksum", FieldType::String)
+                .nullable()
+                .optional(),
+            Field::new("checksum_type", FieldType::String)
+                .nullable()
+                .optional(),
+        ];
+        check_object(value, path, &fields, deviations);
+    }
+}
+
+impl Schema for api::Version {
+    fn check_schema(value: &Value, path: &str, deviations: &mut Vec<SchemaDeviation>) {
+        let fields = [
+            Field::new("version", FieldType::String),
+            Field::new("status", FieldType::String),
+            Field::new("description_html", FieldType::String).nullable(),
+            Field::new("description_markdown", FieldType::String).nullable(),
+            Field::new("created_at", FieldType::String).nullable(),
+            Field::new("updated_at", FieldType::String).nullable(),
+            Field::new("number", FieldType::String),
+            Field::new("release_url", FieldType::String),
+            Field::new("revoke_url", FieldType::String),
+            Field::new(
+                "providers",
+                FieldType::Nested(<Vec<api::Provider>>::check_schema),
+            ),
+        ];
+        check_object(value, path, &fields, deviations);
+    }
+}
+
+impl Schema for api::UploadTarget {
+    fn check_schema(value: &Value, path: &str, deviations: &mut Vec<SchemaDeviation>) {
+        let fields = [Field::new("upload_path", FieldType::String)];
+        check_object(value, path, &fields, deviations);
+    }
+}
+
+impl Schema for api::VagrantBox {
+    fn check_schema(value: &Value, path: &str, deviations: &mut Vec<SchemaDeviation>) {
+        let fields = [
+            Field::new("tag", FieldType::String).nullable(),
+            Field::new("username", FieldType::String),
+            Field::new("name", FieldType::String),
+            Field::new("private", FieldType::Boolean).nullable(),
+            Field::new("downloads", FieldType::UnsignedInteger),
+            Field::new("created_at", FieldType::String),
+            Field::new("updated_at", FieldType::String),
+            Field::new("short_description", FieldType::String).nullable(),
+            Field::new("description_markdown", FieldType::String).nullable(),
+            Field::new("description_html", FieldType::String).nullable(),
+            Field::new(
+                "versions",
+                FieldType::Nested(<Vec<api::Version>>::check_schema),
+            ),
+            Field::new(
+                "current_version",
+                FieldType::Nested(api::Version::check_schema),
+            )
+            .nullable(),
+        ];
+        check_object(value, path, &fields, deviations);
+    }
+}
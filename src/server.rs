@@ -0,0 +1,1264 @@
+//! # Server module
+//!
+//! A [`Server`](struct.Server.html) is a self-hosted registry implementing
+//! the parts of the Vagrant Cloud API v1 that are used by
+//! [`Client`](../struct.Client.html): boxes, versions, providers, releases
+//! and hosted box uploads. It furthermore serves the catalogs (see the
+//! [`catalog`](../catalog/index.html) module) that `vagrant box add
+//! my_user/awesome_box` fetches when pointed at the server via
+//! `VAGRANT_SERVER_URL`.
+//!
+//! The server is only available with the `server` feature. Boxes are stored
+//! via a [`Backend`](trait.Backend.html), either in plain files
+//! ([`FilesystemBackend`](struct.FilesystemBackend.html)) or in a SQLite
+//! database (`SqliteBackend`, requires the `sqlite` feature):
+//!
+//! ```no_run
+//! # use vagabond::server::{FilesystemBackend, Server};
+//! Server::new(FilesystemBackend::new("/var/lib/vagabond"))
+//!     .with_token("my_api_key_here", "my_user")
+//!     .with_public_url("https://boxes.example.com")
+//!     .serve("0.0.0.0:8080")
+//!     .unwrap();
+//! ```
+//!
+//! Clients then use `https://boxes.example.com/api/v1` as their base URL:
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! let client = Client::new(Some("my_api_key_here"))
+//!     .with_base_url("https://boxes.example.com/api/v1");
+//! ```
+//!
+//! Each token may only modify the boxes of the user that it belongs to.
+//! Public boxes can be read without a token, private boxes only with a token
+//! of their owner, passed either as `Authorization: Bearer` header or as
+//! `access_token` query parameter (as done by `vagrant`).
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, ResponseBox};
+
+use super::catalog::{Catalog, CatalogProvider};
+use super::checksum::{ChecksumType, HashingReader};
+use super::constraint::VersionNumber;
+use super::site::validate_directory_name;
+use super::{BoxTag, Error, ProviderName, Result};
+
+mod filesystem;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use self::filesystem::FilesystemBackend;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteBackend;
+
+/// JSON payloads larger than this are rejected
+const MAX_PAYLOAD_SIZE: u64 = 1024 * 1024;
+
+/// Number of requests that are handled concurrently
+const WORKER_THREADS: usize = 8;
+
+/// Characters that are percent-encoded in the path segments of URLs
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A box as stored by a [`Backend`](trait.Backend.html)
+pub struct BoxRecord {
+    /// The user owning the box
+    pub username: String,
+    /// The name of the box
+    pub name: String,
+    /// A short summary of the box
+    #[serde(default)]
+    pub short_description: Option<String>,
+    /// A longer description of the box in Markdown
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Whether the box is only visible to its owner
+    #[serde(default)]
+    pub private: bool,
+    /// Number of downloads of hosted box files
+    #[serde(default)]
+    pub downloads: u64,
+    /// Creation time (RFC 3339)
+    pub created_at: String,
+    /// Time of the last modification (RFC 3339)
+    pub updated_at: String,
+    /// All versions of the box
+    #[serde(default)]
+    pub versions: Vec<VersionRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A version of a [`BoxRecord`](struct.BoxRecord.html)
+pub struct VersionRecord {
+    /// The version number
+    pub version: String,
+    /// `unreleased`, `active` or `revoked`
+    pub status: String,
+    /// Description of the version in Markdown
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Creation time (RFC 3339)
+    pub created_at: String,
+    /// Time of the last modification (RFC 3339)
+    pub updated_at: String,
+    /// All providers of the version
+    #[serde(default)]
+    pub providers: Vec<ProviderRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A provider of a [`VersionRecord`](struct.VersionRecord.html)
+pub struct ProviderRecord {
+    /// The name of the provider
+    pub name: ProviderName,
+    /// The URL of a box file hosted elsewhere
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Whether the box file has been uploaded to the server
+    #[serde(default)]
+    pub hosted: bool,
+    /// Type of `checksum`
+    #[serde(default)]
+    pub checksum_type: Option<String>,
+    /// Checksum of the box file
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Architecture of the guest
+    #[serde(default)]
+    pub architecture: Option<String>,
+    /// Token that authorizes the pending upload of the box file
+    #[serde(default)]
+    pub upload_token: Option<String>,
+    /// Creation time (RFC 3339)
+    pub created_at: String,
+    /// Time of the last modification (RFC 3339)
+    pub updated_at: String,
+}
+
+impl ProviderRecord {
+    /// Whether this provider has neither an external URL nor a hosted box
+    /// file yet
+    fn is_pending(&self) -> bool {
+        !self.hosted && self.url.as_deref().is_none_or(str::is_empty)
+    }
+}
+
+/// Storage of the boxes of a [`Server`](struct.Server.html)
+///
+/// Boxes are always read and written as a whole, the server serializes all
+/// modifications. The only exception is the download counter, which is
+/// incremented concurrently to everything else.
+pub trait Backend: Send + Sync {
+    /// Read the box `tag` including its current number of downloads, `None`
+    /// if it does not exist
+    fn read_box(&self, tag: &BoxTag) -> Result<Option<BoxRecord>>;
+
+    /// Create the box `record` or replace the existing one
+    ///
+    /// The number of downloads of `record` is ignored, it is only changed by
+    /// `increment_downloads()`.
+    fn write_box(&self, record: &BoxRecord) -> Result<()>;
+
+    /// Count a download of a hosted box file of the box `tag`
+    fn increment_downloads(&self, tag: &BoxTag) -> Result<()>;
+
+    /// Delete the box `tag` including all of its hosted box files
+    fn delete_box(&self, tag: &BoxTag) -> Result<()>;
+
+    /// The path at which the hosted box file of the provider `provider` of
+    /// the version `version` of `tag` is stored
+    fn box_file(&self, tag: &BoxTag, version: &str, provider: &ProviderName) -> PathBuf;
+}
+
+/// Reply to a successfully handled request
+enum Reply {
+    Json(Value),
+    File(File),
+}
+
+/// Reply to a request that could not be handled
+#[derive(Debug)]
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+type Handled<T> = std::result::Result<T, Failure>;
+
+fn fail<T, S: Into<String>>(status: u16, message: S) -> Handled<T> {
+    Err(Failure {
+        status,
+        message: message.into(),
+    })
+}
+
+fn not_found<T>() -> Handled<T> {
+    fail(404, "Resource not found!")
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Failure {
+        let status = match err {
+            Error::InvalidBoxTag(_)
+            | Error::InvalidPathSegment(_)
+            | Error::InvalidVersionConstraint(_)
+            | Error::UnsupportedChecksumType(_) => 422,
+            _ => {
+                error!("Failed to handle a request: {}", err);
+                500
+            }
+        };
+        Failure {
+            status,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Failure {
+        Failure::from(Error::from(err))
+    }
+}
+
+/// The parsed parts of a request
+struct Context {
+    method: Method,
+    segments: Vec<String>,
+    query: HashMap<String, String>,
+    /// The user to whom the passed token belongs
+    user: Option<String>,
+    /// The URL under which the server is reachable
+    base_url: String,
+}
+
+impl Context {
+    /// The URL of the file at `segments` below the root of the server
+    fn url(&self, segments: &[&str]) -> String {
+        segments.iter().fold(self.base_url.clone(), |url, seg| {
+            format!("{}/{}", url, utf8_percent_encode(seg, PATH_SEGMENT))
+        })
+    }
+
+    /// The URL of the API endpoint of `segments` below the box `tag`
+    fn box_url(&self, tag: &BoxTag, segments: &[&str]) -> String {
+        let mut all = vec!["api", "v1", "box", tag.username(), tag.name()];
+        all.extend_from_slice(segments);
+        self.url(&all)
+    }
+
+    /// Whether the passed token belongs to `username`
+    fn is_owner(&self, username: &str) -> bool {
+        self.user.as_deref() == Some(username)
+    }
+}
+
+/// The current time in RFC 3339 format
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+/// `len` bytes from the random number generator of the operating system,
+/// hex encoded
+fn random_hex(len: usize) -> Handled<String> {
+    let mut bytes = vec![0; len];
+    if let Err(e) = getrandom::getrandom(&mut bytes) {
+        return fail(500, format!("Cannot generate random data: {}", e));
+    }
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Create an unguessable token for the upload of a box file
+fn upload_token() -> Handled<String> {
+    random_hex(32)
+}
+
+/// Read the JSON payload of a request, unwrapping it from the object `key`
+/// (e.g. `{"version": {...}}`) if present
+fn read_payload<T: DeserializeOwned>(body: &mut dyn Read, key: &str) -> Handled<T> {
+    let mut contents = vec![];
+    body.take(MAX_PAYLOAD_SIZE + 1).read_to_end(&mut contents)?;
+    if contents.len() as u64 > MAX_PAYLOAD_SIZE {
+        return fail(413, "The payload is too large");
+    }
+    let mut value: Value = if contents.iter().all(u8::is_ascii_whitespace) {
+        json!({})
+    } else {
+        serde_json::from_slice(&contents)
+            .or_else(|e| fail(400, format!("Invalid JSON payload: {}", e)))?
+    };
+    if let Some(inner) = value.get_mut(key) {
+        value = inner.take();
+    }
+    serde_json::from_value(value).or_else(|e| fail(422, format!("Invalid payload: {}", e)))
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct BoxPayload {
+    username: Option<String>,
+    name: Option<String>,
+    short_description: Option<String>,
+    description: Option<String>,
+    is_private: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct VersionPayload {
+    version: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ProviderPayload {
+    name: Option<ProviderName>,
+    url: Option<String>,
+    checksum_type: Option<String>,
+    checksum: Option<String>,
+    architecture: Option<String>,
+}
+
+/// Find the version `version` of `record`
+fn find_version<'r>(record: &'r mut BoxRecord, version: &str) -> Handled<&'r mut VersionRecord> {
+    match record.versions.iter_mut().find(|v| v.version == version) {
+        Some(v) => Ok(v),
+        None => not_found(),
+    }
+}
+
+/// Find the provider `provider` of `version`
+fn find_provider<'r>(
+    version: &'r mut VersionRecord,
+    provider: &str,
+) -> Handled<&'r mut ProviderRecord> {
+    match version.providers.iter_mut().find(|p| p.name == provider) {
+        Some(p) => Ok(p),
+        None => not_found(),
+    }
+}
+
+/// Check that `checksum_type` is supported
+fn validate_checksum_type(checksum_type: &Option<String>) -> Handled<()> {
+    if let Some(t) = checksum_type {
+        t.parse::<ChecksumType>()?;
+    }
+    Ok(())
+}
+
+/// Check that the checksum of `provider` can be verified
+fn validate_checksum(provider: &ProviderRecord) -> Handled<()> {
+    if provider.checksum.is_some() && provider.checksum_type.is_none() {
+        return fail(422, "The checksum of the provider has no checksum type");
+    }
+    Ok(())
+}
+
+/// Compare the secrets `lhs` and `rhs` in a time that does not depend on
+/// their contents
+fn constant_time_eq(lhs: &str, rhs: &str) -> bool {
+    lhs.len() == rhs.len()
+        && lhs
+            .bytes()
+            .zip(rhs.bytes())
+            .fold(0, |diff, (l, r)| diff | (l ^ r))
+            == 0
+}
+
+/// Remove the hosted box file at `path`, if it exists
+fn remove_box_file(path: PathBuf) -> Handled<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn provider_json(ctx: &Context, tag: &BoxTag, version: &str, provider: &ProviderRecord) -> Value {
+    let download_url = if provider.hosted {
+        ctx.box_url(
+            tag,
+            &[
+                "version",
+                version,
+                "provider",
+                provider.name.as_str(),
+                "download",
+            ],
+        )
+    } else {
+        provider.url.clone().unwrap_or_default()
+    };
+    json!({
+        "name": provider.name,
+        "hosted": provider.hosted,
+        "hosted_token": null,
+        "original_url": provider.url,
+        "created_at": provider.created_at,
+        "updated_at": provider.updated_at,
+        "download_url": download_url,
+        "architecture": provider.architecture,
+        "checksum": provider.checksum,
+        "checksum_type": provider.checksum_type,
+    })
+}
+
+fn version_json(ctx: &Context, tag: &BoxTag, version: &VersionRecord) -> Value {
+    json!({
+        "version": version.version,
+        "status": version.status,
+        "description_html": null,
+        "description_markdown": version.description,
+        "created_at": version.created_at,
+        "updated_at": version.updated_at,
+        "number": version.version,
+        "release_url": ctx.box_url(tag, &["version", &version.version, "release"]),
+        "revoke_url": ctx.box_url(tag, &["version", &version.version, "revoke"]),
+        "providers": version
+            .providers
+            .iter()
+            .map(|p| provider_json(ctx, tag, &version.version, p))
+            .collect::<Vec<Value>>(),
+    })
+}
+
+fn box_json(ctx: &Context, record: &BoxRecord) -> Handled<Value> {
+    let tag = BoxTag::new(record.username.as_str(), record.name.as_str())?;
+    let current_version = record
+        .versions
+        .iter()
+        .filter(|v| v.status == "active")
+        .filter_map(|v| v.version.parse::<VersionNumber>().ok().map(|n| (n, v)))
+        .max_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs))
+        .map(|(_, v)| version_json(ctx, &tag, v));
+    Ok(json!({
+        "tag": tag.to_string(),
+        "username": record.username,
+        "name": record.name,
+        "private": record.private,
+        "downloads": record.downloads,
+        "created_at": record.created_at,
+        "updated_at": record.updated_at,
+        "short_description": record.short_description,
+        "description_markdown": record.description,
+        "description_html": null,
+        "versions": record
+            .versions
+            .iter()
+            .map(|v| version_json(ctx, &tag, v))
+            .collect::<Vec<Value>>(),
+        "current_version": current_version,
+    }))
+}
+
+/// A Vagrant Cloud compatible registry server
+pub struct Server {
+    backend: Box<dyn Backend>,
+    /// token -> username
+    tokens: HashMap<String, String>,
+    public_url: Option<String>,
+    /// serializes all modifications of boxes
+    lock: Mutex<()>,
+}
+
+impl std::fmt::Debug for Server {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // never print the tokens
+        write!(
+            f,
+            "Server({} tokens, {:?})",
+            self.tokens.len(),
+            self.public_url
+        )
+    }
+}
+
+impl Server {
+    /// Create a server storing its boxes in `backend` without any tokens,
+    /// i.e. a read only server
+    pub fn new<B: Backend + 'static>(backend: B) -> Server {
+        Server {
+            backend: Box::new(backend),
+            tokens: HashMap::new(),
+            public_url: None,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Allow `token` to modify the boxes of `username`.
+    pub fn with_token<T: Into<String>, U: Into<String>>(mut self, token: T, username: U) -> Server {
+        self.tokens.insert(token.into(), username.into());
+        self
+    }
+
+    /// Use `public_url` in the URLs handed out to clients (e.g. download
+    /// URLs), defaults to `http://` followed by the `Host` of each request.
+    ///
+    /// Set this if the server runs behind a reverse proxy.
+    pub fn with_public_url<S: Into<String>>(mut self, public_url: S) -> Server {
+        self.public_url = Some(public_url.into().trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Serve requests on `addr` until the process is terminated.
+    pub fn serve<A: ToSocketAddrs>(self, addr: A) -> Result<()> {
+        let http = bind(addr)?;
+        Arc::new(self).run(&http);
+        Ok(())
+    }
+
+    /// Serve requests on `addr` in a background thread, which is stopped
+    /// once the returned handle is dropped.
+    ///
+    /// Use the port `0` to let the operating system pick a free port, which
+    /// can be retrieved via the handle.
+    pub fn spawn<A: ToSocketAddrs>(self, addr: A) -> Result<ServerHandle> {
+        let http = Arc::new(bind(addr)?);
+        let addr = http
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| Error::Listen("not listening on an IP address".to_string()))?;
+        let server = Arc::new(self);
+        let thread = {
+            let http = Arc::clone(&http);
+            thread::spawn(move || server.run(&http))
+        };
+        Ok(ServerHandle {
+            addr,
+            http,
+            thread: Some(thread),
+        })
+    }
+
+    /// Hand the requests received by `http` to a fixed number of worker
+    /// threads until the server is unblocked
+    fn run(self: &Arc<Self>, http: &tiny_http::Server) {
+        let (sender, receiver) = mpsc::sync_channel::<Request>(WORKER_THREADS);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers: Vec<JoinHandle<()>> = (0..WORKER_THREADS)
+            .map(|_| {
+                let server = Arc::clone(self);
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let request = receiver
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .recv();
+                    match request {
+                        Ok(request) => server.respond(request),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        for request in http.incoming_requests() {
+            if sender.send(request).is_err() {
+                error!("All worker threads of the registry server exited");
+                break;
+            }
+        }
+        drop(sender);
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }
+
+    /// Lock all boxes for a modification
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn respond(&self, mut request: Request) {
+        let response = match self.context(&request) {
+            Ok(ctx) => self.route(&ctx, request.as_reader()),
+            Err(failure) => Err(failure),
+        };
+        let response = match response {
+            Ok(Reply::Json(value)) => json_response(200, &value),
+            Ok(Reply::File(file)) => Response::from_file(file).boxed(),
+            Err(failure) => json_response(
+                failure.status,
+                &json!({"errors": [failure.message], "success": false}),
+            ),
+        };
+        debug!(
+            "{} {} -> {}",
+            request.method(),
+            request.url(),
+            response.status_code().0
+        );
+        let method = request.method().to_string();
+        let url = request.url().to_string();
+        if let Err(e) = request.respond(response) {
+            warn!("Cannot send the reply to {} {}: {}", method, url, e);
+        }
+    }
+
+    /// Parse the URL and the credentials of `request`
+    fn context(&self, request: &Request) -> Handled<Context> {
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (request.url(), ""),
+        };
+        let decode = |s: &str| {
+            percent_decode_str(s)
+                .decode_utf8()
+                .map(|s| s.into_owned())
+                .or_else(|_| fail(400, "The URL is not valid UTF-8"))
+        };
+        let segments = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(decode)
+            .collect::<Handled<Vec<String>>>()?;
+        let query = query
+            .split('&')
+            .filter(|s| !s.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((decode(key)?, decode(&value.replace('+', " "))?))
+            })
+            .collect::<Handled<HashMap<String, String>>>()?;
+
+        let header = |name: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv(name))
+                .map(|h| h.value.as_str().to_string())
+        };
+        let token = header("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer ").map(|t| t.trim().to_string()))
+            .or_else(|| query.get("access_token").cloned());
+        let user = token.and_then(|t| self.tokens.get(&t).cloned());
+        let base_url = match (&self.public_url, header("Host")) {
+            (Some(url), _) => url.clone(),
+            (None, Some(host)) => format!("http://{}", host),
+            (None, None) => "http://localhost".to_string(),
+        };
+
+        Ok(Context {
+            method: request.method().clone(),
+            segments,
+            query,
+            user,
+            base_url,
+        })
+    }
+
+    fn route(&self, ctx: &Context, body: &mut dyn Read) -> Handled<Reply> {
+        let segments: Vec<&str> = ctx.segments.iter().map(String::as_str).collect();
+        let tag = |username: &str, name: &str| -> Handled<BoxTag> {
+            BoxTag::new(username, name).or_else(|_| not_found())
+        };
+        match (&ctx.method, segments.as_slice()) {
+            (Method::Post, ["api", "v1", "boxes"]) => self.create_box(ctx, body),
+            (method, ["api", "v1", "box", username, name, rest @ ..]) => {
+                let tag = tag(username, name)?;
+                match (method, rest) {
+                    (Method::Get, []) => self.read_box(ctx, &tag),
+                    (Method::Put, []) => self.update_box(ctx, &tag, body),
+                    (Method::Delete, []) => self.delete_box(ctx, &tag),
+                    (Method::Post, ["versions"]) => self.create_version(ctx, &tag, body),
+                    (_, ["version", version, rest @ ..]) => {
+                        self.route_version(ctx, &tag, version, rest, body)
+                    }
+                    _ => not_found(),
+                }
+            }
+            (Method::Get, [username, name]) => {
+                let name = name.strip_suffix(".json").unwrap_or(name);
+                self.catalog(ctx, &tag(username, name)?)
+            }
+            _ => not_found(),
+        }
+    }
+
+    fn route_version(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        rest: &[&str],
+        body: &mut dyn Read,
+    ) -> Handled<Reply> {
+        match (&ctx.method, rest) {
+            (Method::Get, []) => {
+                let mut record = self.load(ctx, tag)?;
+                let version = find_version(&mut record, version)?;
+                Ok(Reply::Json(version_json(ctx, tag, version)))
+            }
+            (Method::Put, []) => self.update_version(ctx, tag, version, body),
+            (Method::Delete, []) => self.delete_version(ctx, tag, version),
+            (Method::Put, ["release"]) => self.change_status(ctx, tag, version, "active"),
+            (Method::Put, ["revoke"]) => self.change_status(ctx, tag, version, "revoked"),
+            (Method::Post, ["providers"]) => self.create_provider(ctx, tag, version, body),
+            (Method::Get, ["provider", provider]) => {
+                let mut record = self.load(ctx, tag)?;
+                let found = find_provider(find_version(&mut record, version)?, provider)?;
+                Ok(Reply::Json(provider_json(ctx, tag, version, found)))
+            }
+            (Method::Put, ["provider", provider]) => {
+                self.update_provider(ctx, tag, version, provider, body)
+            }
+            (Method::Delete, ["provider", provider]) => {
+                self.delete_provider(ctx, tag, version, provider)
+            }
+            (Method::Get, ["provider", provider, "upload"]) => {
+                self.prepare_upload(ctx, tag, version, provider)
+            }
+            (Method::Put, ["provider", provider, "file"]) => {
+                self.upload(ctx, tag, version, provider, body)
+            }
+            (Method::Get, ["provider", provider, "download"]) => {
+                self.download(ctx, tag, version, provider)
+            }
+            _ => not_found(),
+        }
+    }
+
+    /// Read the box `tag`, private boxes are only visible to their owner
+    fn load(&self, ctx: &Context, tag: &BoxTag) -> Handled<BoxRecord> {
+        match self.backend.read_box(tag)? {
+            Some(record) if !record.private || ctx.is_owner(tag.username()) => Ok(record),
+            _ => not_found(),
+        }
+    }
+
+    /// Check that the request may modify the boxes of `tag`'s user
+    fn authorize(&self, ctx: &Context, tag: &BoxTag) -> Handled<()> {
+        match &ctx.user {
+            None => fail(401, "Invalid or missing authentication token"),
+            Some(user) if user != tag.username() => fail(
+                403,
+                format!(
+                    "You are not allowed to modify the boxes of {}",
+                    tag.username()
+                ),
+            ),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Apply `modify` to the box `tag` and store the result, if the request
+    /// may modify it
+    fn modify<F>(&self, ctx: &Context, tag: &BoxTag, modify: F) -> Handled<Reply>
+    where
+        F: FnOnce(&mut BoxRecord) -> Handled<Value>,
+    {
+        self.authorize(ctx, tag)?;
+        self.update(tag, modify)
+    }
+
+    /// Apply `modify` to the box `tag` and store the result
+    fn update<F>(&self, tag: &BoxTag, modify: F) -> Handled<Reply>
+    where
+        F: FnOnce(&mut BoxRecord) -> Handled<Value>,
+    {
+        let _guard = self.lock();
+        let mut record = self.backend.read_box(tag)?.map_or_else(not_found, Ok)?;
+        record.updated_at = now();
+        let reply = modify(&mut record)?;
+        self.backend.write_box(&record)?;
+        Ok(Reply::Json(reply))
+    }
+
+    fn create_box(&self, ctx: &Context, body: &mut dyn Read) -> Handled<Reply> {
+        let payload: BoxPayload = read_payload(body, "box")?;
+        let username = match payload.username.or_else(|| ctx.user.clone()) {
+            Some(u) => u,
+            None => return fail(401, "Invalid or missing authentication token"),
+        };
+        let tag = BoxTag::new(username, payload.name.unwrap_or_default())?;
+        self.authorize(ctx, &tag)?;
+
+        let _guard = self.lock();
+        if self.backend.read_box(&tag)?.is_some() {
+            return fail(409, format!("The box {} already exists", tag));
+        }
+        let record = BoxRecord {
+            username: tag.username().to_string(),
+            name: tag.name().to_string(),
+            short_description: payload.short_description,
+            description: payload.description,
+            private: payload.is_private.unwrap_or(false),
+            downloads: 0,
+            created_at: now(),
+            updated_at: now(),
+            versions: vec![],
+        };
+        self.backend.write_box(&record)?;
+        Ok(Reply::Json(box_json(ctx, &record)?))
+    }
+
+    fn read_box(&self, ctx: &Context, tag: &BoxTag) -> Handled<Reply> {
+        Ok(Reply::Json(box_json(ctx, &self.load(ctx, tag)?)?))
+    }
+
+    fn update_box(&self, ctx: &Context, tag: &BoxTag, body: &mut dyn Read) -> Handled<Reply> {
+        let payload: BoxPayload = read_payload(body, "box")?;
+        self.modify(ctx, tag, |record| {
+            if payload.name.as_deref().is_some_and(|n| n != record.name) {
+                return fail(422, "Boxes cannot be renamed");
+            }
+            if payload.short_description.is_some() {
+                record.short_description = payload.short_description;
+            }
+            if payload.description.is_some() {
+                record.description = payload.description;
+            }
+            if let Some(private) = payload.is_private {
+                record.private = private;
+            }
+            box_json(ctx, record)
+        })
+    }
+
+    fn delete_box(&self, ctx: &Context, tag: &BoxTag) -> Handled<Reply> {
+        self.authorize(ctx, tag)?;
+        let _guard = self.lock();
+        let record = self.load(ctx, tag)?;
+        self.backend.delete_box(tag)?;
+        Ok(Reply::Json(box_json(ctx, &record)?))
+    }
+
+    fn create_version(&self, ctx: &Context, tag: &BoxTag, body: &mut dyn Read) -> Handled<Reply> {
+        let VersionPayload {
+            version,
+            description,
+        } = read_payload(body, "version")?;
+        let version = version.unwrap_or_default();
+        version.parse::<VersionNumber>()?;
+        validate_directory_name(&version)?;
+        self.modify(ctx, tag, |record| {
+            if record.versions.iter().any(|v| v.version == version) {
+                return fail(409, format!("The version {} already exists", version));
+            }
+            let version = VersionRecord {
+                version,
+                status: "unreleased".to_string(),
+                description,
+                created_at: now(),
+                updated_at: now(),
+                providers: vec![],
+            };
+            let reply = version_json(ctx, tag, &version);
+            record.versions.push(version);
+            Ok(reply)
+        })
+    }
+
+    fn update_version(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        body: &mut dyn Read,
+    ) -> Handled<Reply> {
+        let payload: VersionPayload = read_payload(body, "version")?;
+        self.modify(ctx, tag, |record| {
+            let version = find_version(record, version)?;
+            if payload
+                .version
+                .as_deref()
+                .is_some_and(|v| v != version.version)
+            {
+                return fail(422, "Versions cannot be renumbered");
+            }
+            if payload.description.is_some() {
+                version.description = payload.description;
+            }
+            version.updated_at = now();
+            Ok(version_json(ctx, tag, version))
+        })
+    }
+
+    fn delete_version(&self, ctx: &Context, tag: &BoxTag, version: &str) -> Handled<Reply> {
+        self.modify(ctx, tag, |record| {
+            let reply = version_json(ctx, tag, find_version(record, version)?);
+            let index = record
+                .versions
+                .iter()
+                .position(|v| v.version == version)
+                .unwrap_or_default();
+            for provider in record.versions.remove(index).providers {
+                remove_box_file(self.backend.box_file(tag, version, &provider.name))?;
+            }
+            Ok(reply)
+        })
+    }
+
+    /// Release (`status` is `active`) or revoke (`revoked`) `version`
+    fn change_status(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        status: &str,
+    ) -> Handled<Reply> {
+        self.modify(ctx, tag, |record| {
+            let version = find_version(record, version)?;
+            match (status, version.status.as_str()) {
+                ("active", "active") => return fail(422, "The version is already released"),
+                ("active", _) if version.providers.iter().all(ProviderRecord::is_pending) => {
+                    return fail(422, "The version has no downloadable providers")
+                }
+                ("revoked", s) if s != "active" => {
+                    return fail(422, "Only released versions can be revoked")
+                }
+                _ => (),
+            }
+            version.status = status.to_string();
+            version.updated_at = now();
+            Ok(version_json(ctx, tag, version))
+        })
+    }
+
+    fn create_provider(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        body: &mut dyn Read,
+    ) -> Handled<Reply> {
+        let ProviderPayload {
+            name,
+            url,
+            checksum_type,
+            checksum,
+            architecture,
+        } = read_payload(body, "provider")?;
+        let name = match name {
+            Some(n) => n,
+            None => return fail(422, "The provider has no name"),
+        };
+        validate_directory_name(name.as_str())?;
+        validate_checksum_type(&checksum_type)?;
+        self.modify(ctx, tag, |record| {
+            let version = find_version(record, version)?;
+            if version.providers.iter().any(|p| p.name == name) {
+                return fail(409, format!("The provider {} already exists", name));
+            }
+            let provider = ProviderRecord {
+                name,
+                url: url.filter(|u| !u.is_empty()),
+                hosted: false,
+                checksum_type,
+                checksum,
+                architecture,
+                upload_token: None,
+                created_at: now(),
+                updated_at: now(),
+            };
+            validate_checksum(&provider)?;
+            let reply = provider_json(ctx, tag, &version.version, &provider);
+            version.providers.push(provider);
+            version.updated_at = now();
+            Ok(reply)
+        })
+    }
+
+    fn update_provider(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        provider: &str,
+        body: &mut dyn Read,
+    ) -> Handled<Reply> {
+        let payload: ProviderPayload = read_payload(body, "provider")?;
+        validate_checksum_type(&payload.checksum_type)?;
+        self.modify(ctx, tag, |record| {
+            let version = find_version(record, version)?;
+            let version_number = version.version.clone();
+            let provider = find_provider(version, provider)?;
+            if payload.name.as_ref().is_some_and(|n| *n != provider.name) {
+                return fail(422, "Providers cannot be renamed");
+            }
+            if let Some(url) = payload.url.filter(|u| !u.is_empty()) {
+                // the box file is no longer hosted by the server
+                if provider.hosted {
+                    remove_box_file(self.backend.box_file(tag, &version_number, &provider.name))?;
+                    provider.hosted = false;
+                }
+                provider.url = Some(url);
+            }
+            if payload.checksum_type.is_some() {
+                provider.checksum_type = payload.checksum_type;
+            }
+            if payload.checksum.is_some() {
+                provider.checksum = payload.checksum;
+            }
+            validate_checksum(provider)?;
+            if payload.architecture.is_some() {
+                provider.architecture = payload.architecture;
+            }
+            provider.updated_at = now();
+            Ok(provider_json(ctx, tag, &version_number, provider))
+        })
+    }
+
+    fn delete_provider(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        provider: &str,
+    ) -> Handled<Reply> {
+        self.modify(ctx, tag, |record| {
+            let found = find_version(record, version)?;
+            let removed = find_provider(found, provider)?;
+            let reply = provider_json(ctx, tag, version, removed);
+            let name = removed.name.clone();
+            found.providers.retain(|p| p.name != name);
+            remove_box_file(self.backend.box_file(tag, version, &name))?;
+            Ok(reply)
+        })
+    }
+
+    /// Hand out an upload path for the box file of `provider`
+    fn prepare_upload(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        provider: &str,
+    ) -> Handled<Reply> {
+        self.modify(ctx, tag, |record| {
+            let version = find_version(record, version)?;
+            let version_number = version.version.clone();
+            let provider = find_provider(version, provider)?;
+            let token = upload_token()?;
+            provider.upload_token = Some(token.clone());
+            let path = ctx.box_url(
+                tag,
+                &[
+                    "version",
+                    &version_number,
+                    "provider",
+                    provider.name.as_str(),
+                    "file",
+                ],
+            );
+            Ok(json!({ "upload_path": format!("{}?token={}", path, token) }))
+        })
+    }
+
+    /// Store the uploaded box file of `provider`
+    ///
+    /// Uploads are authorized via the token in the upload path. The box file
+    /// is verified against the checksum of the provider, if it has one,
+    /// otherwise its SHA-256 checksum is recorded.
+    fn upload(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        provider: &str,
+        body: &mut dyn Read,
+    ) -> Handled<Reply> {
+        let is_authorized = |record: &mut BoxRecord| -> Handled<ProviderRecord> {
+            let provider = find_provider(find_version(record, version)?, provider)?;
+            match (&provider.upload_token, ctx.query.get("token")) {
+                (Some(expected), Some(token)) if constant_time_eq(expected, token) => {
+                    Ok(provider.clone())
+                }
+                _ => fail(403, "Invalid or expired upload token"),
+            }
+        };
+        let mut record = self.backend.read_box(tag)?.map_or_else(not_found, Ok)?;
+        let expected = is_authorized(&mut record)?;
+        let checksum_type = match &expected.checksum_type {
+            Some(t) => t.parse()?,
+            None => ChecksumType::Sha256,
+        };
+
+        // concurrent uploads with the same token must not write to the same
+        // file, only the first one to finish is stored
+        let path = self.backend.box_file(tag, version, &expected.name);
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(format!(".{}.partial", random_hex(8)?));
+        let partial = PathBuf::from(partial);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut reader = HashingReader::new(body, checksum_type);
+        let copied = File::create(&partial).and_then(|mut f| io::copy(&mut reader, &mut f));
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&partial);
+            return Err(e.into());
+        }
+        let checksum = reader.checksum();
+        if expected
+            .checksum
+            .as_deref()
+            .is_some_and(|c| !c.eq_ignore_ascii_case(&checksum.checksum))
+        {
+            let _ = fs::remove_file(&partial);
+            return fail(
+                422,
+                format!("The box file does not match its checksum ({})", checksum),
+            );
+        }
+
+        // the box file is only moved into place once the record is stored,
+        // the record is restored if that fails
+        let store = || -> Handled<Reply> {
+            let _guard = self.lock();
+            let original = self.backend.read_box(tag)?.map_or_else(not_found, Ok)?;
+            let mut record = original.clone();
+            is_authorized(&mut record)?;
+            let version = find_version(&mut record, version)?;
+            let version_number = version.version.clone();
+            let provider = find_provider(version, provider)?;
+            provider.hosted = true;
+            provider.url = None;
+            provider.upload_token = None;
+            provider.checksum_type = Some(checksum.checksum_type.to_string());
+            provider.checksum = Some(checksum.checksum.clone());
+            provider.updated_at = now();
+            let reply = provider_json(ctx, tag, &version_number, provider);
+            record.updated_at = now();
+            self.backend.write_box(&record)?;
+            if let Err(e) = fs::rename(&partial, &path) {
+                self.backend.write_box(&original)?;
+                return Err(e.into());
+            }
+            Ok(Reply::Json(reply))
+        };
+        let res = store();
+        if res.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        res
+    }
+
+    fn download(
+        &self,
+        ctx: &Context,
+        tag: &BoxTag,
+        version: &str,
+        provider: &str,
+    ) -> Handled<Reply> {
+        let mut record = self.load(ctx, tag)?;
+        let found = find_provider(find_version(&mut record, version)?, provider)?;
+        if !found.hosted {
+            return not_found();
+        }
+        let file = match File::open(self.backend.box_file(tag, version, &found.name)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return not_found(),
+            Err(e) => return Err(e.into()),
+        };
+        self.backend.increment_downloads(tag)?;
+        Ok(Reply::File(file))
+    }
+
+    /// The catalog of the released versions of `tag` for `vagrant box add`
+    fn catalog(&self, ctx: &Context, tag: &BoxTag) -> Handled<Reply> {
+        let record = self.load(ctx, tag)?;
+        let mut catalog = Catalog::new(tag.to_string());
+        catalog.description = record.short_description.clone();
+        for version in record.versions.iter().filter(|v| v.status == "active") {
+            for provider in version.providers.iter().filter(|p| !p.is_pending()) {
+                let json = provider_json(ctx, tag, &version.version, provider);
+                catalog.add_provider(
+                    &version.version,
+                    CatalogProvider {
+                        name: provider.name.clone(),
+                        url: json["download_url"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string(),
+                        checksum_type: provider.checksum_type.clone(),
+                        checksum: provider.checksum.clone(),
+                        architecture: provider.architecture.clone(),
+                    },
+                )?;
+            }
+        }
+        for version in &mut catalog.versions {
+            version.description = record
+                .versions
+                .iter()
+                .find(|v| v.version == version.version)
+                .and_then(|v| v.description.clone());
+        }
+        let value = serde_json::to_value(&catalog)
+            .map_err(|e| Error::InternalError(format!("cannot serialize the catalog: {}", e)))?;
+        Ok(Reply::Json(value))
+    }
+}
+
+fn bind<A: ToSocketAddrs>(addr: A) -> Result<tiny_http::Server> {
+    tiny_http::Server::http(addr).map_err(|e| Error::Listen(e.to_string()))
+}
+
+fn json_response(status: u16, value: &Value) -> ResponseBox {
+    let response =
+        Response::from_data(serde_json::to_vec(value).unwrap_or_default()).with_status_code(status);
+    match Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]) {
+        Ok(header) => response.with_header(header).boxed(),
+        Err(()) => response.boxed(),
+    }
+}
+
+/// Handle to a [`Server`](struct.Server.html) running in the background,
+/// which is stopped when the handle is dropped
+pub struct ServerHandle {
+    addr: SocketAddr,
+    http: Arc<tiny_http::Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ServerHandle({})", self.addr)
+    }
+}
+
+impl ServerHandle {
+    /// The address on which the server listens
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of the server, e.g. for `VAGRANT_SERVER_URL`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The base URL of the API for
+    /// [`Client::with_base_url`](../struct.Client.html#method.with_base_url)
+    pub fn api_url(&self) -> String {
+        format!("{}/api/v1", self.url())
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.http.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Backend storing boxes in plain files
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::{Backend, BoxRecord};
+use crate::{BoxTag, Error, ProviderName, Result};
+
+#[derive(Debug, Clone)]
+/// A [`Backend`](trait.Backend.html) storing each box in a directory below
+/// its root:
+///
+/// ```text
+/// my_user/awesome_box/box.json            the box with all versions
+/// my_user/awesome_box/downloads           the number of downloads
+/// my_user/awesome_box/1.0.0/libvirt.box   hosted box files
+/// ```
+pub struct FilesystemBackend {
+    root: PathBuf,
+    /// Serializes the updates of the download counters
+    downloads: Arc<Mutex<()>>,
+}
+
+impl FilesystemBackend {
+    /// Store all boxes below the directory `root`, which is created if
+    /// necessary
+    pub fn new<P: AsRef<Path>>(root: P) -> FilesystemBackend {
+        FilesystemBackend {
+            root: root.as_ref().to_path_buf(),
+            downloads: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn box_dir(&self, tag: &BoxTag) -> PathBuf {
+        self.root.join(tag.username()).join(tag.name())
+    }
+
+    /// Read the number of downloads of the box in `dir`
+    fn read_downloads(dir: &Path) -> Result<u64> {
+        let path = dir.join("downloads");
+        match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .trim()
+                .parse()
+                .map_err(|e| Error::Storage(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Backend for FilesystemBackend {
+    fn read_box(&self, tag: &BoxTag) -> Result<Option<BoxRecord>> {
+        let path = self.box_dir(tag).join("box.json");
+        let mut record: BoxRecord = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| Error::Storage(format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        record.downloads = FilesystemBackend::read_downloads(&self.box_dir(tag))?;
+        Ok(Some(record))
+    }
+
+    fn write_box(&self, record: &BoxRecord) -> Result<()> {
+        let tag = BoxTag::new(record.username.as_str(), record.name.as_str())?;
+        let dir = self.box_dir(&tag);
+        fs::create_dir_all(&dir)?;
+        let contents = serde_json::to_vec_pretty(record)
+            .map_err(|e| Error::InternalError(format!("cannot serialize the box: {}", e)))?;
+        let partial = dir.join("box.json.partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, dir.join("box.json"))?;
+        Ok(())
+    }
+
+    fn increment_downloads(&self, tag: &BoxTag) -> Result<()> {
+        let _guard = self
+            .downloads
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = self.box_dir(tag);
+        let downloads = FilesystemBackend::read_downloads(&dir)? + 1;
+        let partial = dir.join("downloads.partial");
+        fs::write(&partial, downloads.to_string())?;
+        fs::rename(&partial, dir.join("downloads"))?;
+        Ok(())
+    }
+
+    fn delete_box(&self, tag: &BoxTag) -> Result<()> {
+        match fs::remove_dir_all(self.box_dir(tag)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn box_file(&self, tag: &BoxTag, version: &str, provider: &ProviderName) -> PathBuf {
+        self.box_dir(tag)
+            .join(version)
+            .join(format!("{}.box", provider))
+    }
+}
@@ -0,0 +1,144 @@
+//! Backend storing boxes in a SQLite database
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{Backend, BoxRecord};
+use crate::{BoxTag, Error, ProviderName, Result};
+
+/// Convert an error of SQLite
+fn storage_error(err: rusqlite::Error) -> Error {
+    Error::Storage(err.to_string())
+}
+
+/// A [`Backend`](trait.Backend.html) storing all boxes in a SQLite database
+/// and the hosted box files in a directory
+#[derive(Debug)]
+pub struct SqliteBackend {
+    connection: Mutex<Connection>,
+    files: PathBuf,
+}
+
+impl SqliteBackend {
+    /// Open (or create) the database at `database` and store the hosted box
+    /// files below the directory `files`
+    pub fn open<D: AsRef<Path>, F: AsRef<Path>>(database: D, files: F) -> Result<SqliteBackend> {
+        let connection = Connection::open(database).map_err(storage_error)?;
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS boxes (
+                    username TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    record TEXT NOT NULL,
+                    PRIMARY KEY (username, name)
+                )",
+                [],
+            )
+            .map_err(storage_error)?;
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS downloads (
+                    username TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (username, name)
+                )",
+                [],
+            )
+            .map_err(storage_error)?;
+        Ok(SqliteBackend {
+            connection: Mutex::new(connection),
+            files: files.as_ref().to_path_buf(),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn box_dir(&self, tag: &BoxTag) -> PathBuf {
+        self.files.join(tag.username()).join(tag.name())
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn read_box(&self, tag: &BoxTag) -> Result<Option<BoxRecord>> {
+        let record: Option<(String, Option<i64>)> = self
+            .connection()
+            .query_row(
+                "SELECT record, count FROM boxes
+                    LEFT JOIN downloads USING (username, name)
+                    WHERE username = ?1 AND name = ?2",
+                params![tag.username(), tag.name()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(storage_error)?;
+        record
+            .map(|(r, downloads)| {
+                let mut record: BoxRecord = serde_json::from_str(&r)
+                    .map_err(|e| Error::Storage(format!("{}: {}", tag, e)))?;
+                record.downloads = downloads.unwrap_or(0) as u64;
+                Ok(record)
+            })
+            .transpose()
+    }
+
+    fn write_box(&self, record: &BoxRecord) -> Result<()> {
+        let contents = serde_json::to_string(record)
+            .map_err(|e| Error::InternalError(format!("cannot serialize the box: {}", e)))?;
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO boxes (username, name, record) VALUES (?1, ?2, ?3)",
+                params![record.username, record.name, contents],
+            )
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn increment_downloads(&self, tag: &BoxTag) -> Result<()> {
+        let connection = self.connection();
+        connection
+            .execute(
+                "INSERT OR IGNORE INTO downloads (username, name, count) VALUES (?1, ?2, 0)",
+                params![tag.username(), tag.name()],
+            )
+            .map_err(storage_error)?;
+        connection
+            .execute(
+                "UPDATE downloads SET count = count + 1 WHERE username = ?1 AND name = ?2",
+                params![tag.username(), tag.name()],
+            )
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn delete_box(&self, tag: &BoxTag) -> Result<()> {
+        let connection = self.connection();
+        for table in &["boxes", "downloads"] {
+            connection
+                .execute(
+                    &format!("DELETE FROM {} WHERE username = ?1 AND name = ?2", table),
+                    params![tag.username(), tag.name()],
+                )
+                .map_err(storage_error)?;
+        }
+        drop(connection);
+        match fs::remove_dir_all(self.box_dir(tag)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn box_file(&self, tag: &BoxTag, version: &str, provider: &ProviderName) -> PathBuf {
+        self.box_dir(tag)
+            .join(version)
+            .join(format!("{}.box", provider))
+    }
+}
@@ -0,0 +1,363 @@
+//! # Site module
+//!
+//! A [`Site`](struct.Site.html) is a directory tree that serves boxes
+//! without Vagrant Cloud from any static web server or S3 bucket:
+//!
+//! ```text
+//! index.json                       all boxes with their latest version
+//! index.html                       human readable listing of all boxes
+//! my_user/awesome_box.json         catalog of my_user/awesome_box
+//! my_user/awesome_box/1.0.0/*.box  box files added via add_box_file()
+//! ```
+//!
+//! Boxes are then added via `vagrant box add https://host/my_user/awesome_box.json`.
+//!
+//! ```no_run
+//! # use vagabond::catalog::Catalog;
+//! # use vagabond::site::Site;
+//! let site = Site::new("public", "https://boxes.example.com");
+//!
+//! // add a new version to the box, keeping the existing versions
+//! let mut catalog = site
+//!     .read_catalog("my_user/awesome_box")
+//!     .unwrap()
+//!     .unwrap_or_else(|| Catalog::new("my_user/awesome_box"));
+//! site.add_box_file(&mut catalog, "1.1.0", "output/awesome_box.libvirt.box")
+//!     .unwrap();
+//!
+//! let report = site.update(&[catalog]).unwrap();
+//! report.written.iter().for_each(|p| println!("updated {}", p.display()));
+//! ```
+//!
+//! Updates are incremental: files are only written if their contents
+//! changed, and the index covers all catalogs in the tree, not only the ones
+//! passed to [`update`](struct.Site.html#method.update).
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::catalog::{read_box_file, Catalog, CatalogProvider};
+use super::checksum::{self, ChecksumType};
+use super::constraint::VersionNumber;
+use super::{api, BoxTag, Error, Result};
+
+/// Characters that are percent-encoded in the path segments of URLs
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Contents of the `index.json` of a site
+pub struct SiteIndex {
+    /// All boxes of the site, sorted by name
+    pub boxes: Vec<IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A box in the [`SiteIndex`](struct.SiteIndex.html)
+pub struct IndexEntry {
+    /// The name of the box, e.g. `my_user/awesome_box`
+    pub name: String,
+    /// The description of the box
+    pub description: Option<String>,
+    /// The highest version of the box
+    pub latest_version: Option<String>,
+    /// The URL of the box's catalog
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The files touched by an update of a [`Site`](struct.Site.html)
+pub struct Report {
+    /// Files that were created or modified
+    pub written: Vec<PathBuf>,
+    /// Files that already had the desired contents
+    pub unchanged: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+/// A static site serving box catalogs and box files
+pub struct Site {
+    root: PathBuf,
+    base_url: String,
+}
+
+/// Check that `name` (e.g. a version number) can be used as a directory name
+pub(crate) fn validate_directory_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.chars().all(|c| c == '.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.+~".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidPathSegment(name.to_string()))
+    }
+}
+
+/// Escape `text` for the use in HTML
+fn escape_html(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+impl Site {
+    /// Create a site in the directory `root` that is served at `base_url`
+    pub fn new<P: AsRef<Path>, S: Into<String>>(root: P, base_url: S) -> Site {
+        Site {
+            root: root.as_ref().to_path_buf(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The URL of the file at `segments` below the root
+    fn url(&self, segments: &[&str]) -> String {
+        segments.iter().fold(self.base_url.clone(), |url, seg| {
+            format!("{}/{}", url, utf8_percent_encode(seg, PATH_SEGMENT))
+        })
+    }
+
+    /// The path of the catalog of the box `name` (e.g. `my_user/awesome_box`)
+    pub fn catalog_path(&self, name: &str) -> Result<PathBuf> {
+        let tag: BoxTag = name.parse()?;
+        Ok(self
+            .root
+            .join(tag.username())
+            .join(format!("{}.json", tag.name())))
+    }
+
+    /// The URL of the catalog of the box `name`, which can be passed to
+    /// `vagrant box add`
+    pub fn catalog_url(&self, name: &str) -> Result<String> {
+        let tag: BoxTag = name.parse()?;
+        Ok(self.url(&[tag.username(), &format!("{}.json", tag.name())]))
+    }
+
+    /// Read the catalog of the box `name`, `None` if the site doesn't contain
+    /// it yet.
+    pub fn read_catalog(&self, name: &str) -> Result<Option<Catalog>> {
+        let path = self.catalog_path(name)?;
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|e| Error::InvalidCatalog(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Copy the `.box` file at `path` into the site below the version
+    /// `version` of the box of `catalog` and add it to `catalog`.
+    ///
+    /// The file is only copied if the site doesn't already contain an
+    /// identical file. A provider of `version` with the same name and
+    /// architecture is replaced, so that adding the same files again or
+    /// replacing a box file of an existing version works as expected.
+    /// `catalog` has to be written via [`update`](#method.update) afterwards.
+    pub fn add_box_file<P: AsRef<Path>>(
+        &self,
+        catalog: &mut Catalog,
+        version: &str,
+        path: P,
+    ) -> Result<Report> {
+        let path = path.as_ref();
+        let tag: BoxTag = catalog.name.parse()?;
+        validate_directory_name(version)?;
+        let file_name = path.file_name().and_then(|n| n.to_str()).ok_or_else(|| {
+            Error::InvalidBoxFile(format!("'{}' has no valid file name", path.display()))
+        })?;
+
+        let dir = self
+            .root
+            .join(tag.username())
+            .join(tag.name())
+            .join(version);
+        let destination = dir.join(file_name);
+        let url = self.url(&[tag.username(), tag.name(), version, file_name]);
+        let (metadata, checksum) = read_box_file(path)?;
+        let provider = CatalogProvider {
+            name: metadata.provider,
+            url,
+            checksum_type: Some(checksum.checksum_type.to_string()),
+            checksum: Some(checksum.checksum.clone()),
+            architecture: metadata.architecture,
+        };
+
+        // the catalog already describes an identical copy => don't hash it
+        let listed = catalog
+            .versions
+            .iter()
+            .filter(|v| v.version == version)
+            .flat_map(|v| v.providers.iter())
+            .any(|p| *p == provider);
+        let size = |path: &Path| fs::metadata(path).map(|m| m.len()).ok();
+        let unchanged = size(&destination).is_some_and(|s| Some(s) == size(path))
+            && (listed
+                || checksum::checksum_file(&destination, ChecksumType::Sha256, |_, _| ())?
+                    == checksum);
+
+        let mut report = Report::default();
+        if unchanged {
+            report.unchanged.push(destination.clone());
+        } else {
+            fs::create_dir_all(&dir)?;
+            let mut partial = destination.as_os_str().to_owned();
+            partial.push(".partial");
+            fs::copy(path, &partial)?;
+            fs::rename(&partial, &destination)?;
+            report.written.push(destination.clone());
+        }
+
+        catalog.set_provider(version, provider);
+        Ok(report)
+    }
+
+    /// Write `contents` to `path` unless it already has these contents
+    fn write_if_changed(&self, path: &Path, contents: &[u8], report: &mut Report) -> Result<()> {
+        let mut current = vec![];
+        let unchanged = File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut current))
+            .map(|_| current == contents)
+            .unwrap_or(false);
+        if unchanged {
+            report.unchanged.push(path.to_path_buf());
+            return Ok(());
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)?;
+        report.written.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// All catalogs in the site, sorted by name
+    ///
+    /// Files that are no catalogs are skipped.
+    pub fn catalogs(&self) -> Result<Vec<Catalog>> {
+        let mut catalogs = vec![];
+        let users = match fs::read_dir(&self.root) {
+            Ok(users) => users,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(catalogs),
+            Err(e) => return Err(e.into()),
+        };
+        for user in users {
+            let user = user?;
+            if !user.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(user.path())? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "json") || !path.is_file() {
+                    continue;
+                }
+                match serde_json::from_str::<Catalog>(&fs::read_to_string(&path)?) {
+                    Ok(catalog) => catalogs.push(catalog),
+                    Err(e) => warn!("Skipping {}, which is no catalog: {}", path.display(), e),
+                }
+            }
+        }
+        catalogs.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+        Ok(catalogs)
+    }
+
+    /// The index of all `catalogs`
+    fn index(&self, catalogs: &[Catalog]) -> Result<SiteIndex> {
+        let boxes = catalogs
+            .iter()
+            .map(|catalog| {
+                let latest_version = catalog
+                    .versions
+                    .iter()
+                    .filter_map(|v| v.version.parse::<VersionNumber>().ok())
+                    .max()
+                    .map(|v| v.to_string());
+                Ok(IndexEntry {
+                    name: catalog.name.clone(),
+                    description: catalog.description.clone(),
+                    latest_version,
+                    url: self.catalog_url(&catalog.name)?,
+                })
+            })
+            .collect::<Result<Vec<IndexEntry>>>()?;
+        Ok(SiteIndex { boxes })
+    }
+
+    /// Render `index` as HTML page
+    fn render_html(index: &SiteIndex) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Vagrant boxes</title>\n</head>\n<body>\n<h1>Vagrant boxes</h1>\n<ul>\n",
+        );
+        for entry in &index.boxes {
+            html.push_str(&format!(
+                "<li><a href=\"{url}\">{name}</a>",
+                url = escape_html(&entry.url),
+                name = escape_html(&entry.name)
+            ));
+            if let Some(version) = &entry.latest_version {
+                html.push_str(&format!(" {}", escape_html(version)));
+            }
+            if let Some(description) = &entry.description {
+                html.push_str(&format!(" - {}", escape_html(description)));
+            }
+            html.push_str(&format!(
+                "<br><code>vagrant box add {}</code></li>\n",
+                escape_html(&entry.url)
+            ));
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+        html
+    }
+
+    /// Write `catalogs` to the site and regenerate the index of all boxes.
+    ///
+    /// Catalogs of other boxes that are already part of the site are kept.
+    pub fn update(&self, catalogs: &[Catalog]) -> Result<Report> {
+        let mut report = Report::default();
+        for catalog in catalogs {
+            let contents = serde_json::to_vec_pretty(catalog).map_err(|e| {
+                Error::InternalError(format!("cannot serialize the catalog: {}", e))
+            })?;
+            self.write_if_changed(&self.catalog_path(&catalog.name)?, &contents, &mut report)?;
+        }
+
+        let index = self.index(&self.catalogs()?)?;
+        let contents = serde_json::to_vec_pretty(&index)
+            .map_err(|e| Error::InternalError(format!("cannot serialize the index: {}", e)))?;
+        self.write_if_changed(&self.root.join("index.json"), &contents, &mut report)?;
+        self.write_if_changed(
+            &self.root.join("index.html"),
+            Site::render_html(&index).as_bytes(),
+            &mut report,
+        )?;
+        Ok(report)
+    }
+
+    /// Write the catalogs of `boxes` from Vagrant Cloud to the site, see
+    /// [`update`](#method.update).
+    ///
+    /// The catalogs refer to the box files hosted on Vagrant Cloud.
+    pub fn update_from_api(&self, boxes: &[api::VagrantBox]) -> Result<Report> {
+        let catalogs: Vec<Catalog> = boxes.iter().map(Catalog::from).collect();
+        self.update(&catalogs)
+    }
+}
@@ -0,0 +1,400 @@
+//! # Store module
+//!
+//! Vagrant keeps the boxes installed via `vagrant box add` in its local box
+//! store (`~/.vagrant.d/boxes` or `$VAGRANT_HOME/boxes`):
+//!
+//! ```text
+//! my_user-VAGRANTSLASH-awesome_box/metadata_url        URL of the box's catalog
+//! my_user-VAGRANTSLASH-awesome_box/1.0.0/libvirt/      extracted box file
+//! my_user-VAGRANTSLASH-awesome_box/1.1.0/amd64/libvirt/
+//! ```
+//!
+//! The `/` in the name of a box is stored as `-VAGRANTSLASH-` (and `:` as
+//! `-VAGRANTCOLON-`). Boxes with an architecture are stored in an additional
+//! directory named after it.
+//!
+//! A [`Store`](struct.Store.html) lists, installs and removes boxes and
+//! finds installed boxes that have newer releases:
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # use vagabond::store::Store;
+//! let store = Store::new(Store::default_location().unwrap());
+//! for installed in store.installed().unwrap() {
+//!     println!("{} {} ({})", installed.name, installed.version, installed.provider);
+//! }
+//!
+//! let client = Client::new(None as Option<String>);
+//! let username = "my_user".to_string();
+//! let box_name = "awesome_box".to_string();
+//! let vagrant_box = client.read_box(&VagrantBox::new(&username, &box_name)).unwrap();
+//! for outdated in store.outdated(&[vagrant_box]).unwrap() {
+//!     println!(
+//!         "{} can be updated from {} to {}",
+//!         outdated.installed.name, outdated.installed.version, outdated.latest
+//!     );
+//! }
+//! ```
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::boxfile::{BoxFile, Metadata};
+use super::constraint::{VersionConstraint, VersionNumber};
+use super::site::validate_directory_name;
+use super::{api, Error, ProviderName, Result};
+
+/// Name of the file in which Vagrant stores the catalog URL of a box
+const METADATA_URL: &str = "metadata_url";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A box installed in a [`Store`](struct.Store.html)
+pub struct InstalledBox {
+    /// The name of the box, e.g. `my_user/awesome_box`
+    pub name: String,
+    /// The installed version
+    pub version: String,
+    /// The provider of the installed box
+    pub provider: ProviderName,
+    /// The architecture of the installed box, if it was installed with one
+    pub architecture: Option<String>,
+    /// The URL of the catalog from which the box was installed
+    pub metadata_url: Option<String>,
+    /// The directory containing the extracted box
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An installed box that has a newer release
+pub struct Outdated {
+    /// The highest installed version of the box
+    pub installed: InstalledBox,
+    /// The latest released version of the box for the installed provider
+    /// and architecture
+    pub latest: String,
+}
+
+/// Escape `name` like Vagrant does for the name of the box's directory
+fn escape(name: &str) -> Result<String> {
+    let escaped = name
+        .replace('/', "-VAGRANTSLASH-")
+        .replace(':', "-VAGRANTCOLON-");
+    if escaped.is_empty() || escaped == "." || escaped == ".." || escaped.contains(['\\', '\0']) {
+        return Err(Error::InvalidPathSegment(name.to_string()));
+    }
+    Ok(escaped)
+}
+
+/// The reverse of `escape()`
+fn unescape(name: &str) -> String {
+    name.replace("-VAGRANTSLASH-", "/")
+        .replace("-VAGRANTCOLON-", ":")
+}
+
+/// The directories in `dir` that are not hidden, sorted by name
+fn subdirectories(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![];
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(dirs),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if entry.file_type()?.is_dir() && !hidden {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Read the `metadata.json` in `dir`, `None` if `dir` contains no box
+fn read_metadata(dir: &Path) -> Result<Option<Metadata>> {
+    let path = dir.join("metadata.json");
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| Error::InvalidBoxFile(format!("{}: {}", path.display(), e))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Compare two version numbers, unparsable ones are sorted first
+fn compare_versions(lhs: &str, rhs: &str) -> std::cmp::Ordering {
+    match (lhs.parse::<VersionNumber>(), rhs.parse::<VersionNumber>()) {
+        (Ok(l), Ok(r)) => l.cmp(&r),
+        (Ok(_), Err(_)) => std::cmp::Ordering::Greater,
+        (Err(_), Ok(_)) => std::cmp::Ordering::Less,
+        (Err(_), Err(_)) => lhs.cmp(rhs),
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Vagrant's local box store
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    /// Use the box store in the directory `root`
+    pub fn new<P: AsRef<Path>>(root: P) -> Store {
+        Store {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The box store used by Vagrant: `$VAGRANT_HOME/boxes` if
+    /// `VAGRANT_HOME` is set, `~/.vagrant.d/boxes` otherwise, `None` if the
+    /// home directory is unknown.
+    pub fn default_location() -> Option<PathBuf> {
+        env::var_os("VAGRANT_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".vagrant.d")))
+            .map(|home| home.join("boxes"))
+    }
+
+    /// The directory of the box `name`
+    fn box_dir(&self, name: &str) -> Result<PathBuf> {
+        Ok(self.root.join(escape(name)?))
+    }
+
+    /// A new directory in the root of the store for extracting a box, which
+    /// is hidden from Vagrant and `installed()`
+    fn temp_dir(&self) -> Result<PathBuf> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        fs::create_dir_all(&self.root)?;
+        let dir = self.root.join(format!(
+            ".tmp-vagabond-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir(&dir)?;
+        Ok(dir)
+    }
+
+    /// All installed boxes, sorted by name, version, provider and
+    /// architecture
+    ///
+    /// Directories that don't contain a box are skipped.
+    pub fn installed(&self) -> Result<Vec<InstalledBox>> {
+        let mut installed = vec![];
+        for box_dir in subdirectories(&self.root)? {
+            let name = match box_dir.file_name().and_then(|n| n.to_str()) {
+                Some(n) => unescape(n),
+                None => continue,
+            };
+            let metadata_url = match fs::read_to_string(box_dir.join(METADATA_URL)) {
+                Ok(url) => Some(url.trim().to_string()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            for version_dir in subdirectories(&box_dir)? {
+                let version = match version_dir.file_name().and_then(|n| n.to_str()) {
+                    Some(v) => v.to_string(),
+                    None => continue,
+                };
+                let mut found = |path: PathBuf, metadata: Metadata, arch: Option<String>| {
+                    installed.push(InstalledBox {
+                        name: name.clone(),
+                        version: version.clone(),
+                        provider: metadata.provider,
+                        architecture: arch.or(metadata.architecture),
+                        metadata_url: metadata_url.clone(),
+                        path,
+                    })
+                };
+                for dir in subdirectories(&version_dir)? {
+                    // either <version>/<provider> or <version>/<arch>/<provider>
+                    // (some Vagrant releases use <version>/<provider>/<arch>)
+                    if let Some(metadata) = read_metadata(&dir)? {
+                        found(dir, metadata, None);
+                        continue;
+                    }
+                    let dir_name = |dir: &Path| {
+                        dir.file_name()
+                            .and_then(|n| n.to_str())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    let outer = dir_name(&dir);
+                    for inner_dir in subdirectories(&dir)? {
+                        if let Some(metadata) = read_metadata(&inner_dir)? {
+                            let inner = dir_name(&inner_dir);
+                            let arch = if metadata.provider == outer.as_str() {
+                                inner
+                            } else {
+                                outer.clone()
+                            };
+                            found(inner_dir, metadata, Some(arch));
+                        }
+                    }
+                }
+            }
+        }
+        installed.sort_by(|lhs, rhs| {
+            lhs.name
+                .cmp(&rhs.name)
+                .then_with(|| compare_versions(&lhs.version, &rhs.version))
+                .then_with(|| lhs.provider.cmp(&rhs.provider))
+                .then_with(|| lhs.architecture.cmp(&rhs.architecture))
+        });
+        Ok(installed)
+    }
+
+    /// Install the downloaded `.box` file at `path` as the version `version`
+    /// of the box `name`, like `vagrant box add` does.
+    ///
+    /// The provider and architecture are read from the box's
+    /// `metadata.json`. `metadata_url` is the URL of the catalog from which
+    /// the box was obtained, which Vagrant uses to check for updates.
+    /// Returns an `Error::BoxAlreadyInstalled` if this provider (and
+    /// architecture) of `version` is already installed.
+    ///
+    /// The box is extracted into a hidden directory in the root of the store
+    /// and only moved into place once it is complete, nothing is left in
+    /// the box's directory if the installation fails.
+    pub fn install<P: AsRef<Path>>(
+        &self,
+        name: &str,
+        version: &str,
+        path: P,
+        metadata_url: Option<&str>,
+    ) -> Result<InstalledBox> {
+        validate_directory_name(version)?;
+        let box_dir = self.box_dir(name)?;
+        let version_dir = box_dir.join(version);
+
+        // extract on the same filesystem, so that it can be moved into place
+        let partial = self.temp_dir()?;
+        let extracted = BoxFile::extract(path, &partial).and_then(|box_file| {
+            let metadata = box_file.metadata().clone();
+            validate_directory_name(metadata.provider.as_str())?;
+            let mut destination = version_dir.clone();
+            if let Some(arch) = &metadata.architecture {
+                validate_directory_name(arch)?;
+                destination.push(arch);
+            }
+            destination.push(metadata.provider.as_str());
+            if destination.exists() {
+                return Err(Error::BoxAlreadyInstalled(format!(
+                    "{} {} ({})",
+                    name, version, metadata.provider
+                )));
+            }
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if let Err(e) = fs::rename(&partial, &destination) {
+                // remove the directories created above if they are empty
+                for dir in destination
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|dir| *dir != self.root)
+                {
+                    let _ = fs::remove_dir(dir);
+                }
+                return Err(e.into());
+            }
+            Ok((metadata, destination))
+        });
+        let (metadata, destination) = match extracted {
+            Ok(res) => res,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&partial);
+                return Err(e);
+            }
+        };
+
+        if let Some(url) = metadata_url {
+            fs::write(box_dir.join(METADATA_URL), url)?;
+        }
+        Ok(InstalledBox {
+            name: name.to_string(),
+            version: version.to_string(),
+            provider: metadata.provider,
+            architecture: metadata.architecture,
+            metadata_url: metadata_url.map(str::to_string),
+            path: destination,
+        })
+    }
+
+    /// Remove the version `version` of the box `name` with all of its
+    /// providers and return the removed boxes.
+    ///
+    /// The box is removed completely once its last version is removed, like
+    /// `vagrant box remove` does.
+    pub fn remove_version(&self, name: &str, version: &str) -> Result<Vec<InstalledBox>> {
+        validate_directory_name(version)?;
+        let removed: Vec<InstalledBox> = self
+            .installed()?
+            .into_iter()
+            .filter(|b| b.name == name && b.version == version)
+            .collect();
+        let box_dir = self.box_dir(name)?;
+        match fs::remove_dir_all(box_dir.join(version)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        if subdirectories(&box_dir)?.is_empty() {
+            match fs::remove_dir_all(&box_dir) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// The installed boxes for which `boxes` (e.g. replies of
+    /// [`read_box`](../struct.Client.html#method.read_box)) contain a newer
+    /// released version with the same provider and architecture.
+    ///
+    /// Only the highest installed version of each box, provider and
+    /// architecture is reported. Installed boxes that are not part of
+    /// `boxes` are ignored.
+    pub fn outdated(&self, boxes: &[api::VagrantBox]) -> Result<Vec<Outdated>> {
+        let any: VersionConstraint = ">= 0".parse()?;
+        let installed = self.installed()?;
+        let mut outdated: Vec<Outdated> = vec![];
+        // sorted by version, so the last one of each provider is the highest
+        for (i, candidate) in installed.iter().enumerate() {
+            let is_newest = installed[i + 1..].iter().all(|b| {
+                b.name != candidate.name
+                    || b.provider != candidate.provider
+                    || b.architecture != candidate.architecture
+            });
+            if !is_newest {
+                continue;
+            }
+            let vagrant_box = boxes.iter().find(|b| {
+                b.tag.as_deref() == Some(candidate.name.as_str())
+                    || format!("{}/{}", b.username, b.name) == candidate.name
+            });
+            let latest = vagrant_box.and_then(|b| {
+                b.resolve_version(
+                    &any,
+                    candidate.provider.as_str(),
+                    candidate.architecture.as_deref(),
+                )
+            });
+            if let Some(latest) = latest {
+                if compare_versions(&latest.version, &candidate.version).is_gt() {
+                    outdated.push(Outdated {
+                        installed: candidate.clone(),
+                        latest: latest.version.clone(),
+                    });
+                }
+            }
+        }
+        Ok(outdated)
+    }
+}
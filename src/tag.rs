@@ -0,0 +1,114 @@
+//! # Box tag module
+//!
+//! Boxes on Vagrant Cloud are identified by their tag, which consists of the
+//! name of the owning user or organization and the name of the box separated
+//! by a slash, e.g. `opensuse/Tumbleweed.x86_64`.
+//!
+//! ```
+//! # use vagabond::tag::BoxTag;
+//! let tag: BoxTag = "opensuse/Tumbleweed.x86_64".parse().unwrap();
+//! assert_eq!(tag.username(), "opensuse");
+//! assert_eq!(tag.name(), "Tumbleweed.x86_64");
+//! assert_eq!(tag.to_string(), "opensuse/Tumbleweed.x86_64");
+//!
+//! assert!("opensuse/../other_user".parse::<BoxTag>().is_err());
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::{Error, Result, VagrantBox};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Validated identifier of a box in the form `username/name`
+///
+/// Usernames and box names may only contain ASCII letters, numbers, dashes,
+/// underscores and periods, and must not consist only of periods.
+pub struct BoxTag {
+    username: String,
+    name: String,
+}
+
+/// Check that `part` is a valid username or box name
+fn validate(part: &str, what: &str) -> Result<()> {
+    if part.is_empty() {
+        return Err(Error::InvalidBoxTag(format!(
+            "the {} must not be empty",
+            what
+        )));
+    }
+    if part.chars().all(|c| c == '.') {
+        return Err(Error::InvalidBoxTag(format!(
+            "'{}' is not a valid {}",
+            part, what
+        )));
+    }
+    match part
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == '.'))
+    {
+        Some(c) => Err(Error::InvalidBoxTag(format!(
+            "the {} '{}' contains the invalid character '{}'",
+            what, part, c
+        ))),
+        None => Ok(()),
+    }
+}
+
+impl BoxTag {
+    /// Create a new tag from a `username` and a box `name`, returning an
+    /// `Error::InvalidBoxTag` if either of them is invalid.
+    pub fn new<U, N>(username: U, name: N) -> Result<BoxTag>
+    where
+        U: Into<String>,
+        N: Into<String>,
+    {
+        let username = username.into();
+        let name = name.into();
+        validate(&username, "username")?;
+        validate(&name, "box name")?;
+        Ok(BoxTag { username, name })
+    }
+
+    /// The name of the user or organization owning the box
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// The name of the box
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Create a [`VagrantBox`](../struct.VagrantBox.html) referring to the box
+    /// with this tag
+    pub fn vagrant_box(&self) -> VagrantBox<'_, '_, '_, '_> {
+        VagrantBox::new(&self.username, &self.name)
+    }
+
+    /// The path segments under which this box can be found in the API
+    pub(crate) fn path_segments(&self) -> [&str; 3] {
+        ["box", &self.username, &self.name]
+    }
+}
+
+impl FromStr for BoxTag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<BoxTag> {
+        let mut parts = s.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(username), Some(name)) => BoxTag::new(username, name),
+            _ => Err(Error::InvalidBoxTag(format!(
+                "'{}' is not of the form username/name",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for BoxTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.username, self.name)
+    }
+}
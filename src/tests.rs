@@ -1,6 +1,6 @@
 lazy_static! {
-    static ref PROVIDER_LIBVIRT: String = "libvirt".to_string();
-    static ref PROVIDER_VIRTBOX: String = "virtualbox".to_string();
+    static ref PROVIDER_LIBVIRT: ProviderName = ProviderName::Libvirt;
+    static ref PROVIDER_VIRTBOX: ProviderName = ProviderName::VirtualBox;
     static ref URL: String = "https://foo.bar.baz/my/box/img.box".to_string();
     static ref VERSION: String = "5.6.8".to_string();
     static ref VERSION_DESCRIPTION: String = "The best version to come!".to_string();
@@ -14,25 +14,25 @@ use super::*;
 
 #[test]
 fn compare_providers() {
-    let box_provider = BoxProvider {
-        name: &PROVIDER_LIBVIRT,
-        url: &URL,
-    };
+    let box_provider = BoxProvider::new(&PROVIDER_LIBVIRT, &URL);
 
     let mut api_response = api::Provider {
-        name: "libvirt".to_string(),
+        name: ProviderName::Libvirt,
         original_url: Some(URL.to_string()),
         ..Default::default()
     };
 
     assert_eq!(&box_provider, api_response);
 
-    api_response.name = "bla".to_string();
+    api_response.name = PROVIDER_VIRTBOX.clone();
     assert_ne!(&box_provider, api_response);
 
-    api_response.name = PROVIDER_LIBVIRT.to_string();
+    api_response.name = PROVIDER_LIBVIRT.clone();
     api_response.original_url = None;
     assert_ne!(&box_provider, api_response);
+
+    let empty = String::new();
+    assert_eq!(&BoxProvider::new(&PROVIDER_LIBVIRT, &empty), api_response);
 }
 
 #[test]
@@ -54,6 +54,17 @@ fn compare_versions() {
 
     api_response.version = "1.2.3".to_string();
     assert_ne!(&box_version, api_response);
+
+    let empty = String::new();
+    api_response.version = VERSION.to_string();
+    api_response.description_markdown = None;
+    assert_eq!(
+        &BoxVersion {
+            version: &VERSION,
+            description: &empty,
+        },
+        api_response
+    );
 }
 
 #[test]
@@ -73,6 +84,31 @@ fn compare_boxes() {
     assert_ne!(&vagrant_box, api_response);
 }
 
+#[test]
+fn update_version_sends_the_version_object() {
+    let _mock = mockito::mock("PUT", "/box/me/updated_box/version/5.6.8")
+        .match_body(mockito::Matcher::Json(serde_json::json!({
+            "version": {
+                "version": "5.6.8",
+                "description": "The best version to come!",
+            }
+        })))
+        .with_body(r#"{"version": "5.6.8"}"#)
+        .create();
+
+    let name = "updated_box".to_string();
+    let updated = mock_client()
+        .update_version(
+            &VagrantBox::new(&USERNAME, &name),
+            &BoxVersion {
+                version: &VERSION,
+                description: &VERSION_DESCRIPTION,
+            },
+        )
+        .unwrap();
+    assert_eq!(updated.version, "5.6.8");
+}
+
 #[test]
 fn error_conversion_from_reqwest_error() {
     let url = &URL.to_string();
@@ -86,7 +122,7 @@ fn error_conversion_from_reqwest_error() {
             let inner_err_msg = format!("{}", e);
             assert_eq!(inner_err_msg, err_msg);
         }
-        _ => assert!(false),
+        e => panic!("unexpected error variant: {:?}", e),
     }
 }
 
@@ -97,16 +133,18 @@ fn error_conversion_from_malformed_request_result() {
         .with_body("{}")
         .create();
 
-    let res = reqwest::blocking::get(&mockito::server_url());
+    let res = reqwest::blocking::get(mockito::server_url());
 
     assert!(res.is_ok());
 
-    match Error::from(res.unwrap()) {
-        Error::ApiCallFailure(code, msg) => {
-            assert_eq!(code, 200);
-            assert_eq!(msg, "");
+    match Error::from_response("GET", res.unwrap()) {
+        Error::UnexpectedStatus(details) => {
+            assert_eq!(details.status, 200);
+            assert_eq!(details.method, "GET");
+            assert!(details.errors.is_empty());
+            assert_eq!(details.body, "{}");
         }
-        _ => assert!(false),
+        e => panic!("unexpected error variant: {:?}", e),
     }
 }
 
@@ -124,15 +162,2792 @@ fn error_conversion_from_vagrantcloud_error_request_result() {
         )
         .create();
 
-    let res = reqwest::blocking::get(&mockito::server_url());
+    let res = reqwest::blocking::get(mockito::server_url());
 
     assert!(res.is_ok());
 
-    match Error::from(res.unwrap()) {
-        Error::ApiCallFailure(code, msg) => {
-            assert_eq!(code, 421);
-            assert_eq!(msg, "Resource not found!");
+    match Error::from_response("GET", res.unwrap()) {
+        Error::UnexpectedStatus(details) => {
+            assert_eq!(details.status, 421);
+            assert_eq!(details.errors, vec!["Resource not found!"]);
+        }
+        e => panic!("unexpected error variant: {:?}", e),
+    }
+}
+
+#[test]
+fn error_conversion_maps_status_codes_to_variants() {
+    let body = r#"{"errors": ["Something is wrong", "and this too"], "success": false}"#;
+    let error_for_status = |status| {
+        let _mock = mockito::mock("PUT", "/status")
+            .with_status(status)
+            .with_header("Retry-After", "42")
+            .with_body(body)
+            .create();
+        let res = reqwest::blocking::Client::new()
+            .put(format!("{}/status", mockito::server_url()))
+            .send()
+            .unwrap();
+        Error::from_response("PUT", res)
+    };
+
+    match error_for_status(404) {
+        Error::NotFound(details) => {
+            assert_eq!(details.method, "PUT");
+            assert_eq!(details.url, format!("{}/status", mockito::server_url()));
+            assert_eq!(details.body, body);
+        }
+        e => panic!("unexpected error variant: {:?}", e),
+    }
+    match error_for_status(401) {
+        Error::Unauthorized(_) => (),
+        e => panic!("unexpected error variant: {:?}", e),
+    }
+    match error_for_status(403) {
+        Error::Forbidden(_) => (),
+        e => panic!("unexpected error variant: {:?}", e),
+    }
+    match error_for_status(409) {
+        Error::Conflict(_) => (),
+        e => panic!("unexpected error variant: {:?}", e),
+    }
+    match error_for_status(422) {
+        Error::Validation { errors, .. } => {
+            assert_eq!(errors, vec!["Something is wrong", "and this too"])
         }
-        _ => assert!(false),
+        e => panic!("unexpected error variant: {:?}", e),
     }
+    match error_for_status(429) {
+        Error::RateLimited { retry_after, .. } => {
+            assert_eq!(retry_after, Some(std::time::Duration::from_secs(42)))
+        }
+        e => panic!("unexpected error variant: {:?}", e),
+    }
+    let err = error_for_status(503);
+    match &err {
+        Error::Server(_) => (),
+        e => panic!("unexpected error variant: {:?}", e),
+    }
+    assert_eq!(
+        err.into_status(),
+        Some(reqwest::StatusCode::SERVICE_UNAVAILABLE)
+    );
+    assert!(format!("{}", err).ends_with(": Something is wrong, and this too"));
+}
+
+#[test]
+fn unexpected_response_contains_body_and_location() {
+    let _mock = mockito::mock("GET", "/box")
+        .with_status(200)
+        .with_body("{\n  \"name\": \"foo\",\n  \"hosted\": \"yes\"\n}")
+        .create();
+
+    let client = Client::new(None as Option<String>);
+    let res: Result<api::Provider> = client.api_call(
+        format!("{}/box", mockito::server_url()),
+        RequestType::Get,
+        None as Option<()>,
+    );
+
+    match res {
+        Err(Error::UnexpectedResponse(e)) => {
+            assert_eq!(e.path, "hosted");
+            assert_eq!(e.line, 3);
+            assert_eq!(e.url, format!("{}/box", mockito::server_url()));
+            assert_eq!(
+                String::from_utf8(e.body).unwrap(),
+                "{\n  \"name\": \"foo\",\n  \"hosted\": \"yes\"\n}"
+            );
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    match decode_response::<api::Version>(
+        "url",
+        br#"{"version": "1", "providers": [{"hosted": 1}]}"#,
+    ) {
+        Err(Error::UnexpectedResponse(e)) => {
+            assert_eq!(e.path, "providers[0].hosted");
+            assert!(e.message.starts_with("invalid type: integer `1`"));
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match decode_response::<Vec<u32>>("url", b"[1, 2] trailing") {
+        Err(Error::UnexpectedResponse(e)) => assert_eq!(e.path, "."),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn no_content_replies_decode_like_null() {
+    let _mock = mockito::mock("DELETE", "/no_content")
+        .with_status(204)
+        .create();
+
+    let client = Client::new(None as Option<String>);
+    let url = format!("{}/no_content", mockito::server_url());
+    let res: Result<()> = client.api_call(&url, RequestType::Delete, None as Option<()>);
+    assert!(res.is_ok());
+
+    let res: Result<Option<api::Version>> =
+        client.api_call(&url, RequestType::Delete, None as Option<()>);
+    assert_eq!(res.unwrap(), None);
+
+    let res: Result<api::Version> = client.api_call(&url, RequestType::Delete, None as Option<()>);
+    match res {
+        Err(Error::UnexpectedResponse(e)) => {
+            assert!(e.message.starts_with("received no content"));
+            assert!(e.body.is_empty());
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn delete_calls_accept_no_content() {
+    let _mocks = [
+        mockito::mock("DELETE", "/box/me/deleted_box")
+            .with_status(204)
+            .create(),
+        mockito::mock("DELETE", "/box/me/deleted_box/version/5.6.8")
+            .with_status(204)
+            .create(),
+        mockito::mock(
+            "DELETE",
+            "/box/me/deleted_box/version/5.6.8/provider/libvirt",
+        )
+        .with_body(r#"{"name": "libvirt", "hosted": false}"#)
+        .create(),
+    ];
+
+    let client = mock_client();
+    let name = "deleted_box".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let box_version = BoxVersion {
+        version: &VERSION,
+        description: &VERSION_DESCRIPTION,
+    };
+
+    assert_eq!(client.delete_box(&vagrant_box).unwrap(), None);
+    assert_eq!(
+        client.delete_version(&vagrant_box, &box_version).unwrap(),
+        None
+    );
+    let deleted = client
+        .delete_provider(
+            &vagrant_box,
+            &box_version,
+            &BoxProvider::new(&PROVIDER_LIBVIRT, &URL),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(deleted.name, "libvirt");
+}
+
+#[test]
+fn version_number_ordering() {
+    let parse = |v: &str| v.parse::<constraint::VersionNumber>().unwrap();
+
+    assert_eq!(parse("1.2"), parse("1.2.0"));
+    assert!(parse("1.10") > parse("1.9"));
+    assert!(parse("1.0.a") < parse("1.0"));
+    assert!(parse("1.0-rc1") < parse("1.0"));
+    assert!(parse("20.1.0.beta1") < parse("20.1.0.beta2"));
+    assert!(parse("1.0-rc1").is_prerelease());
+
+    assert!("1..2".parse::<constraint::VersionNumber>().is_err());
+    assert!("".parse::<constraint::VersionNumber>().is_err());
+}
+
+#[test]
+fn version_constraint_matching() {
+    let constraint: constraint::VersionConstraint = "~> 20.1".parse().unwrap();
+    assert!(constraint.matches_str("20.1"));
+    assert!(constraint.matches_str("20.9.3"));
+    assert!(!constraint.matches_str("21.0"));
+    assert!(!constraint.matches_str("20.0.9"));
+
+    let constraint: constraint::VersionConstraint = "~> 1.2.3".parse().unwrap();
+    assert!(constraint.matches_str("1.2.10"));
+    assert!(!constraint.matches_str("1.3"));
+
+    let constraint: constraint::VersionConstraint = ">= 1.2, < 2.0".parse().unwrap();
+    assert_eq!(constraint.requirements().len(), 2);
+    assert!(constraint.matches_str("1.2"));
+    assert!(constraint.matches_str("1.99"));
+    assert!(!constraint.matches_str("2"));
+    assert!(!constraint.matches_str("1.1.9"));
+    assert_eq!(constraint.to_string(), ">= 1.2, < 2.0");
+
+    let constraint: constraint::VersionConstraint = "1.0, != 1.1".parse().unwrap();
+    assert!(constraint.matches_str("1.0.0"));
+    assert!(!constraint.matches_str("1.1"));
+
+    assert!("".parse::<constraint::VersionConstraint>().is_err());
+    match ">= 1.2, <".parse::<constraint::VersionConstraint>() {
+        Err(Error::InvalidVersionConstraint(msg)) => assert_eq!(msg, "invalid requirement '<'"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn resolve_version_picks_highest_released_match() {
+    let provider = |name: &str, arch: Option<&str>| api::Provider {
+        name: name.into(),
+        architecture: arch.map(str::to_string),
+        ..Default::default()
+    };
+    let version = |ver: &str, status: &str, providers| api::Version {
+        version: ver.to_string(),
+        status: status.to_string(),
+        providers,
+        ..Default::default()
+    };
+
+    let vagrant_box = api::VagrantBox {
+        versions: vec![
+            version("2.0", "active", vec![provider("libvirt", Some("amd64"))]),
+            version(
+                "1.9",
+                "unreleased",
+                vec![provider("libvirt", Some("amd64"))],
+            ),
+            version("1.5", "active", vec![provider("virtualbox", Some("amd64"))]),
+            version("1.4", "active", vec![provider("libvirt", Some("arm64"))]),
+            version("1.3", "active", vec![provider("libvirt", Some("amd64"))]),
+            version("not a version", "active", vec![provider("libvirt", None)]),
+        ],
+        ..Default::default()
+    };
+
+    let constraint = ">= 1.2, < 2.0".parse().unwrap();
+    let resolve = |prov, arch| {
+        vagrant_box
+            .resolve_version(&constraint, prov, arch)
+            .map(|ver| ver.version.as_str())
+    };
+
+    assert_eq!(resolve("libvirt", None), Some("1.4"));
+    assert_eq!(resolve("libvirt", Some("amd64")), Some("1.3"));
+    assert_eq!(resolve("virtualbox", None), Some("1.5"));
+    assert_eq!(resolve("hyperv", None), None);
+}
+
+#[test]
+fn box_tag_parsing() {
+    let tag: BoxTag = "opensuse/Tumbleweed.x86_64".parse().unwrap();
+    assert_eq!(tag.username(), "opensuse");
+    assert_eq!(tag.name(), "Tumbleweed.x86_64");
+    assert_eq!(tag.to_string(), "opensuse/Tumbleweed.x86_64");
+    assert_eq!(
+        &tag.vagrant_box(),
+        api::VagrantBox {
+            username: "opensuse".to_string(),
+            name: "Tumbleweed.x86_64".to_string(),
+            ..Default::default()
+        }
+    );
+
+    for invalid in &[
+        "no_slash",
+        "/name",
+        "user/",
+        "user/name/extra",
+        "user/..",
+        "./name",
+        "us er/name",
+        "user/name?foo",
+    ] {
+        match invalid.parse::<BoxTag>() {
+            Err(Error::InvalidBoxTag(_)) => (),
+            res => panic!("'{}' should be invalid, got: {:?}", invalid, res),
+        }
+    }
+}
+
+#[test]
+fn endpoints_are_percent_encoded() {
+    let client = Client::new(None as Option<String>);
+    let version = "1.0/../../evil?x#y".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &BOXNAME);
+
+    assert_eq!(
+        client
+            .box_endpoint(&vagrant_box, &["version", &version])
+            .unwrap()
+            .as_str(),
+        "https://app.vagrantup.com/api/v1/box/me/MY_BOX/version/1.0%2F..%2F..%2Fevil%3Fx%23y"
+    );
+    assert_eq!(
+        client.endpoint(&["boxes"]).unwrap().as_str(),
+        "https://app.vagrantup.com/api/v1/boxes"
+    );
+
+    match client.box_endpoint(&vagrant_box, &["version", ".."]) {
+        Err(Error::InvalidPathSegment(seg)) => assert_eq!(seg, ".."),
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let username = "me/../other".to_string();
+    match client.read_box(&VagrantBox::new(&username, &BOXNAME)) {
+        Err(Error::InvalidBoxTag(_)) => (),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn provider_names() {
+    for known in &[
+        "virtualbox",
+        "libvirt",
+        "vmware_desktop",
+        "hyperv",
+        "parallels",
+        "docker",
+        "qemu",
+    ] {
+        let name = ProviderName::from(*known);
+        assert!(name.is_known());
+        assert_eq!(name.as_str(), *known);
+        assert_eq!(name.warning(), None);
+    }
+
+    assert_eq!(
+        ProviderName::from("vmware_desktop"),
+        ProviderName::VmwareDesktop
+    );
+    assert_eq!(
+        ProviderName::from("Hyper-V".to_string()),
+        ProviderName::Custom("Hyper-V".to_string())
+    );
+    assert_eq!(
+        ProviderName::from("Hyper-V").warning().unwrap(),
+        "unknown provider 'Hyper-V', did you mean 'hyperv'?"
+    );
+    assert_eq!(
+        ProviderName::from("lxc").warning().unwrap(),
+        "unknown provider 'lxc'"
+    );
+
+    // custom names of known providers are the known providers
+    let custom = ProviderName::Custom("libvirt".to_string());
+    assert_eq!(custom, ProviderName::Libvirt);
+    assert!(custom.is_known());
+    assert_eq!(custom.warning(), None);
+    let names: std::collections::HashSet<ProviderName> =
+        vec![custom, ProviderName::Libvirt].into_iter().collect();
+    assert_eq!(names.len(), 1);
+    assert!(ProviderName::Docker < ProviderName::Custom("lxc".to_string()));
+
+    let provider: api::Provider = serde_json::from_str(
+        r#"{"name": "virtualbox", "hosted": false, "created_at": "",
+                                 "updated_at": "", "download_url": ""}"#,
+    )
+    .unwrap();
+    assert_eq!(provider.name, ProviderName::VirtualBox);
+    assert_eq!(
+        serde_json::to_string(&BoxProvider::new(
+            &ProviderName::Custom("lxc".to_string()),
+            &URL
+        ))
+        .unwrap(),
+        format!(r#"{{"name":"lxc","url":"{}"}}"#, *URL)
+    );
+}
+
+lazy_static! {
+    static ref INCOMPLETE_BOX: String = r#"{
+  "tag": "me/MY_BOX",
+  "username": "me",
+  "name": "MY_BOX",
+  "private": false,
+  "downloads": null,
+  "created_at": "2020-01-01T00:00:00.000Z",
+  "updated_at": "2020-01-01T00:00:00.000Z",
+  "short_description": null,
+  "description_markdown": null,
+  "description_html": null,
+  "current_version": null,
+  "new_field": {"foo": "bar"},
+  "versions": [
+    {
+      "version": "1.0",
+      "status": "active",
+      "description_html": null,
+      "description_markdown": null,
+      "created_at": null,
+      "updated_at": null,
+      "number": "1.0",
+      "release_url": "",
+      "revoke_url": "",
+      "providers": [
+        {
+          "name": "libvirt",
+          "hosted": "no",
+          "hosted_token": null,
+          "original_url": null,
+          "created_at": "",
+          "updated_at": "",
+          "download_url": null
+        }
+      ]
+    }
+  ]
+}"#
+    .to_string();
+}
+
+#[test]
+fn lenient_deserialization_tolerates_null_and_unknown_fields() {
+    let _mock = mockito::mock("GET", "/box/me/MY_BOX")
+        .with_status(200)
+        .with_body(INCOMPLETE_BOX.replace(r#""hosted": "no""#, r#""hosted": null"#))
+        .create();
+
+    let mut client = Client::new(None as Option<String>);
+    client.base_url = mockito::server_url();
+
+    let vagrant_box = client
+        .read_box(&VagrantBox::new(&USERNAME, &BOXNAME))
+        .unwrap();
+    assert_eq!(vagrant_box.downloads, 0);
+    assert_eq!(vagrant_box.extra.len(), 1);
+    assert_eq!(vagrant_box.extra["new_field"]["foo"], "bar");
+
+    let provider = &vagrant_box.versions[0].providers[0];
+    assert_eq!(provider.name, ProviderName::Libvirt);
+    assert!(!provider.hosted);
+    assert_eq!(provider.download_url, "");
+}
+
+#[test]
+fn strict_deserialization_reports_all_deviations() {
+    let _mock = mockito::mock("GET", "/box/me/MY_BOX")
+        .with_status(200)
+        .with_body(INCOMPLETE_BOX.as_str())
+        .create();
+
+    let mut client = Client::new(None as Option<String>)
+        .with_deserialization_mode(schema::DeserializationMode::Strict);
+    client.base_url = mockito::server_url();
+
+    match client.read_box(&VagrantBox::new(&USERNAME, &BOXNAME)) {
+        Err(Error::SchemaViolation { deviations, .. }) => {
+            let deviations: Vec<String> = deviations.iter().map(|d| d.to_string()).collect();
+            assert_eq!(
+                deviations,
+                vec![
+                    "downloads: unexpected null",
+                    "versions[0].providers[0].hosted: expected boolean, found string",
+                    "versions[0].providers[0].download_url: unexpected null",
+                    "new_field: unknown field",
+                ]
+            );
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+/// A version number, its status and its providers (name and url)
+type VersionFixture<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+/// Build the JSON reply of the API for the box `username/name` with the given
+/// `versions`
+fn box_json(username: &str, name: &str, versions: &[VersionFixture]) -> String {
+    let versions: Vec<serde_json::Value> = versions
+        .iter()
+        .map(|(version, status, providers)| {
+            serde_json::json!({
+                "version": version,
+                "status": status,
+                "description_markdown": format!("version {}", version),
+                "providers": providers
+                    .iter()
+                    .map(|(name, url)| serde_json::json!({
+                        "name": name,
+                        "hosted": false,
+                        "original_url": url,
+                        "download_url": url,
+                    }))
+                    .collect::<Vec<serde_json::Value>>(),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "tag": format!("{}/{}", username, name),
+        "username": username,
+        "name": name,
+        "versions": versions,
+    })
+    .to_string()
+}
+
+/// Create a Client that sends all requests to the mockito server
+fn mock_client() -> Client {
+    let mut client = Client::new(Some("token"));
+    client.base_url = mockito::server_url();
+    client
+}
+
+#[test]
+fn plan_for_missing_box_creates_everything() {
+    let _mock = mockito::mock("GET", "/box/me/plan_missing")
+        .with_status(404)
+        .with_body(r#"{"errors": ["Resource not found!"], "success": false}"#)
+        .create();
+
+    let name = "plan_missing".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let box_version = BoxVersion {
+        version: &VERSION,
+        description: &VERSION_DESCRIPTION,
+    };
+    let provider = BoxProvider::new(&PROVIDER_LIBVIRT, &URL);
+
+    let plan = mock_client()
+        .plan_provider_present(&vagrant_box, &box_version, &provider, true)
+        .unwrap();
+
+    let version_spec = plan::VersionSpec::from(&box_version);
+    assert_eq!(
+        plan.operations,
+        vec![
+            plan::Operation::CreateBox,
+            plan::Operation::CreateVersion(version_spec.clone()),
+            plan::Operation::CreateProvider {
+                version: version_spec.clone(),
+                provider: plan::ProviderSpec::from(&provider),
+            },
+            plan::Operation::ReleaseVersion(version_spec),
+        ]
+    );
+    assert_eq!(
+        plan.to_string(),
+        format!(
+            "Plan for me/plan_missing:
+  1. create the box
+  2. create version 5.6.8
+  3. create provider libvirt of version 5.6.8 with the url {url}
+  4. release version 5.6.8",
+            url = *URL
+        )
+    );
+}
+
+#[test]
+fn plan_deletes_providers_of_other_versions() {
+    let name = "plan_delete".to_string();
+    let _mock = mockito::mock("GET", "/box/me/plan_delete")
+        .with_status(200)
+        .with_body(box_json(
+            "me",
+            &name,
+            &[
+                ("1.0", "active", &[("libvirt", "a"), ("virtualbox", "b")]),
+                ("2.0", "active", &[("libvirt", "c")]),
+                (&VERSION, "unreleased", &[("libvirt", "d")]),
+            ],
+        ))
+        .create();
+
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let description = "version 5.6.8".to_string();
+    let box_version = BoxVersion {
+        version: &VERSION,
+        description: &description,
+    };
+    let provider = BoxProvider::new(&PROVIDER_LIBVIRT, &URL);
+
+    let plan = mock_client()
+        .plan_provider_present(&vagrant_box, &box_version, &provider, true)
+        .unwrap();
+
+    assert_eq!(
+        plan.operations
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<String>>(),
+        vec![
+            "delete provider libvirt of version 1.0".to_string(),
+            "delete provider libvirt of version 2.0".to_string(),
+            "delete version 2.0".to_string(),
+            format!(
+                "update provider libvirt of version 5.6.8: url d -> {}",
+                *URL
+            ),
+            "release version 5.6.8".to_string(),
+        ]
+    );
+
+    let plan = mock_client()
+        .plan_provider_present(&vagrant_box, &box_version, &provider, false)
+        .unwrap();
+    assert_eq!(plan.operations.len(), 2);
+}
+
+#[test]
+fn apply_executes_exactly_the_plan() {
+    let name = "apply_box".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let box_version = BoxVersion {
+        version: &VERSION,
+        description: &VERSION_DESCRIPTION,
+    };
+    let version_spec = plan::VersionSpec::from(&box_version);
+    let plan = plan::Plan {
+        vagrant_box: plan::BoxSpec::from(&vagrant_box),
+        operations: vec![
+            plan::Operation::DeleteVersion(plan::VersionSpec {
+                version: "1.0".to_string(),
+                description: "".to_string(),
+            }),
+            plan::Operation::CreateProvider {
+                version: version_spec.clone(),
+                provider: plan::ProviderSpec {
+                    name: ProviderName::Libvirt,
+                    url: URL.to_string(),
+                    checksum_type: None,
+                    checksum: None,
+                },
+            },
+            plan::Operation::ReleaseVersion(version_spec),
+        ],
+    };
+
+    let version_body = r#"{"version": "1.0", "providers": []}"#;
+    let mocks = [
+        mockito::mock("DELETE", "/box/me/apply_box/version/1.0")
+            .with_body(version_body)
+            .create(),
+        mockito::mock("POST", "/box/me/apply_box/version/5.6.8/providers")
+            .match_header("Authorization", "Bearer token")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "provider": {"name": "libvirt", "url": *URL}
+            })))
+            .with_body(r#"{"name": "libvirt"}"#)
+            .create(),
+        mockito::mock("PUT", "/box/me/apply_box/version/5.6.8/release")
+            .with_body(version_body)
+            .create(),
+        mockito::mock("GET", "/box/me/apply_box")
+            .with_body(box_json("me", &name, &[]))
+            .create(),
+    ];
+
+    let res = mock_client().apply(&plan).unwrap();
+    assert_eq!(res.name, name);
+    mocks.iter().for_each(|m| m.assert());
+}
+
+#[test]
+fn manifest_formats_are_equivalent() {
+    let toml = r#"
+[[boxes]]
+username = "me"
+name = "MY_BOX"
+private = false
+
+[[boxes.versions]]
+version = "1.0"
+description = "first"
+released = false
+
+[[boxes.versions.providers]]
+name = "libvirt"
+url = "https://foo.bar.baz/1.0/libvirt.box"
+"#;
+    let yaml = r#"
+boxes:
+  - username: me
+    name: MY_BOX
+    private: false
+    versions:
+      - version: "1.0"
+        description: first
+        released: false
+        providers:
+          - name: libvirt
+            url: https://foo.bar.baz/1.0/libvirt.box
+"#;
+    let json = r#"{"boxes": [{
+        "username": "me", "name": "MY_BOX", "private": false,
+        "versions": [{
+            "version": "1.0", "description": "first", "released": false,
+            "providers": [
+                {"name": "libvirt", "url": "https://foo.bar.baz/1.0/libvirt.box"}
+            ]
+        }]
+    }]}"#;
+
+    let manifest = manifest::Manifest::from_toml_str(toml).unwrap();
+    assert_eq!(manifest::Manifest::from_yaml_str(yaml).unwrap(), manifest);
+    assert_eq!(manifest::Manifest::from_json_str(json).unwrap(), manifest);
+
+    let version = &manifest.boxes[0].versions[0];
+    assert!(!version.released);
+    assert_eq!(version.providers[0].name, ProviderName::Libvirt);
+
+    match manifest::Manifest::from_toml_str("[[boxes]]\nusername = \"me\"\n") {
+        Err(Error::InvalidManifest(msg)) => assert!(msg.contains("name"), "{}", msg),
+        res => panic!("expected an InvalidManifest error, got {:?}", res),
+    }
+
+    // versions and providers must be unique
+    let duplicate_version = "[[boxes]]\nusername = \"me\"\nname = \"MY_BOX\"\n\
+                             [[boxes.versions]]\nversion = \"1.0\"\n\
+                             [[boxes.versions]]\nversion = \"1.0\"\n";
+    match manifest::Manifest::from_toml_str(duplicate_version) {
+        Err(Error::InvalidManifest(msg)) => {
+            assert_eq!(msg, "version 1.0 of me/MY_BOX is listed twice")
+        }
+        res => panic!("expected an InvalidManifest error, got {:?}", res),
+    }
+    let duplicate_provider = json.replace(
+        r#"{"name": "libvirt", "url": "https://foo.bar.baz/1.0/libvirt.box"}"#,
+        r#"{"name": "libvirt", "url": "a"}, {"name": "libvirt", "url": "b"}"#,
+    );
+    match manifest::Manifest::from_json_str(&duplicate_provider) {
+        Err(Error::InvalidManifest(msg)) => assert_eq!(
+            msg,
+            "provider libvirt of version 1.0 of me/MY_BOX is listed twice"
+        ),
+        res => panic!("expected an InvalidManifest error, got {:?}", res),
+    }
+}
+
+#[test]
+fn manifest_plan_converges_and_prunes() {
+    let _mock = mockito::mock("GET", "/box/me/manifest_box")
+        .with_status(200)
+        .with_body(box_json(
+            "me",
+            "manifest_box",
+            &[
+                ("1.0", "active", &[("libvirt", "a"), ("virtualbox", "b")]),
+                ("2.0", "unreleased", &[("libvirt", "c")]),
+                ("3.0", "active", &[("libvirt", "d")]),
+            ],
+        ))
+        .create();
+
+    let manifest = manifest::Manifest::from_yaml_str(
+        r#"
+boxes:
+  - username: me
+    name: manifest_box
+    versions:
+      - version: "1.0"
+        description: version 1.0
+        providers:
+          - {name: libvirt, url: a}
+      - version: "2.0"
+        description: second
+        providers:
+          - {name: libvirt, url: e}
+      - version: "3.0"
+        description: version 3.0
+        released: false
+        providers:
+          - {name: libvirt, url: d}
+      - version: "4.0"
+        released: false
+        providers:
+          - {name: libvirt, url: f}
+"#,
+    )
+    .unwrap();
+
+    let plans = mock_client().plan_manifest(&manifest, true).unwrap();
+    assert_eq!(plans.len(), 1);
+    assert_eq!(
+        plans[0]
+            .operations
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<String>>(),
+        vec![
+            "delete provider virtualbox of version 1.0",
+            "update the description of version 2.0: \"version 2.0\" -> \"second\"",
+            "update provider libvirt of version 2.0: url c -> e",
+            "release version 2.0",
+            "revoke version 3.0",
+            "create version 4.0",
+            "create provider libvirt of version 4.0 with the url f",
+        ]
+    );
+
+    let plans = mock_client().plan_manifest(&manifest, false).unwrap();
+    assert_eq!(plans[0].operations.len(), 6);
+}
+
+#[test]
+fn applied_manifest_plans_are_empty() {
+    let manifest = manifest::Manifest::from_yaml_str(
+        r#"
+boxes:
+  - username: me
+    name: idempotent_box
+    versions:
+      - version: "1.0"
+        providers:
+          - {name: libvirt, url: ""}
+          - {name: virtualbox, url: a}
+      - version: "2.0"
+        released: false
+"#,
+    )
+    .unwrap();
+
+    let created = [
+        mockito::mock("GET", "/box/me/idempotent_box")
+            .with_body(box_json("me", "idempotent_box", &[]))
+            .expect(2)
+            .create(),
+        mockito::mock("POST", "/box/me/idempotent_box/versions")
+            .with_body(r#"{"version": "1.0"}"#)
+            .expect(2)
+            .create(),
+        mockito::mock("POST", "/box/me/idempotent_box/version/1.0/providers")
+            .with_body(r#"{"name": "libvirt", "hosted": false}"#)
+            .expect(2)
+            .create(),
+        mockito::mock("PUT", "/box/me/idempotent_box/version/1.0/release")
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+    ];
+    let client = mock_client();
+    client.reconcile(&manifest, true).unwrap();
+    created.iter().for_each(mockito::Mock::assert);
+    drop(created);
+
+    // the API reports the empty description and the url of the hosted
+    // provider as null
+    let _mock = mockito::mock("GET", "/box/me/idempotent_box")
+        .with_body(
+            serde_json::json!({
+                "tag": "me/idempotent_box",
+                "username": "me",
+                "name": "idempotent_box",
+                "versions": [{
+                    "version": "1.0",
+                    "status": "active",
+                    "description_markdown": null,
+                    "providers": [
+                        {"name": "libvirt", "hosted": true, "original_url": null},
+                        {"name": "virtualbox", "hosted": false, "original_url": "a"},
+                    ],
+                }, {
+                    "version": "2.0",
+                    "status": "unreleased",
+                    "description_markdown": null,
+                    "providers": [],
+                }],
+            })
+            .to_string(),
+        )
+        .create();
+    let plans = client.plan_manifest(&manifest, true).unwrap();
+    assert_eq!(plans.len(), 1);
+    assert!(plans[0].operations.is_empty(), "{:?}", plans[0].operations);
+}
+
+#[test]
+fn retention_policy_evaluation() {
+    let mut vagrant_box: api::VagrantBox = serde_json::from_str(&box_json(
+        "me",
+        "retention_box",
+        &[
+            ("1.0.0", "active", &[("libvirt", "a"), ("virtualbox", "b")]),
+            ("1.1.0", "active", &[("libvirt", "c"), ("virtualbox", "d")]),
+            ("1.2.0", "active", &[("libvirt", "e")]),
+            ("1.3.0", "active", &[("libvirt", "f")]),
+            ("2.0.0", "unreleased", &[("libvirt", "g")]),
+            ("2.1.0", "unreleased", &[("libvirt", "h")]),
+        ],
+    ))
+    .unwrap();
+    vagrant_box.versions[4].created_at = Some("2020-01-01T00:00:00.000Z".to_string());
+    vagrant_box.versions[5].created_at = Some("2020-01-30T00:00:00.000Z".to_string());
+    let now = "2020-02-01T00:00:00Z"
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    let week = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+
+    let describe = |policy: &retention::RetentionPolicy| {
+        policy
+            .evaluate(&vagrant_box, now)
+            .operations
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<String>>()
+    };
+
+    assert!(describe(&retention::RetentionPolicy::new()).is_empty());
+    assert_eq!(
+        describe(
+            &retention::RetentionPolicy::new()
+                .keep_last_released(2)
+                .delete_unreleased_older_than(week)
+        ),
+        vec![
+            "delete provider libvirt of version 1.0.0",
+            "delete provider libvirt of version 1.1.0",
+            "delete version 2.0.0",
+        ]
+    );
+    assert_eq!(
+        describe(
+            &retention::RetentionPolicy::new()
+                .keep_last_released(1)
+                .protect(r"^1\.1\.")
+                .unwrap()
+        ),
+        vec![
+            "delete provider libvirt of version 1.0.0",
+            "delete version 1.2.0"
+        ]
+    );
+    // protected versions don't count towards the kept releases
+    assert_eq!(
+        describe(
+            &retention::RetentionPolicy::new()
+                .keep_last_released(2)
+                .protect(r"^1\.3\.")
+                .unwrap()
+        ),
+        vec!["delete provider libvirt of version 1.0.0"]
+    );
+
+    match retention::RetentionPolicy::new().protect("1.(") {
+        Err(Error::InvalidPattern(_)) => (),
+        res => panic!("expected an InvalidPattern error, got {:?}", res),
+    }
+}
+
+#[test]
+fn failed_plan_is_rolled_back() {
+    let name = "rollback_box".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let version_spec = plan::VersionSpec {
+        version: "2.0".to_string(),
+        description: "new".to_string(),
+    };
+    let old_version = plan::VersionSpec {
+        version: "1.0".to_string(),
+        description: "version 1.0".to_string(),
+    };
+    let plan = plan::Plan {
+        vagrant_box: plan::BoxSpec::from(&vagrant_box),
+        operations: vec![
+            plan::Operation::DeleteVersion(old_version),
+            plan::Operation::CreateVersion(version_spec.clone()),
+            plan::Operation::CreateProvider {
+                version: version_spec,
+                provider: plan::ProviderSpec {
+                    name: ProviderName::Libvirt,
+                    url: URL.to_string(),
+                    checksum_type: None,
+                    checksum: None,
+                },
+            },
+        ],
+    };
+
+    let old_version_body = serde_json::json!({
+        "version": "1.0",
+        "status": "active",
+        "description_markdown": "version 1.0",
+        "providers": [{"name": "virtualbox", "original_url": "a"}],
+    })
+    .to_string();
+    let mocks = [
+        mockito::mock("GET", "/box/me/rollback_box/version/1.0")
+            .with_body(&old_version_body)
+            .create(),
+        mockito::mock("DELETE", "/box/me/rollback_box/version/1.0")
+            .with_body(&old_version_body)
+            .create(),
+        mockito::mock("POST", "/box/me/rollback_box/versions")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"version": {"version": "2.0"}}),
+            ))
+            .with_body(r#"{"version": "2.0"}"#)
+            .create(),
+        mockito::mock("POST", "/box/me/rollback_box/version/2.0/providers")
+            .with_status(500)
+            .with_body(r#"{"errors": ["boom"]}"#)
+            .create(),
+        // compensations
+        mockito::mock("DELETE", "/box/me/rollback_box/version/2.0")
+            .with_body(r#"{"version": "2.0"}"#)
+            .create(),
+        mockito::mock("POST", "/box/me/rollback_box/versions")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"version": {"version": "1.0"}}),
+            ))
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+        mockito::mock("POST", "/box/me/rollback_box/version/1.0/providers")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "provider": {"name": "virtualbox", "url": "a"}
+            })))
+            .with_body(r#"{"name": "virtualbox"}"#)
+            .create(),
+        mockito::mock("PUT", "/box/me/rollback_box/version/1.0/release")
+            .with_status(422)
+            .with_body(r#"{"errors": ["cannot release"]}"#)
+            .create(),
+    ];
+
+    match mock_client().with_rollback(true).apply(&plan) {
+        Err(Error::RolledBack { error, rollback }) => {
+            assert!(matches!(*error, Error::Server(_)));
+            assert_eq!(
+                rollback.failed_operation.to_string(),
+                format!(
+                    "create provider libvirt of version 2.0 with the url {}",
+                    *URL
+                )
+            );
+            assert_eq!(
+                rollback
+                    .compensated
+                    .iter()
+                    .map(|op| op.to_string())
+                    .collect::<Vec<String>>(),
+                vec![
+                    "delete version 2.0",
+                    "create version 1.0",
+                    "create provider virtualbox of version 1.0 with the url a",
+                ]
+            );
+            assert!(!rollback.is_complete());
+            assert_eq!(rollback.failures[0].0.to_string(), "release version 1.0");
+        }
+        res => panic!("expected a rollback, got {:?}", res),
+    }
+    mocks.iter().for_each(|m| m.assert());
+}
+
+#[test]
+fn rollback_keeps_previously_released_versions() {
+    let name = "released_rollback_box".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let version = plan::VersionSpec {
+        version: "1.0".to_string(),
+        description: "version 1.0".to_string(),
+    };
+    let plan = plan::Plan {
+        vagrant_box: plan::BoxSpec::from(&vagrant_box),
+        operations: vec![
+            plan::Operation::ReleaseVersion(version.clone()),
+            plan::Operation::CreateProvider {
+                version,
+                provider: plan::ProviderSpec {
+                    name: ProviderName::Libvirt,
+                    url: URL.to_string(),
+                    checksum_type: None,
+                    checksum: None,
+                },
+            },
+        ],
+    };
+
+    let mocks = [
+        mockito::mock("GET", "/box/me/released_rollback_box/version/1.0")
+            .with_body(r#"{"version": "1.0", "status": "active"}"#)
+            .create(),
+        mockito::mock("PUT", "/box/me/released_rollback_box/version/1.0/release")
+            .with_body(r#"{"version": "1.0", "status": "active"}"#)
+            .create(),
+        mockito::mock(
+            "POST",
+            "/box/me/released_rollback_box/version/1.0/providers",
+        )
+        .with_status(500)
+        .with_body(r#"{"errors": ["boom"]}"#)
+        .create(),
+        mockito::mock("PUT", "/box/me/released_rollback_box/version/1.0/revoke")
+            .expect(0)
+            .create(),
+    ];
+
+    match mock_client().with_rollback(true).apply(&plan) {
+        Err(Error::RolledBack { rollback, .. }) => {
+            assert!(rollback.is_complete());
+            assert!(rollback.compensated.is_empty());
+        }
+        res => panic!("expected a rollback, got {:?}", res),
+    }
+    mocks.iter().for_each(|m| m.assert());
+}
+
+#[test]
+fn release_modes() {
+    let name = "release_modes".to_string();
+    let _mock = mockito::mock("GET", "/box/me/release_modes")
+        .with_status(200)
+        .with_body(box_json(
+            "me",
+            &name,
+            &[
+                ("1.0", "active", &[("libvirt", URL.as_str())]),
+                ("2.0", "unreleased", &[("libvirt", URL.as_str())]),
+            ],
+        ))
+        .create();
+
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let provider = BoxProvider::new(&PROVIDER_LIBVIRT, &URL);
+    let plan_for = |version: &str, release: plan::ReleaseMode| {
+        let version = version.to_string();
+        let description = format!("version {}", version);
+        let options = plan::EnsureOptions {
+            release,
+            ..Default::default()
+        };
+        mock_client()
+            .plan_provider_present_with_options(
+                &vagrant_box,
+                &BoxVersion {
+                    version: &version,
+                    description: &description,
+                },
+                &provider,
+                &options,
+            )
+            .unwrap()
+            .operations
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<String>>()
+    };
+
+    assert!(plan_for("1.0", plan::ReleaseMode::IfUnreleased).is_empty());
+    assert_eq!(
+        plan_for("1.0", plan::ReleaseMode::Always),
+        vec!["release version 1.0"]
+    );
+    assert_eq!(
+        plan_for("2.0", plan::ReleaseMode::IfUnreleased),
+        vec!["release version 2.0"]
+    );
+    assert!(plan_for("2.0", plan::ReleaseMode::Never).is_empty());
+    assert_eq!(
+        plan_for("3.0", plan::ReleaseMode::Never),
+        vec![
+            "create version 3.0".to_string(),
+            format!(
+                "create provider libvirt of version 3.0 with the url {}",
+                *URL
+            ),
+        ]
+    );
+}
+
+#[test]
+fn multiple_providers_are_released_together() {
+    let name = "multi_provider".to_string();
+    let _mock = mockito::mock("GET", "/box/me/multi_provider")
+        .with_status(200)
+        .with_body(box_json(
+            "me",
+            &name,
+            &[
+                ("1.0", "active", &[("libvirt", "a"), ("virtualbox", "b")]),
+                ("2.0", "active", &[("libvirt", "c"), ("docker", "d")]),
+                (&VERSION, "unreleased", &[("virtualbox", "e")]),
+            ],
+        ))
+        .create();
+
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let description = "version 5.6.8".to_string();
+    let box_version = BoxVersion {
+        version: &VERSION,
+        description: &description,
+    };
+    let providers = [
+        BoxProvider::new(&PROVIDER_LIBVIRT, &URL),
+        BoxProvider::new(&PROVIDER_VIRTBOX, &URL),
+    ];
+    let options = plan::EnsureOptions {
+        delete_other_version: true,
+        ..Default::default()
+    };
+
+    let plan = mock_client()
+        .plan_providers_present(&vagrant_box, &box_version, &providers, &options)
+        .unwrap();
+    assert_eq!(
+        plan.operations
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<String>>(),
+        vec![
+            "delete provider libvirt of version 1.0".to_string(),
+            "delete provider virtualbox of version 1.0".to_string(),
+            "delete version 1.0".to_string(),
+            "delete provider libvirt of version 2.0".to_string(),
+            format!(
+                "create provider libvirt of version 5.6.8 with the url {}",
+                *URL
+            ),
+            format!(
+                "update provider virtualbox of version 5.6.8: url e -> {}",
+                *URL
+            ),
+            "release version 5.6.8".to_string(),
+        ]
+    );
+
+    match mock_client().plan_providers_present(
+        &vagrant_box,
+        &box_version,
+        &[providers[0].clone(), providers[0].clone()],
+        &options,
+    ) {
+        Err(Error::DuplicateProvider(name)) => assert_eq!(name, ProviderName::Libvirt),
+        res => panic!("expected a DuplicateProvider error, got {:?}", res),
+    }
+    assert!(matches!(
+        mock_client().ensure_providers_present(&vagrant_box, &box_version, &[], &options),
+        Err(Error::NoProviders)
+    ));
+}
+
+#[derive(Default)]
+struct RecordingObserver(std::sync::Mutex<Vec<String>>);
+
+impl observer::Observer for RecordingObserver {
+    fn before(&self, event: &observer::Event) {
+        self.0.lock().unwrap().push(format!("before {}", event));
+    }
+
+    fn after(&self, event: &observer::Event, outcome: &observer::Outcome) {
+        let payload = match event {
+            observer::Event::ApiCall {
+                payload: Some(p), ..
+            } => format!(" with {}", p),
+            _ => "".to_string(),
+        };
+        self.0.lock().unwrap().push(format!(
+            "after {}{}: {}",
+            event,
+            payload,
+            if outcome.is_success() { "ok" } else { "failed" }
+        ));
+    }
+}
+
+#[test]
+fn observers_see_operations_and_api_calls() {
+    let name = "observed_box".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let version_spec = plan::VersionSpec {
+        version: "1.0".to_string(),
+        description: "descr".to_string(),
+    };
+    let plan = plan::Plan {
+        vagrant_box: plan::BoxSpec::from(&vagrant_box),
+        operations: vec![
+            plan::Operation::UpdateVersion {
+                version: version_spec.clone(),
+                current: version_spec.clone(),
+            },
+            plan::Operation::ReleaseVersion(version_spec),
+        ],
+    };
+    let _mocks = [
+        mockito::mock("PUT", "/box/me/observed_box/version/1.0")
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+        mockito::mock("PUT", "/box/me/observed_box/version/1.0/release")
+            .with_status(500)
+            .create(),
+    ];
+
+    let recorder = std::sync::Arc::new(RecordingObserver::default());
+    let client = mock_client().with_observer(recorder.clone());
+    assert!(client.apply(&plan).is_err());
+
+    let url = mockito::server_url();
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        vec![
+            "before me/observed_box: update the description of version 1.0: \"descr\" -> \"descr\""
+                .to_string(),
+            format!("before PUT {}/box/me/observed_box/version/1.0", url),
+            format!(
+                "after PUT {}/box/me/observed_box/version/1.0 with {}: ok",
+                url, r#"{"version":{"version":"1.0","description":"descr"}}"#
+            ),
+            "after me/observed_box: update the description of version 1.0: \"descr\" -> \"descr\": ok"
+                .to_string(),
+            "before me/observed_box: release version 1.0".to_string(),
+            format!("before PUT {}/box/me/observed_box/version/1.0/release", url),
+            format!(
+                "after PUT {}/box/me/observed_box/version/1.0/release: failed",
+                url
+            ),
+            "after me/observed_box: release version 1.0: failed".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn audit_log_records_mutations_with_rotation() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let log = audit::AuditLog::open(&path).unwrap().with_rotation(400, 5);
+    let client = mock_client().with_audit_log(log);
+
+    let name = "audited_box".to_string();
+    let other_name = "other_audited_box".to_string();
+    let version = "1.0".to_string();
+    let description = "".to_string();
+    let box_version = BoxVersion {
+        version: &version,
+        description: &description,
+    };
+    let _mocks = [
+        mockito::mock("POST", "/box/me/audited_box/versions")
+            .with_status(201)
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+        mockito::mock("GET", "/box/me/audited_box/version/1.0")
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+        mockito::mock("DELETE", "/box/me/audited_box/version/1.0/provider/libvirt")
+            .with_status(404)
+            .with_body(r#"{"errors": ["Resource not found!"]}"#)
+            .create(),
+        mockito::mock("PUT", "/box/me/other_audited_box/version/1.0/release")
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+    ];
+
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    client.create_version(&vagrant_box, &box_version).unwrap();
+    client.read_version(&vagrant_box, &box_version).unwrap();
+    assert!(client
+        .delete_provider(
+            &vagrant_box,
+            &box_version,
+            &BoxProvider::new(&PROVIDER_LIBVIRT, &URL)
+        )
+        .is_err());
+    client
+        .release_version(&VagrantBox::new(&USERNAME, &other_name), &box_version)
+        .unwrap();
+
+    // every record is larger than 200 bytes => each one ends up in its own file
+    assert!(path.exists());
+    assert!(dir.path().join("audit.jsonl.2").exists());
+    assert!(!dir.path().join("audit.jsonl.3").exists());
+
+    assert_eq!(audit::read_records(&path).unwrap().len(), 3);
+    let history = audit::history(&path, &"me/audited_box".parse().unwrap()).unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|r| (r.method.as_str(), r.status, r.success))
+            .collect::<Vec<_>>(),
+        vec![("POST", Some(201), true), ("DELETE", Some(404), false)]
+    );
+    assert_eq!(history[1].entity.version.as_deref(), Some("1.0"));
+    assert_eq!(history[1].entity.provider.as_deref(), Some("libvirt"));
+    assert!(history[1]
+        .response
+        .as_deref()
+        .unwrap()
+        .contains("Resource not found!"));
+
+    let fingerprint = audit::token_fingerprint(Some("token"));
+    assert!(fingerprint.starts_with("sha256:"));
+    assert!(history.iter().all(|r| r.token_fingerprint == fingerprint));
+    assert!(!std::fs::read_to_string(&path)
+        .unwrap()
+        .contains("\"token\""));
+}
+
+#[test]
+fn audit_log_redacts_credentials() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let client = mock_client().with_audit_log(audit::AuditLog::open(&path).unwrap());
+
+    let name = "secret_box".to_string();
+    let box_version = BoxVersion {
+        version: &VERSION,
+        description: &VERSION_DESCRIPTION,
+    };
+    let upload_path = format!("{}/upload/secret_box?token=s3cr3t", mockito::server_url());
+    let _mocks = [
+        mockito::mock("POST", "/box/me/secret_box/version/5.6.8/providers")
+            .with_body(r#"{"name": "libvirt", "hosted": true, "hosted_token": "s3cr3t"}"#)
+            .create(),
+        mockito::mock(
+            "GET",
+            "/box/me/secret_box/version/5.6.8/provider/libvirt/upload",
+        )
+        .with_body(serde_json::json!({ "upload_path": upload_path }).to_string())
+        .create(),
+        mockito::mock("PUT", "/upload/secret_box?token=s3cr3t").create(),
+    ];
+
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    client
+        .create_provider(
+            &vagrant_box,
+            &box_version,
+            &BoxProvider::new(&PROVIDER_LIBVIRT, &URL),
+        )
+        .unwrap();
+    client
+        .upload_provider(
+            &vagrant_box,
+            &box_version,
+            &ProviderName::Libvirt,
+            std::io::Cursor::new(b"box".to_vec()),
+            Some(3),
+        )
+        .unwrap();
+
+    // upload paths may carry the token in their path, failed uploads report
+    // the upload path in their error
+    let _failing = mockito::mock(
+        "GET",
+        "/box/me/secret_box/version/5.6.8/provider/virtualbox/upload",
+    )
+    .with_body(r#"{"upload_path": "http://127.0.0.1:1/v1/object/s3cr3t?token=s3cr3t"}"#)
+    .create();
+    assert!(client
+        .upload_provider(
+            &vagrant_box,
+            &box_version,
+            &ProviderName::VirtualBox,
+            std::io::Cursor::new(b"box".to_vec()),
+            Some(3),
+        )
+        .is_err());
+
+    let records = audit::read_records(&path).unwrap();
+    assert_eq!(records.len(), 3);
+    assert!(records[0]
+        .response
+        .as_deref()
+        .unwrap()
+        .contains(r#""hosted_token": "[redacted]""#));
+    assert_eq!(
+        records[1].endpoint,
+        format!("{}/upload", mockito::server_url())
+    );
+    assert_eq!(records[2].endpoint, "http://127.0.0.1:1/v1");
+    let error = records[2].error.as_deref().unwrap();
+    assert!(error.contains("http://127.0.0.1:1/v1"), "{}", error);
+    assert!(!std::fs::read_to_string(&path).unwrap().contains("s3cr3t"));
+}
+
+#[test]
+fn concurrent_execution_aggregates_errors() {
+    let client = mock_client().with_max_concurrency(4);
+    let res = client.execute_concurrently((0..10).collect(), |_, i: u32| {
+        if i % 3 == 0 {
+            Err(Error::InternalError(i.to_string()))
+        } else {
+            Ok(i * 2)
+        }
+    });
+    match res {
+        Err(Error::Aggregate(errors)) => assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            ["0", "3", "6", "9"]
+                .iter()
+                .map(|i| format!("Internal error occurred: {}", i))
+                .collect::<Vec<_>>()
+        ),
+        res => panic!("expected an Aggregate error, got {:?}", res),
+    }
+
+    let res = client.execute_concurrently((1..=10).collect(), |_, i: u32| Ok(i * 2));
+    assert_eq!(res.unwrap(), (1..=10).map(|i| i * 2).collect::<Vec<u32>>());
+}
+
+#[test]
+fn deletions_are_applied_concurrently_within_the_rate_limit() {
+    let name = "parallel_box".to_string();
+    let vagrant_box = VagrantBox::new(&USERNAME, &name);
+    let version = |v: &str| plan::VersionSpec {
+        version: v.to_string(),
+        description: "".to_string(),
+    };
+    let libvirt = plan::ProviderSpec {
+        name: ProviderName::Libvirt,
+        url: URL.to_string(),
+        checksum_type: None,
+        checksum: None,
+    };
+    let mut operations = vec![];
+    for v in &["1", "2", "3"] {
+        operations.push(plan::Operation::DeleteProvider {
+            version: version(v),
+            provider: libvirt.clone(),
+        });
+    }
+    operations.push(plan::Operation::DeleteVersion(version("3")));
+    operations.push(plan::Operation::ReleaseVersion(version("4")));
+    let plan = plan::Plan {
+        vagrant_box: plan::BoxSpec::from(&vagrant_box),
+        operations,
+    };
+
+    let mut mocks: Vec<mockito::Mock> = ["1", "2", "3"]
+        .iter()
+        .map(|v| {
+            mockito::mock(
+                "DELETE",
+                format!("/box/me/parallel_box/version/{}/provider/libvirt", v).as_str(),
+            )
+            .with_body(r#"{"name": "libvirt"}"#)
+            .create()
+        })
+        .collect();
+    mocks.push(
+        mockito::mock("DELETE", "/box/me/parallel_box/version/3")
+            .with_body(r#"{"version": "3"}"#)
+            .create(),
+    );
+    mocks.push(
+        mockito::mock("PUT", "/box/me/parallel_box/version/4/release")
+            .with_body(r#"{"version": "4"}"#)
+            .create(),
+    );
+    mocks.push(
+        mockito::mock("GET", "/box/me/parallel_box")
+            .with_body(box_json("me", &name, &[]))
+            .create(),
+    );
+
+    let client = mock_client()
+        .with_max_concurrency(3)
+        .with_rate_limit(20, std::time::Duration::from_secs(1));
+    let start = std::time::Instant::now();
+    client.apply(&plan).unwrap();
+    // 6 requests spaced by 50ms each
+    assert!(start.elapsed() >= std::time::Duration::from_millis(250));
+    mocks.iter().for_each(|m| m.assert());
+}
+
+#[test]
+fn rate_limit_honours_retry_after() {
+    let _mocks = [
+        mockito::mock("GET", "/box/me/busy_box")
+            .with_status(429)
+            .with_header("Retry-After", "1")
+            .with_body(r#"{"errors": ["slow down"]}"#)
+            .create(),
+        mockito::mock("GET", "/box/me/idle_box")
+            .with_body(box_json("me", "idle_box", &[]))
+            .create(),
+        mockito::mock("GET", "/box/me/dated_box")
+            .with_status(429)
+            .with_header(
+                "Retry-After",
+                &(chrono::Utc::now() + chrono::Duration::seconds(2))
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            )
+            .create(),
+        mockito::mock("GET", "/box/me/past_box")
+            .with_status(429)
+            .with_header("Retry-After", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .create(),
+    ];
+
+    // the Retry-After header may also be a date
+    let client = mock_client();
+    let dated = "dated_box".to_string();
+    match client.read_box(&VagrantBox::new(&USERNAME, &dated)) {
+        Err(Error::RateLimited {
+            retry_after: Some(retry_after),
+            ..
+        }) => assert!(
+            retry_after > std::time::Duration::ZERO
+                && retry_after <= std::time::Duration::from_secs(2),
+            "{:?}",
+            retry_after
+        ),
+        res => panic!("expected a RateLimited error, got {:?}", res),
+    }
+    let past = "past_box".to_string();
+    assert!(matches!(
+        client.read_box(&VagrantBox::new(&USERNAME, &past)),
+        Err(Error::RateLimited { retry_after: Some(d), .. }) if d.is_zero()
+    ));
+
+    let client = mock_client().with_rate_limit(100, std::time::Duration::from_secs(1));
+    let busy = "busy_box".to_string();
+    let idle = "idle_box".to_string();
+    let start = std::time::Instant::now();
+    assert!(matches!(
+        client.read_box(&VagrantBox::new(&USERNAME, &busy)),
+        Err(Error::RateLimited { .. })
+    ));
+    client.read_box(&VagrantBox::new(&USERNAME, &idle)).unwrap();
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+}
+
+#[test]
+fn promote_version_copies_providers_and_checksums() {
+    let staging = "promote_staging".to_string();
+    let public = "promote_public".to_string();
+    let source_box = VagrantBox::new(&USERNAME, &staging);
+    let target_box = VagrantBox::new(&USERNAME, &public);
+
+    let version_body = |status: &str| {
+        serde_json::json!({
+            "version": "1.0",
+            "status": status,
+            "description_markdown": "version 1.0",
+            "providers": [
+                {
+                    "name": "libvirt",
+                    "original_url": "a",
+                    "checksum_type": "sha256",
+                    "checksum": "abc",
+                },
+                {"name": "virtualbox", "original_url": "b"},
+            ],
+        })
+        .to_string()
+    };
+    let mocks = [
+        mockito::mock("GET", "/box/me/promote_public/version/1.0")
+            .with_status(404)
+            .with_body(r#"{"errors": ["Resource not found!"], "success": false}"#)
+            .expect(1)
+            .create(),
+        mockito::mock("GET", "/box/me/promote_public/version/1.0")
+            .with_body(version_body("active"))
+            .expect_at_least(1)
+            .create(),
+        mockito::mock("GET", "/box/me/promote_staging/version/1.0")
+            .with_body(version_body("active"))
+            .expect_at_least(1)
+            .create(),
+        mockito::mock("POST", "/box/me/promote_public/versions")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "version": {"version": "1.0", "description": "version 1.0"}
+            })))
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+        mockito::mock("POST", "/box/me/promote_public/version/1.0/providers")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "provider": {
+                    "name": "libvirt",
+                    "url": "a",
+                    "checksum_type": "sha256",
+                    "checksum": "abc",
+                }
+            })))
+            .with_body(r#"{"name": "libvirt"}"#)
+            .create(),
+        mockito::mock("POST", "/box/me/promote_public/version/1.0/providers")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "provider": {"name": "virtualbox", "url": "b"}
+            })))
+            .with_body(r#"{"name": "virtualbox"}"#)
+            .create(),
+        mockito::mock("PUT", "/box/me/promote_public/version/1.0/release")
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+        mockito::mock("GET", "/box/me/promote_public")
+            .with_body(box_json("me", &public, &[]))
+            .create(),
+        mockito::mock("PUT", "/box/me/promote_staging/version/1.0/revoke")
+            .with_body(r#"{"version": "1.0"}"#)
+            .create(),
+    ];
+
+    let client = mock_client();
+    let promoted = client
+        .promote_version(
+            &source_box,
+            &target_box,
+            "1.0",
+            promote::SourceAction::Revoke,
+        )
+        .unwrap();
+    assert_eq!(promoted.status, "active");
+    assert_eq!(promoted.providers.len(), 2);
+    assert_eq!(promoted.providers[0].checksum.as_deref(), Some("abc"));
+    mocks.iter().for_each(|m| m.assert());
+
+    // promoting again is a no-op
+    assert!(client
+        .plan_promote_version(&source_box, &target_box, "1.0")
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn promoting_hosted_providers() {
+    let staging = "hosted_staging".to_string();
+    let public = "hosted_public".to_string();
+    let source_box = VagrantBox::new(&USERNAME, &staging);
+    let target_box = VagrantBox::new(&USERNAME, &public);
+    let download_url =
+        "https://vagrantcloud.com/me/boxes/hosted_staging/versions/1.0/providers/libvirt.box";
+
+    let mocks = [
+        mockito::mock("GET", "/box/me/hosted_staging/version/1.0")
+            .with_body(
+                serde_json::json!({
+                    "version": "1.0",
+                    "status": "active",
+                    "description_markdown": null,
+                    "providers": [{
+                        "name": "libvirt",
+                        "hosted": true,
+                        "original_url": null,
+                        "download_url": download_url,
+                    }],
+                })
+                .to_string(),
+            )
+            .expect_at_least(1)
+            .create(),
+        // already promoted, the API reports the empty description as null
+        mockito::mock("GET", "/box/me/hosted_public/version/1.0")
+            .with_body(
+                serde_json::json!({
+                    "version": "1.0",
+                    "status": "active",
+                    "description_markdown": null,
+                    "providers": [{
+                        "name": "libvirt",
+                        "hosted": false,
+                        "original_url": download_url,
+                        "download_url": download_url,
+                    }],
+                })
+                .to_string(),
+            )
+            .expect_at_least(1)
+            .create(),
+        mockito::mock("DELETE", "/box/me/hosted_staging/version/1.0")
+            .expect(0)
+            .create(),
+    ];
+
+    let client = mock_client();
+    assert!(client
+        .plan_promote_version(&source_box, &target_box, "1.0")
+        .unwrap()
+        .is_empty());
+    assert!(matches!(
+        client.promote_version(
+            &source_box,
+            &target_box,
+            "1.0",
+            promote::SourceAction::Delete
+        ),
+        Err(Error::HostedSourceProvider(ProviderName::Libvirt))
+    ));
+    mocks.iter().for_each(|m| m.assert());
+}
+
+#[test]
+fn mirror_copies_missing_versions_and_rehosts_box_files() {
+    let name = "mirrored".to_string();
+    let tag = BoxTag::new("me", &name).unwrap();
+    let box_file_url = format!("{}/files/2.0.box", mockito::server_url());
+    let source_box = box_json(
+        "me",
+        &name,
+        &[
+            ("1.0", "active", &[("libvirt", "a"), ("virtualbox", "b")]),
+            (
+                "2.0",
+                "active",
+                &[("libvirt", &box_file_url), ("virtualbox", "c")],
+            ),
+            ("3.0", "unreleased", &[("libvirt", "d")]),
+            ("0.9", "active", &[("libvirt", "e")]),
+        ],
+    );
+    let destination_box = serde_json::json!({
+        "username": "me",
+        "name": "mirrored",
+        "versions": [{
+            "version": "1.0",
+            "status": "active",
+            "providers": [{"name": "libvirt", "hosted": true}],
+        }],
+    })
+    .to_string();
+
+    let mocks = [
+        mockito::mock("GET", "/src/box/me/mirrored")
+            .with_body(&source_box)
+            .expect_at_least(1)
+            .create(),
+        mockito::mock("GET", "/dst/box/me/mirrored")
+            .with_body(&destination_box)
+            .expect_at_least(1)
+            .create(),
+        mockito::mock("POST", "/dst/box/me/mirrored/versions")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"version": {"version": "2.0"}}),
+            ))
+            .with_body(r#"{"version": "2.0"}"#)
+            .create(),
+        mockito::mock("POST", "/dst/box/me/mirrored/version/2.0/providers")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "provider": {"name": "libvirt", "url": box_file_url}
+            })))
+            .with_body(r#"{"name": "libvirt"}"#)
+            .create(),
+        mockito::mock(
+            "GET",
+            "/dst/box/me/mirrored/version/2.0/provider/libvirt/upload",
+        )
+        .with_body(
+            serde_json::json!({
+                "upload_path": format!("{}/upload/abc", mockito::server_url())
+            })
+            .to_string(),
+        )
+        .create(),
+        mockito::mock("GET", "/files/2.0.box")
+            .with_body("BOXDATA")
+            .create(),
+        mockito::mock("PUT", "/upload/abc")
+            .match_body("BOXDATA")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .create(),
+        mockito::mock("PUT", "/dst/box/me/mirrored/version/2.0/release")
+            .with_body(r#"{"version": "2.0"}"#)
+            .create(),
+    ];
+
+    let source =
+        Client::new(Some("source token")).with_base_url(format!("{}/src", mockito::server_url()));
+    let destination = Client::new(Some("destination token"))
+        .with_base_url(format!("{}/dst", mockito::server_url()));
+    let selection = mirror::Selection::new()
+        .with_box(tag.clone())
+        .with_constraint(">= 1.0".parse().unwrap())
+        .with_provider(ProviderName::Libvirt);
+    let mirror = mirror::Mirror::new(&source, &destination).with_rehost(true);
+
+    let sync = mirror.plan_box(&tag, &selection).unwrap();
+    assert_eq!(
+        sync.to_string(),
+        format!(
+            "Plan for me/mirrored:
+  1. create version 2.0
+  2. create provider libvirt of version 2.0 with the url {url}
+  3. release version 2.0
+  - upload provider libvirt of version 2.0 from {url}",
+            url = box_file_url
+        )
+    );
+
+    assert_eq!(mirror.sync(&selection).unwrap().len(), 1);
+    mocks.iter().for_each(|m| m.assert());
+}
+
+/// Build a tar archive containing `files` (path, contents)
+fn tar_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, *contents).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn box_files_are_parsed_regardless_of_compression() {
+    use std::io::Write;
+
+    let archive = tar_archive(&[
+        (
+            "./metadata.json",
+            br#"{"provider": "libvirt", "format": "qcow2", "virtual_size": 42}"#,
+        ),
+        ("info.json", br#"{"author": "me"}"#),
+        (
+            "Vagrantfile",
+            b"Vagrant.configure(\"2\") do |config|\nend\n",
+        ),
+        ("box.img", &[0; 4096]),
+    ]);
+
+    let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+    gzip.write_all(&archive).unwrap();
+    let mut bzip2 = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::fast());
+    bzip2.write_all(&archive).unwrap();
+    let mut xz = xz2::write::XzEncoder::new(vec![], 1);
+    xz.write_all(&archive).unwrap();
+
+    for (compression, data) in [
+        (boxfile::Compression::None, archive.clone()),
+        (boxfile::Compression::Gzip, gzip.finish().unwrap()),
+        (boxfile::Compression::Bzip2, bzip2.finish().unwrap()),
+        (boxfile::Compression::Xz, xz.finish().unwrap()),
+    ] {
+        let box_file = boxfile::BoxFile::from_reader(data.as_slice()).unwrap();
+        assert_eq!(box_file.compression(), compression);
+        assert_eq!(box_file.metadata().provider, ProviderName::Libvirt);
+        assert_eq!(box_file.metadata().format.as_deref(), Some("qcow2"));
+        assert_eq!(box_file.metadata().virtual_size, Some(42));
+        assert_eq!(box_file.metadata().architecture, None);
+        assert_eq!(
+            box_file.info().and_then(|i| i.get("author")),
+            Some(&serde_json::json!("me"))
+        );
+        assert!(box_file
+            .vagrantfile()
+            .unwrap()
+            .starts_with("Vagrant.configure"));
+        assert_eq!(
+            box_file
+                .entries()
+                .iter()
+                .map(|e| (e.path.as_str(), e.size))
+                .collect::<Vec<(&str, u64)>>(),
+            vec![
+                ("metadata.json", 62),
+                ("info.json", 16),
+                ("Vagrantfile", 39),
+                ("box.img", 4096),
+            ]
+        );
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("no_metadata.box");
+    std::fs::write(&path, tar_archive(&[("box.img", b"disk")])).unwrap();
+    assert!(matches!(
+        boxfile::BoxFile::open(&path),
+        Err(Error::InvalidBoxFile(_))
+    ));
+    assert!(matches!(
+        boxfile::BoxFile::open(dir.path().join("missing.box")),
+        Err(Error::Filesystem(_))
+    ));
+}
+
+#[test]
+fn packaged_boxes_are_deterministic_and_readable() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("disk.qcow2");
+    std::fs::write(&image, vec![7u8; 10000]).unwrap();
+    let mut info = serde_json::Map::new();
+    info.insert("author".to_string(), "me".into());
+
+    let packager = packager::Packager::libvirt(&image, 20)
+        .with_vagrantfile("Vagrant.configure(\"2\") do |config|\nend\n")
+        .with_info(info)
+        .with_mtime(1_600_000_000);
+    let first = dir.path().join("first.box");
+    let second = dir.path().join("second.box");
+    packager.write_to_file(&first).unwrap();
+    packager.write_to_file(&second).unwrap();
+    assert_eq!(
+        std::fs::read(&first).unwrap(),
+        std::fs::read(&second).unwrap()
+    );
+    assert!(!dir.path().join("first.box.partial").exists());
+
+    let box_file = boxfile::BoxFile::open(&first).unwrap();
+    assert_eq!(box_file.compression(), boxfile::Compression::Gzip);
+    assert_eq!(box_file.metadata().provider, ProviderName::Libvirt);
+    assert_eq!(box_file.metadata().format.as_deref(), Some("qcow2"));
+    assert_eq!(box_file.metadata().virtual_size, Some(20));
+    assert_eq!(
+        box_file.info().and_then(|i| i.get("author")),
+        Some(&serde_json::json!("me"))
+    );
+    assert!(box_file.vagrantfile().is_some());
+    assert_eq!(
+        box_file
+            .entries()
+            .iter()
+            .map(|e| (e.path.as_str(), e.size))
+            .collect::<Vec<(&str, u64)>>()[3],
+        ("box.img", 10000)
+    );
+
+    let ovf = dir.path().join("box.ovf");
+    let disk = dir.path().join("disk-001.vmdk");
+    std::fs::write(&ovf, "<Envelope/>").unwrap();
+    std::fs::write(&disk, "vmdk").unwrap();
+    let mut virtualbox = vec![];
+    packager::Packager::virtualbox(&ovf, &[&disk])
+        .with_compression(boxfile::Compression::None)
+        .write(&mut virtualbox)
+        .unwrap();
+    let box_file = boxfile::BoxFile::from_reader(virtualbox.as_slice()).unwrap();
+    assert_eq!(box_file.metadata().provider, ProviderName::VirtualBox);
+    assert_eq!(
+        box_file
+            .entries()
+            .iter()
+            .map(|e| e.path.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["metadata.json", "box.ovf", "disk-001.vmdk"]
+    );
+
+    for packager in [
+        packager::Packager::libvirt(&image, 20).with_file_as("box.img", &image),
+        packager::Packager::libvirt(&image, 20).with_file_as("metadata.json", &image),
+        packager::Packager::libvirt(&image, 20).with_file_as("../escape", &image),
+    ] {
+        assert!(matches!(
+            packager.write(vec![]),
+            Err(Error::InvalidBoxFile(_))
+        ));
+    }
+}
+
+#[test]
+fn checksums_are_computed_while_uploading() {
+    let expected = [
+        (checksum::ChecksumType::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+        (
+            checksum::ChecksumType::Sha1,
+            "a9993e364706816aba3e25717850c26c9cd0d89d",
+        ),
+        (
+            checksum::ChecksumType::Sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            checksum::ChecksumType::Sha384,
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7",
+        ),
+        (
+            checksum::ChecksumType::Sha512,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        ),
+    ];
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("abc.box");
+    std::fs::write(&path, "abc").unwrap();
+    for (checksum_type, digest) in &expected {
+        assert_eq!(
+            checksum_type
+                .to_string()
+                .parse::<checksum::ChecksumType>()
+                .unwrap(),
+            *checksum_type
+        );
+        let progress = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = progress.clone();
+        let checksum = checksum::checksum_file(&path, *checksum_type, move |done, total| {
+            recorded.lock().unwrap().push((done, total))
+        })
+        .unwrap();
+        assert_eq!(checksum.checksum, *digest);
+        assert_eq!(progress.lock().unwrap().last(), Some(&(3, Some(3))));
+    }
+    assert!(matches!(
+        "crc32".parse::<checksum::ChecksumType>(),
+        Err(Error::UnsupportedChecksumType(_))
+    ));
+
+    let name = "checksum_upload".to_string();
+    let _mocks = [
+        mockito::mock(
+            "GET",
+            "/box/me/checksum_upload/version/1.0/provider/libvirt/upload",
+        )
+        .with_body(
+            serde_json::json!({
+                "upload_path": format!("{}/upload/checksum", mockito::server_url())
+            })
+            .to_string(),
+        )
+        .create(),
+        mockito::mock("PUT", "/upload/checksum")
+            .match_body("abc")
+            .create(),
+    ];
+    let reader = checksum::HashingReader::new(
+        std::fs::File::open(&path).unwrap(),
+        checksum::ChecksumType::Sha256,
+    );
+    let handle = reader.checksum_handle();
+    let version = "1.0".to_string();
+    let description = String::new();
+    mock_client()
+        .upload_provider(
+            &VagrantBox::new(&USERNAME, &name),
+            &BoxVersion {
+                version: &version,
+                description: &description,
+            },
+            &ProviderName::Libvirt,
+            reader,
+            Some(3),
+        )
+        .unwrap();
+    assert_eq!(handle.processed(), 3);
+    assert_eq!(handle.checksum().checksum, expected[2].1);
+    assert_eq!(
+        handle.checksum().to_string(),
+        format!("sha256:{}", expected[2].1)
+    );
+}
+
+#[test]
+fn catalogs_from_api_boxes_and_box_files() {
+    let mut vagrant_box: api::VagrantBox = serde_json::from_str(&box_json(
+        "me",
+        "catalog",
+        &[
+            ("1.0", "active", &[("libvirt", "a"), ("virtualbox", "b")]),
+            ("2.0", "unreleased", &[("libvirt", "c")]),
+        ],
+    ))
+    .unwrap();
+    vagrant_box.versions[0].providers[0].checksum_type = Some("sha256".to_string());
+    vagrant_box.versions[0].providers[0].checksum = Some("abc".to_string());
+    assert_eq!(
+        serde_json::to_value(catalog::Catalog::from(&vagrant_box)).unwrap(),
+        serde_json::json!({
+            "name": "me/catalog",
+            "versions": [{
+                "version": "1.0",
+                "description": "version 1.0",
+                "providers": [
+                    {"name": "libvirt", "url": "a", "checksum_type": "sha256", "checksum": "abc"},
+                    {"name": "virtualbox", "url": "b"},
+                ],
+            }],
+        })
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("disk.qcow2");
+    std::fs::write(&image, "qcow2").unwrap();
+    let new_box = dir.path().join("awesome box-1.10.box");
+    let old_box = dir.path().join("awesome_box-1.9.box");
+    packager::Packager::libvirt(&image, 1)
+        .write_to_file(&new_box)
+        .unwrap();
+    packager::Packager::libvirt(&image, 2)
+        .write_to_file(&old_box)
+        .unwrap();
+
+    let catalog = catalog::Catalog::from_box_files(
+        "me/awesome_box",
+        "https://boxes.example.com/awesome/",
+        &[("1.10", &new_box), ("1.9", &old_box)],
+    )
+    .unwrap()
+    .with_description("awesome");
+    assert_eq!(catalog.description.as_deref(), Some("awesome"));
+    assert_eq!(
+        catalog
+            .versions
+            .iter()
+            .map(|v| v.version.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["1.9", "1.10"]
+    );
+    let provider = &catalog.versions[1].providers[0];
+    assert_eq!(provider.name, ProviderName::Libvirt);
+    assert_eq!(
+        provider.url,
+        "https://boxes.example.com/awesome/awesome%20box-1.10.box"
+    );
+    assert_eq!(provider.checksum_type.as_deref(), Some("sha256"));
+    assert_eq!(
+        provider.checksum,
+        Some(
+            checksum::checksum_file(&new_box, checksum::ChecksumType::Sha256, |_, _| ())
+                .unwrap()
+                .checksum
+        )
+    );
+    assert_eq!(
+        serde_json::from_str::<catalog::Catalog>(&catalog.to_json().unwrap()).unwrap(),
+        catalog
+    );
+
+    let mut catalog = catalog;
+    assert!(matches!(
+        catalog.add_box_file("1.9", &new_box, "https://example.com/dup.box"),
+        Err(Error::DuplicateProvider(ProviderName::Libvirt))
+    ));
+}
+
+#[test]
+fn sites_are_updated_incrementally() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("disk.qcow2");
+    std::fs::write(&image, "qcow2").unwrap();
+    let box_file = dir.path().join("awesome.box");
+    packager::Packager::libvirt(&image, 1)
+        .write_to_file(&box_file)
+        .unwrap();
+
+    let root = dir.path().join("public");
+    let site = site::Site::new(&root, "https://boxes.example.com/");
+    assert_eq!(site.read_catalog("me/awesome").unwrap(), None);
+    assert!(matches!(
+        site.read_catalog("me/../etc"),
+        Err(Error::InvalidBoxTag(_))
+    ));
+
+    let mut catalog = catalog::Catalog::new("me/awesome").with_description("<awesome>");
+    assert!(matches!(
+        site.add_box_file(&mut catalog, "..", &box_file),
+        Err(Error::InvalidPathSegment(_))
+    ));
+    let report = site.add_box_file(&mut catalog, "1.0", &box_file).unwrap();
+    let copy = root
+        .join("me")
+        .join("awesome")
+        .join("1.0")
+        .join("awesome.box");
+    assert_eq!(report.written, vec![copy.clone()]);
+    assert_eq!(
+        catalog.versions[0].providers[0].url,
+        "https://boxes.example.com/me/awesome/1.0/awesome.box"
+    );
+
+    let report = site.update(&[catalog.clone()]).unwrap();
+    assert_eq!(
+        report.written,
+        vec![
+            root.join("me").join("awesome.json"),
+            root.join("index.json"),
+            root.join("index.html"),
+        ]
+    );
+    assert_eq!(
+        site.read_catalog("me/awesome").unwrap(),
+        Some(catalog.clone())
+    );
+    let html = std::fs::read_to_string(root.join("index.html")).unwrap();
+    assert!(html.contains("&lt;awesome&gt;"));
+    assert!(html.contains("vagrant box add https://boxes.example.com/me/awesome.json"));
+
+    // nothing changed, nothing is written
+    let mut unchanged = catalog::Catalog::new("me/awesome").with_description("<awesome>");
+    let report = site.add_box_file(&mut unchanged, "1.0", &box_file).unwrap();
+    assert_eq!(report.unchanged, vec![copy.clone()]);
+    assert_eq!(unchanged, catalog);
+    let report = site.update(&[unchanged]).unwrap();
+    assert!(report.written.is_empty());
+    assert_eq!(report.unchanged.len(), 3);
+
+    // adding the file to the existing catalog again changes nothing
+    let mut existing = site.read_catalog("me/awesome").unwrap().unwrap();
+    let report = site.add_box_file(&mut existing, "1.0", &box_file).unwrap();
+    assert_eq!(report.unchanged, vec![copy.clone()]);
+    assert_eq!(existing, catalog);
+
+    // a rebuilt box file replaces the previous one
+    std::fs::write(&image, "rebuilt qcow2").unwrap();
+    packager::Packager::libvirt(&image, 1)
+        .write_to_file(&box_file)
+        .unwrap();
+    let report = site.add_box_file(&mut existing, "1.0", &box_file).unwrap();
+    assert_eq!(report.written, vec![copy.clone()]);
+    assert_eq!(
+        std::fs::read(&copy).unwrap(),
+        std::fs::read(&box_file).unwrap()
+    );
+    assert_eq!(existing.versions[0].providers.len(), 1);
+    assert_ne!(
+        existing.versions[0].providers[0].checksum,
+        catalog.versions[0].providers[0].checksum
+    );
+    assert!(!copy.with_extension("box.partial").exists());
+    let report = site.update(&[existing]).unwrap();
+    assert_eq!(report.written, vec![root.join("me").join("awesome.json")]);
+
+    // updating another box keeps the existing ones in the index
+    let mut vagrant_box: api::VagrantBox = serde_json::from_str(&box_json(
+        "other",
+        "cloud",
+        &[
+            ("1.2", "active", &[("libvirt", "a")]),
+            ("1.10", "active", &[("libvirt", "b")]),
+        ],
+    ))
+    .unwrap();
+    vagrant_box.tag = None;
+    let report = site.update_from_api(&[vagrant_box]).unwrap();
+    assert_eq!(report.written.len(), 3);
+    let index: site::SiteIndex =
+        serde_json::from_str(&std::fs::read_to_string(root.join("index.json")).unwrap()).unwrap();
+    assert_eq!(
+        index
+            .boxes
+            .iter()
+            .map(|b| (b.name.as_str(), b.latest_version.as_deref()))
+            .collect::<Vec<_>>(),
+        vec![("me/awesome", Some("1.0")), ("other/cloud", Some("1.10"))]
+    );
+}
+
+#[cfg(feature = "server")]
+/// Publish a hosted box on a registry server with `backend` and check that
+/// vagrant can consume it
+fn check_registry_server<B: server::Backend + 'static>(backend: B) {
+    let handle = server::Server::new(backend)
+        .with_token("secret", "me")
+        .with_token("other", "you")
+        .spawn("127.0.0.1:0")
+        .unwrap();
+    let client = Client::new(Some("secret"))
+        .with_base_url(handle.api_url())
+        .with_deserialization_mode(schema::DeserializationMode::Strict);
+
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("disk.qcow2");
+    std::fs::write(&image, "qcow2").unwrap();
+    let box_path = dir.path().join("hosted.box");
+    packager::Packager::libvirt(&image, 1)
+        .write_to_file(&box_path)
+        .unwrap();
+    let contents = std::fs::read(&box_path).unwrap();
+
+    let username = "me".to_string();
+    let name = "hosted".to_string();
+    let vagrant_box = VagrantBox::new(&username, &name);
+    let (ver, descr) = ("1.0.0".to_string(), "first".to_string());
+    let version = BoxVersion {
+        version: &ver,
+        description: &descr,
+    };
+    let no_url = String::new();
+    let provider = BoxProvider::new(&ProviderName::Libvirt, &no_url);
+
+    client.create_box(&vagrant_box).unwrap();
+    client.create_version(&vagrant_box, &version).unwrap();
+    assert!(matches!(
+        client.create_version(&vagrant_box, &version),
+        Err(Error::Conflict(_))
+    ));
+    client
+        .create_provider(&vagrant_box, &version, &provider)
+        .unwrap();
+    // nothing to download yet
+    assert!(matches!(
+        client.release_version(&vagrant_box, &version),
+        Err(Error::Validation { .. })
+    ));
+    client
+        .upload_provider(
+            &vagrant_box,
+            &version,
+            &ProviderName::Libvirt,
+            std::io::Cursor::new(contents.clone()),
+            Some(contents.len() as u64),
+        )
+        .unwrap();
+    client.release_version(&vagrant_box, &version).unwrap();
+
+    let read = client.read_box(&vagrant_box).unwrap();
+    assert_eq!(read.tag.as_deref(), Some("me/hosted"));
+    let current = read.current_version.unwrap();
+    assert_eq!(current.status, "active");
+    let hosted = &current.providers[0];
+    assert!(hosted.hosted);
+    assert_eq!(hosted.checksum_type.as_deref(), Some("sha256"));
+    let checksum = checksum::checksum_file(&box_path, checksum::ChecksumType::Sha256, |_, _| ())
+        .unwrap()
+        .checksum;
+    assert_eq!(hosted.checksum.as_deref(), Some(checksum.as_str()));
+
+    // what `vagrant box add me/hosted` fetches with VAGRANT_SERVER_URL
+    let catalog: catalog::Catalog = reqwest::blocking::get(format!("{}/me/hosted", handle.url()))
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(catalog.name, "me/hosted");
+    assert_eq!(catalog.versions[0].version, "1.0.0");
+    let url = &catalog.versions[0].providers[0].url;
+    assert_eq!(url, &hosted.download_url);
+    let downloaded = reqwest::blocking::get(url).unwrap().bytes().unwrap();
+    assert_eq!(downloaded.to_vec(), contents);
+    assert_eq!(client.read_box(&vagrant_box).unwrap().downloads, 1);
+
+    // only the owner may modify the box
+    assert!(matches!(
+        Client::new(None as Option<String>)
+            .with_base_url(handle.api_url())
+            .delete_box(&vagrant_box),
+        Err(Error::Unauthorized(_))
+    ));
+    assert!(matches!(
+        Client::new(Some("other"))
+            .with_base_url(handle.api_url())
+            .delete_box(&vagrant_box),
+        Err(Error::Forbidden(_))
+    ));
+
+    // checksums are updated separately from their type, but never without
+    // one
+    let update_provider = |provider: &str, payload: serde_json::Value| {
+        reqwest::blocking::Client::new()
+            .put(format!(
+                "{}/box/me/hosted/version/1.0.0/provider/{}",
+                handle.api_url(),
+                provider
+            ))
+            .bearer_auth("secret")
+            .json(&serde_json::json!({ "provider": payload }))
+            .send()
+            .unwrap()
+    };
+    let updated: api::Provider =
+        update_provider("libvirt", serde_json::json!({ "checksum": checksum }))
+            .json()
+            .unwrap();
+    assert_eq!(updated.checksum_type.as_deref(), Some("sha256"));
+    let abc = "abc".to_string();
+    let mut other = BoxProvider::new(&ProviderName::VirtualBox, &URL);
+    other.checksum = Some(&abc);
+    assert!(matches!(
+        client.create_provider(&vagrant_box, &version, &other),
+        Err(Error::Validation { .. })
+    ));
+    other.checksum = None;
+    client
+        .create_provider(&vagrant_box, &version, &other)
+        .unwrap();
+    assert_eq!(
+        update_provider("virtualbox", serde_json::json!({ "checksum": abc })).status(),
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    // modifications keep the download counter
+    client.revoke_version(&vagrant_box, &version).unwrap();
+    assert_eq!(client.read_box(&vagrant_box).unwrap().downloads, 1);
+    let catalog: catalog::Catalog =
+        reqwest::blocking::get(format!("{}/me/hosted.json", handle.url()))
+            .unwrap()
+            .json()
+            .unwrap();
+    assert!(catalog.versions.is_empty());
+
+    client.delete_box(&vagrant_box).unwrap();
+    assert!(matches!(
+        client.read_box(&vagrant_box),
+        Err(Error::NotFound(_))
+    ));
+    assert_eq!(
+        reqwest::blocking::get(url).unwrap().status(),
+        reqwest::StatusCode::NOT_FOUND
+    );
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn registry_server_with_filesystem_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    check_registry_server(server::FilesystemBackend::new(dir.path()));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn registry_server_with_sqlite_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    check_registry_server(
+        server::SqliteBackend::open(dir.path().join("boxes.db"), dir.path().join("files")).unwrap(),
+    );
+}
+
+#[test]
+fn downloads_are_verified_and_resumed() {
+    let contents = b"0123456789";
+    let expected = {
+        let mut hasher = checksum::Hasher::new(checksum::ChecksumType::Sha256);
+        hasher.update(contents);
+        hasher.checksum()
+    };
+    let client = Client::new(None as Option<String>);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("downloaded.box");
+    let partial = dir.path().join("downloaded.box.partial");
+
+    // redirects are followed and the progress is reported
+    let redirect = mockito::mock("GET", "/download/redirect.box")
+        .with_status(302)
+        .with_header(
+            "location",
+            &format!("{}/download/full.box", mockito::server_url()),
+        )
+        .create();
+    let full = mockito::mock("GET", "/download/full.box")
+        .with_body(&contents[..])
+        .expect_at_least(1)
+        .create();
+    let mut reported = vec![];
+    let download = client
+        .download(
+            &format!("{}/download/redirect.box", mockito::server_url()),
+            Some(&expected),
+            &path,
+            |done, total| reported.push((done, total)),
+        )
+        .unwrap();
+    redirect.assert();
+    assert_eq!(download.size, 10);
+    assert_eq!(download.resumed_from, 0);
+    assert_eq!(download.checksum, expected);
+    assert_eq!(reported.last(), Some(&(10, Some(10))));
+    assert_eq!(std::fs::read(&path).unwrap(), contents);
+    assert!(!partial.exists());
+
+    // interrupted downloads are resumed
+    std::fs::write(&partial, &contents[..4]).unwrap();
+    let ranged = mockito::mock("GET", "/download/ranged.box")
+        .match_header("range", "bytes=4-")
+        .with_status(206)
+        .with_header("content-range", "bytes 4-9/10")
+        .with_body(&contents[4..])
+        .create();
+    let mut provider: api::Provider = serde_json::from_value(serde_json::json!({
+        "name": "libvirt",
+        "download_url": format!("{}/download/ranged.box", mockito::server_url()),
+        "checksum_type": "sha256",
+        "checksum": expected.checksum.to_uppercase(),
+    }))
+    .unwrap();
+    let download = client
+        .download_provider(&provider, &path, |_, _| ())
+        .unwrap();
+    ranged.assert();
+    assert_eq!(download.resumed_from, 4);
+    assert_eq!(download.size, 10);
+    assert_eq!(std::fs::read(&path).unwrap(), contents);
+
+    // servers that don't support ranges send the whole file
+    std::fs::write(&partial, b"garbage").unwrap();
+    let download = client
+        .download(
+            &format!("{}/download/full.box", mockito::server_url()),
+            Some(&expected),
+            &path,
+            |_, _| (),
+        )
+        .unwrap();
+    full.assert();
+    assert_eq!(download.resumed_from, 0);
+    assert_eq!(std::fs::read(&path).unwrap(), contents);
+
+    // corrupt downloads are discarded
+    provider.download_url = format!("{}/download/full.box", mockito::server_url());
+    provider.checksum = Some("0".repeat(64));
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        client.download_provider(&provider, &path, |_, _| ()),
+        Err(Error::ChecksumMismatch { actual, .. }) if actual == expected
+    ));
+    assert!(!path.exists());
+    assert!(!partial.exists());
+
+    provider.checksum_type = Some("crc32".to_string());
+    assert!(matches!(
+        client.download_provider(&provider, &path, |_, _| ()),
+        Err(Error::UnsupportedChecksumType(_))
+    ));
+    provider.checksum_type = None;
+    assert!(matches!(
+        client.download_provider(&provider, &path, |_, _| ()),
+        Err(Error::MissingChecksumType(name)) if name == ProviderName::Libvirt
+    ));
+}
+
+#[test]
+fn downloads_are_only_resumed_from_the_same_file() {
+    let contents = b"0123456789";
+    let client = Client::new(None as Option<String>);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("downloaded.box");
+    let partial = dir.path().join("downloaded.box.partial");
+    let validator = dir.path().join("downloaded.box.partial.validator");
+
+    // the validator of an interrupted download is kept
+    let truncated = mockito::mock("GET", "/download/truncated.box")
+        .with_header("etag", "\"v1\"")
+        .with_header("content-length", "10")
+        .with_body(&contents[..4])
+        .create();
+    let url = format!("{}/download/truncated.box", mockito::server_url());
+    assert!(matches!(
+        client.download(&url, None, &path, |_, _| ()),
+        Err(Error::IncompleteDownload(_))
+    ));
+    truncated.assert();
+    assert_eq!(std::fs::read(&partial).unwrap(), &contents[..4]);
+    assert_eq!(std::fs::read_to_string(&validator).unwrap(), "\"v1\"");
+
+    // and sent along with the range
+    let ranged = mockito::mock("GET", "/download/truncated.box")
+        .match_header("range", "bytes=4-")
+        .match_header("if-range", "\"v1\"")
+        .with_status(206)
+        .with_header("content-range", "bytes 4-9/10")
+        .with_body(&contents[4..])
+        .create();
+    let download = client.download(&url, None, &path, |_, _| ()).unwrap();
+    ranged.assert();
+    assert_eq!(download.resumed_from, 4);
+    assert_eq!(std::fs::read(&path).unwrap(), contents);
+    assert!(!partial.exists());
+    assert!(!validator.exists());
+
+    // without a validator or a checksum the download starts over
+    std::fs::write(&partial, b"garbage").unwrap();
+    let full = mockito::mock("GET", "/download/full.box")
+        .match_header("range", mockito::Matcher::Missing)
+        .with_body(&contents[..])
+        .create();
+    let download = client
+        .download(
+            &format!("{}/download/full.box", mockito::server_url()),
+            None,
+            &path,
+            |_, _| (),
+        )
+        .unwrap();
+    full.assert();
+    assert_eq!(download.resumed_from, 0);
+    assert_eq!(std::fs::read(&path).unwrap(), contents);
+}
+
+#[test]
+fn box_store_installs_lists_and_removes_boxes() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("disk.qcow2");
+    std::fs::write(&image, "qcow2").unwrap();
+    let plain_box = dir.path().join("plain.box");
+    let amd64_box = dir.path().join("amd64.box");
+    packager::Packager::libvirt(&image, 1)
+        .write_to_file(&plain_box)
+        .unwrap();
+    packager::Packager::new(boxfile::Metadata {
+        provider: ProviderName::Libvirt,
+        format: Some("qcow2".to_string()),
+        virtual_size: Some(1),
+        architecture: Some("amd64".to_string()),
+        extra: Default::default(),
+    })
+    .with_file_as("box.img", &image)
+    .write_to_file(&amd64_box)
+    .unwrap();
+
+    let root = dir.path().join("boxes");
+    let store = store::Store::new(&root);
+    assert_eq!(store.installed().unwrap(), vec![]);
+
+    let url = "https://boxes.example.com/me/box.json";
+    let plain = store
+        .install("me/box", "1.0", &plain_box, Some(url))
+        .unwrap();
+    assert_eq!(
+        plain.path,
+        root.join("me-VAGRANTSLASH-box").join("1.0").join("libvirt")
+    );
+    assert_eq!(
+        std::fs::read(plain.path.join("box.img")).unwrap(),
+        b"qcow2".to_vec()
+    );
+    let amd64 = store.install("me/box", "1.0", &amd64_box, None).unwrap();
+    assert_eq!(amd64.architecture.as_deref(), Some("amd64"));
+    let newer = store.install("me/box", "1.1", &amd64_box, None).unwrap();
+    assert!(matches!(
+        store.install("me/box", "1.0", &plain_box, None),
+        Err(Error::BoxAlreadyInstalled(_))
+    ));
+    // failed installations leave nothing behind
+    let broken_box = dir.path().join("broken.box");
+    std::fs::write(&broken_box, "not a box").unwrap();
+    assert!(store.install("me/box", "2.0", &broken_box, None).is_err());
+    assert!(!root.join("me-VAGRANTSLASH-box").join("2.0").exists());
+    assert!(store
+        .install("other/box", "1.0", &broken_box, None)
+        .is_err());
+    let mut entries = std::fs::read_dir(&root)
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    entries.sort();
+    assert_eq!(entries, vec!["me-VAGRANTSLASH-box"]);
+
+    // hidden directories, e.g. of interrupted installations, are skipped
+    for hidden in &[
+        root.join(".tmp-vagabond-1-0").join("1.0").join("libvirt"),
+        root.join("me-VAGRANTSLASH-box")
+            .join(".partial")
+            .join("libvirt"),
+    ] {
+        std::fs::create_dir_all(hidden).unwrap();
+        std::fs::copy(
+            plain.path.join("metadata.json"),
+            hidden.join("metadata.json"),
+        )
+        .unwrap();
+    }
+
+    let installed = store.installed().unwrap();
+    assert_eq!(
+        installed
+            .iter()
+            .map(|b| (
+                b.name.as_str(),
+                b.version.as_str(),
+                b.architecture.as_deref(),
+                b.metadata_url.as_deref()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("me/box", "1.0", None, Some(url)),
+            ("me/box", "1.0", Some("amd64"), Some(url)),
+            ("me/box", "1.1", Some("amd64"), Some(url)),
+        ]
+    );
+
+    // only the box without an architecture has a newer release
+    let vagrant_box: api::VagrantBox = serde_json::from_str(&box_json(
+        "me",
+        "box",
+        &[
+            ("1.2", "active", &[("libvirt", "a")]),
+            ("1.3", "unreleased", &[("libvirt", "b")]),
+        ],
+    ))
+    .unwrap();
+    let outdated = store.outdated(&[vagrant_box]).unwrap();
+    assert_eq!(outdated.len(), 1);
+    assert_eq!(outdated[0].installed.path, plain.path);
+    assert_eq!(outdated[0].latest, "1.2");
+
+    let removed = store.remove_version("me/box", "1.0").unwrap();
+    assert_eq!(removed.len(), 2);
+    assert_eq!(store.installed().unwrap().len(), 1);
+    assert_eq!(
+        store.remove_version("me/box", "1.1").unwrap()[0].path,
+        newer.path
+    );
+    assert!(!root.join("me-VAGRANTSLASH-box").exists());
+    assert_eq!(store.installed().unwrap(), vec![]);
+
+    assert!(store.install("..", "1.0", &plain_box, None).is_err());
+    assert!(store.install("me/box", "..", &plain_box, None).is_err());
 }
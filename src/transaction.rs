@@ -0,0 +1,249 @@
+//! # Transaction module
+//!
+//! Applying a [`Plan`](../plan/struct.Plan.html) consists of multiple API
+//! calls, any of which can fail. By default, the operations performed before
+//! the failure are kept, which can leave a box half-modified (e.g. a new
+//! version without providers).
+//!
+//! With [`Client::with_rollback`](../struct.Client.html#method.with_rollback)
+//! every performed operation is journaled together with its compensation.
+//! If an operation fails, the compensations of all previously performed
+//! operations are executed in reverse order and an `Error::RolledBack` is
+//! returned, that carries the original error and a
+//! [`RollbackReport`](struct.RollbackReport.html):
+//!
+//! ```no_run
+//! # use vagabond::*;
+//! # let username = "my_vagrant_cloud_user_name".to_string();
+//! # let box_name = "awesome_box".to_string();
+//! # let ver = "1.2.3".to_string();
+//! # let descr = "Release from today!".to_string();
+//! # let url = "https://foo.bar.baz/path/to/my/awesome.box".to_string();
+//! let client = Client::new(Some("my_api_key_here".to_string())).with_rollback(true);
+//! let vagrant_box = VagrantBox::new(&username, &box_name);
+//! let box_version = BoxVersion { version: &ver, description: &descr };
+//! let provider = BoxProvider::new(&ProviderName::Libvirt, &url);
+//!
+//! match client.ensure_provider_present(&vagrant_box, &box_version, &provider, true) {
+//!     Err(Error::RolledBack { error, rollback }) => {
+//!         println!("{} failed: {}", rollback.failed_operation, error);
+//!         println!("{}", rollback);
+//!     }
+//!     res => println!("{:?}", res),
+//! }
+//! ```
+//!
+//! Compensations are best effort: deleted providers are recreated from their
+//! original URL (which is not possible for providers hosted on Vagrant Cloud)
+//! and a revoked version keeps the status `revoked`.
+
+use std::fmt;
+
+use super::plan::{BoxSpec, Operation, Plan, ProviderSpec, VersionSpec};
+use super::{api, Client, Error, Result};
+
+#[derive(Debug)]
+/// The outcome of rolling back a partially applied plan
+pub struct RollbackReport {
+    /// The operation whose failure triggered the rollback
+    pub failed_operation: Operation,
+    /// The compensating operations that were performed successfully, in the
+    /// order in which they were performed
+    pub compensated: Vec<Operation>,
+    /// The compensating operations that failed together with their errors
+    pub failures: Vec<(Operation, Error)>,
+}
+
+impl RollbackReport {
+    /// Returns true if all performed operations were compensated
+    /// successfully, i.e. the box is back in its original state.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for RollbackReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_complete() {
+            write!(
+                f,
+                "rolled back successfully ({} compensating operations)",
+                self.compensated.len()
+            )
+        } else {
+            write!(
+                f,
+                "rollback incomplete, {} of {} compensating operations failed: {}",
+                self.failures.len(),
+                self.failures.len() + self.compensated.len(),
+                self.failures
+                    .iter()
+                    .map(|(op, e)| format!("{}: {}", op, e))
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            )
+        }
+    }
+}
+
+/// The operations that recreate the deleted version `snapshot`
+fn restore_version(snapshot: &api::Version) -> Vec<Operation> {
+    let version = VersionSpec::from(snapshot);
+    let mut operations = vec![Operation::CreateVersion(version.clone())];
+    operations.extend(
+        snapshot
+            .providers
+            .iter()
+            .map(|prov| Operation::CreateProvider {
+                version: version.clone(),
+                provider: ProviderSpec::from(prov),
+            }),
+    );
+    if snapshot.status == "active" {
+        operations.push(Operation::ReleaseVersion(version));
+    }
+    operations
+}
+
+impl Client {
+    /// Compute the operations that undo `operation`, which is about to be
+    /// performed on `vagrant_box`.
+    ///
+    /// This reads the state of versions that are going to be deleted,
+    /// released or revoked, so it has to be called **before** the operation
+    /// is performed.
+    fn compensation(&self, vagrant_box: &BoxSpec, operation: &Operation) -> Result<Plan> {
+        let in_box = |operations| Plan {
+            vagrant_box: vagrant_box.clone(),
+            operations,
+        };
+
+        Ok(match operation {
+            Operation::CreateBox => in_box(vec![Operation::DeleteBox]),
+            Operation::DeleteBox => {
+                let snapshot = self.read_box(&vagrant_box.as_vagrant_box())?;
+                let mut operations = vec![Operation::CreateBox];
+                snapshot
+                    .versions
+                    .iter()
+                    .for_each(|ver| operations.extend(restore_version(ver)));
+                Plan {
+                    vagrant_box: BoxSpec::from(&snapshot),
+                    operations,
+                }
+            }
+            Operation::UpdateBox { current } => Plan {
+                vagrant_box: current.clone(),
+                operations: vec![Operation::UpdateBox {
+                    current: vagrant_box.clone(),
+                }],
+            },
+            Operation::CreateVersion(ver) => in_box(vec![Operation::DeleteVersion(ver.clone())]),
+            Operation::UpdateVersion { version, current } => {
+                in_box(vec![Operation::UpdateVersion {
+                    version: current.clone(),
+                    current: version.clone(),
+                }])
+            }
+            Operation::DeleteProvider { version, provider } => {
+                in_box(vec![Operation::CreateProvider {
+                    version: version.clone(),
+                    provider: provider.clone(),
+                }])
+            }
+            Operation::DeleteVersion(ver) => {
+                let snapshot =
+                    self.read_version(&vagrant_box.as_vagrant_box(), &ver.as_box_version())?;
+                in_box(restore_version(&snapshot))
+            }
+            Operation::CreateProvider { version, provider } => {
+                in_box(vec![Operation::DeleteProvider {
+                    version: version.clone(),
+                    provider: provider.clone(),
+                }])
+            }
+            Operation::UpdateProvider {
+                version,
+                provider,
+                current,
+            } => in_box(vec![Operation::UpdateProvider {
+                version: version.clone(),
+                provider: current.clone(),
+                current: provider.clone(),
+            }]),
+            Operation::ReleaseVersion(ver) | Operation::RevokeVersion(ver) => {
+                // releasing an active or revoking an inactive version changes
+                // nothing, so undoing it must not change anything either
+                let was_active = self
+                    .read_version(&vagrant_box.as_vagrant_box(), &ver.as_box_version())?
+                    .status
+                    == "active";
+                in_box(match operation {
+                    Operation::ReleaseVersion(_) if !was_active => {
+                        vec![Operation::RevokeVersion(ver.clone())]
+                    }
+                    Operation::RevokeVersion(_) if was_active => {
+                        vec![Operation::ReleaseVersion(ver.clone())]
+                    }
+                    _ => vec![],
+                })
+            }
+        })
+    }
+
+    /// Executes the operations of `plan` in their order like
+    /// [`apply`](#method.apply), but rolls back all performed operations if
+    /// one of them fails.
+    ///
+    /// On failure an `Error::RolledBack` is returned with the error of the
+    /// failed operation and the outcome of the rollback.
+    pub fn apply_transactional(&self, plan: &Plan) -> Result<api::VagrantBox> {
+        let vagrant_box = plan.vagrant_box.as_vagrant_box();
+        // compensations of the operations performed so far, each stored as a
+        // plan, as compensating an UpdateBox requires the previous settings
+        // of the box
+        let mut journal: Vec<Plan> = vec![];
+
+        for op in &plan.operations {
+            let res = self.compensation(&plan.vagrant_box, op).and_then(|undo| {
+                self.apply_operation(&vagrant_box, op)?;
+                Ok(undo)
+            });
+            match res {
+                Ok(undo) => journal.push(undo),
+                Err(error) => {
+                    let rollback = self.roll_back(journal, op.clone());
+                    return Err(Error::RolledBack {
+                        error: Box::new(error),
+                        rollback: Box::new(rollback),
+                    });
+                }
+            }
+        }
+        self.read_box(&vagrant_box)
+    }
+
+    /// Execute the compensations in `journal` in reverse order
+    fn roll_back(&self, journal: Vec<Plan>, failed_operation: Operation) -> RollbackReport {
+        warn!("Operation '{}' failed, rolling back", failed_operation);
+        let mut report = RollbackReport {
+            failed_operation,
+            compensated: vec![],
+            failures: vec![],
+        };
+
+        for undo in journal.iter().rev() {
+            let vagrant_box = undo.vagrant_box.as_vagrant_box();
+            for op in &undo.operations {
+                match self.apply_operation(&vagrant_box, op) {
+                    Ok(()) => report.compensated.push(op.clone()),
+                    Err(e) => {
+                        error!("Failed to roll back via '{}': {}", op, e);
+                        report.failures.push((op.clone(), e));
+                    }
+                }
+            }
+        }
+        report
+    }
+}
@@ -53,7 +53,7 @@ impl TestFixture {
         fixture
     }
 
-    fn get_vagrant_box(&self) -> vagabond::VagrantBox {
+    fn get_vagrant_box(&self) -> vagabond::VagrantBox<'_, '_, '_, '_> {
         vagabond::VagrantBox::new(&self.user, &self.box_name)
     }
 
@@ -86,13 +86,13 @@ impl VersionFixture {
         let test_fixture = TestFixture::new(box_name);
         test_fixture.box_create().unwrap();
         VersionFixture {
-            test_fixture: test_fixture,
+            test_fixture,
             version: version.map_or("1.2.3".to_string(), |v| v.to_string()),
             description: description.map_or("This is a test Box".to_string(), |d| d.to_string()),
         }
     }
 
-    fn get_vagrant_version(&self) -> vagabond::BoxVersion {
+    fn get_vagrant_version(&self) -> vagabond::BoxVersion<'_, '_> {
         vagabond::BoxVersion {
             version: &self.version,
             description: &self.description,
@@ -132,8 +132,8 @@ fn version_creation_should_succeed() {
     let version_result = ver_create_res.unwrap();
 
     assert_eq!(version_result.version, version);
-    if version_result.description_markdown.is_some() {
-        assert_eq!(version_result.description_markdown.unwrap(), description);
+    if let Some(descr) = version_result.description_markdown {
+        assert_eq!(descr, description);
     }
 }
 
@@ -151,7 +151,9 @@ fn version_deletion_should_work() {
 
     assert!(delete_res.is_ok());
 
-    assert_eq!(delete_res.unwrap().version, fixture.version);
+    if let Some(deleted_version) = delete_res.unwrap() {
+        assert_eq!(deleted_version.version, fixture.version);
+    }
 }
 
 lazy_static! {
@@ -161,8 +163,8 @@ lazy_static! {
     static ref VERSION4: String = "29".to_string();
     static ref BOX_NAME: String = "fresh_box".to_string();
     static ref VER_DESCR: String = "version 15!!".to_string();
-    static ref LIBVIRT: String = "libvirt".to_string();
-    static ref VIRTUALBOX: String = "virtualbox".to_string();
+    static ref LIBVIRT: vagabond::ProviderName = vagabond::ProviderName::Libvirt;
+    static ref VIRTUALBOX: vagabond::ProviderName = vagabond::ProviderName::VirtualBox;
     static ref URL: String = "https://foo.bar.baz/my/box/15.16.17/img.box".to_string();
     static ref URL2: String = "https://foo.bar.baz/my/box/31.29.1/img.box".to_string();
     static ref URL3: String = "https://foo.bar.baz/my/box/28.1/img.box".to_string();
@@ -184,30 +186,15 @@ lazy_static! {
         description: &VER_DESCR,
     };
     static ref LIBVIRT_PROVIDER_1: vagabond::BoxProvider<'static, 'static> =
-        vagabond::BoxProvider {
-            name: &LIBVIRT,
-            url: &URL,
-        };
+        vagabond::BoxProvider::new(&LIBVIRT, &URL);
     static ref LIBVIRT_PROVIDER_2: vagabond::BoxProvider<'static, 'static> =
-        vagabond::BoxProvider {
-            name: &LIBVIRT,
-            url: &URL2,
-        };
+        vagabond::BoxProvider::new(&LIBVIRT, &URL2);
     static ref LIBVIRT_PROVIDER_3: vagabond::BoxProvider<'static, 'static> =
-        vagabond::BoxProvider {
-            name: &LIBVIRT,
-            url: &URL3,
-        };
+        vagabond::BoxProvider::new(&LIBVIRT, &URL3);
     static ref LIBVIRT_PROVIDER_4: vagabond::BoxProvider<'static, 'static> =
-        vagabond::BoxProvider {
-            name: &LIBVIRT,
-            url: &URL4,
-        };
+        vagabond::BoxProvider::new(&LIBVIRT, &URL4);
     static ref VIRTUALBOX_PROVIDER_1: vagabond::BoxProvider<'static, 'static> =
-        vagabond::BoxProvider {
-            name: &VIRTUALBOX,
-            url: &URL,
-        };
+        vagabond::BoxProvider::new(&VIRTUALBOX, &URL);
 }
 
 // fn assert_all_equal(api_response: &vagabond::api::VagrantBox) -> () {}
@@ -299,10 +286,7 @@ fn check_provider_updated_by_ensure_provider_present() {
     assert_eq!(old_provider, *LIBVIRT_PROVIDER_1);
 
     let url = "https://this.url.doesn/t/exist.box".to_string();
-    let provider_with_new_url = vagabond::BoxProvider {
-        name: LIBVIRT_PROVIDER_1.name,
-        url: &url,
-    };
+    let provider_with_new_url = vagabond::BoxProvider::new(LIBVIRT_PROVIDER_1.name, &url);
 
     let updated_box = fixture.client.ensure_provider_present(
         &fixture.get_vagrant_box(),
@@ -356,8 +340,8 @@ fn test_add_second_provider() {
     let prov = &box_res.versions[0].providers;
 
     assert_eq!(box_res.versions[0].providers.len(), 2);
-    assert!(prov.into_iter().any(|prov| prov == *LIBVIRT_PROVIDER_1));
-    assert!(prov.into_iter().any(|prov| prov == *VIRTUALBOX_PROVIDER_1));
+    assert!(prov.iter().any(|prov| prov == *LIBVIRT_PROVIDER_1));
+    assert!(prov.iter().any(|prov| prov == *VIRTUALBOX_PROVIDER_1));
 }
 
 #[test]
@@ -533,7 +517,9 @@ fn check_adding_a_provider_to_existing_empty_version_works() {
         .delete_version(&fixture.get_vagrant_box(), &BOX_VERSION_1)
         .unwrap();
 
-    assert_eq!(&deleted_version.version, BOX_VERSION_1.version);
+    if let Some(deleted_version) = deleted_version {
+        assert_eq!(&deleted_version.version, BOX_VERSION_1.version);
+    }
 
     // now create a new provider for the same version
 